@@ -0,0 +1,379 @@
+use super::hosts::SinkholeAddresses;
+use super::rules::RuleMatcher;
+use crate::db::{self, DbError};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const HTTP_PORT: u16 = 80;
+const MAX_REQUEST_BYTES: usize = 8192;
+/// Requests are answered one at a time on the listening thread, so a client
+/// gets this long to send its headers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedAttempt {
+    pub domain: String,
+    pub count: i64,
+    pub last_attempt: DateTime<Utc>,
+}
+
+/// Serves a "blocked by FocusLock" page on the sinkhole address so plain
+/// HTTP visits to a blocked site explain themselves instead of looking like
+/// a network failure. HTTPS visits still fail at the TLS handshake.
+///
+/// Only visits to blocked names count as attempts; anything else reaching
+/// the address, e.g. the address typed in directly, just gets the page.
+pub struct BlockPageServer {
+    running: Arc<AtomicBool>,
+    /// When the block lifts, on the monotonic clock
+    unlocks_at: Arc<RwLock<Option<Instant>>>,
+    matcher: Arc<RwLock<RuleMatcher>>,
+    db: Arc<Mutex<Connection>>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl BlockPageServer {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            running: Arc::new(AtomicBool::new(false)),
+            unlocks_at: Arc::new(RwLock::new(None)),
+            matcher: Arc::new(RwLock::new(RuleMatcher::default())),
+            db: Arc::new(Mutex::new(conn)),
+            threads: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// `now` is the trusted time (`TimeLock::now`) `unlock_time` is measured
+    /// against. The page counts down from there by the monotonic clock, so
+    /// changing the system clock doesn't change it.
+    pub fn set_unlock_time(&self, unlock_time: DateTime<Utc>, now: DateTime<Utc>) {
+        let remaining = (unlock_time - now).to_std().unwrap_or_default();
+        *self.unlocks_at.write().unwrap() = Some(Instant::now() + remaining);
+    }
+
+    pub fn clear_unlock_time(&self) {
+        *self.unlocks_at.write().unwrap() = None;
+    }
+
+    pub fn set_rules(&self, domains: &[String]) {
+        *self.matcher.write().unwrap() = RuleMatcher::new(domains);
+    }
+
+    pub fn start(&self, sinkhole: SinkholeAddresses) -> io::Result<()> {
+        if self.running.load(Ordering::Relaxed) {
+            return Ok(()); // Already running
+        }
+
+        // 0.0.0.0 in the hosts file still connects to loopback, and binding
+        // every interface would expose the page to the network
+        let ipv4 = if sinkhole.ipv4.is_unspecified() {
+            Ipv4Addr::LOCALHOST
+        } else {
+            sinkhole.ipv4
+        };
+        let ipv6 = if sinkhole.ipv6.is_unspecified() {
+            Ipv6Addr::LOCALHOST
+        } else {
+            sinkhole.ipv6
+        };
+
+        let mut listeners = vec![TcpListener::bind(SocketAddr::new(
+            IpAddr::V4(ipv4),
+            HTTP_PORT,
+        ))?];
+        match TcpListener::bind(SocketAddr::new(IpAddr::V6(ipv6), HTTP_PORT)) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => log::warn!("Block page not available over IPv6: {}", e),
+        }
+
+        for listener in &listeners {
+            listener.set_nonblocking(true)?;
+        }
+
+        // Only now that every listener is ready, so a failure above leaves
+        // the server stopped
+        self.running.store(true, Ordering::Relaxed);
+        let mut threads = self.threads.lock().unwrap();
+        for listener in listeners {
+            let running = self.running.clone();
+            let unlocks_at = self.unlocks_at.clone();
+            let matcher = self.matcher.clone();
+            let db = self.db.clone();
+
+            threads.push(thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let unlocks_at = *unlocks_at.read().unwrap();
+                            handle_connection(stream, unlocks_at, &matcher, &db);
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(100));
+                        }
+                        Err(e) => log::warn!("Block page accept failed: {}", e),
+                    }
+                }
+            }));
+        }
+
+        log::info!("Block page server started on {}", ipv4);
+        Ok(())
+    }
+
+    /// Stops serving and waits for the listeners, so the port is free again
+    /// once this returns.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        for handle in self.threads.lock().unwrap().drain(..) {
+            if handle.join().is_err() {
+                log::error!("Block page thread panicked");
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn attempts(&self) -> SqliteResult<Vec<BlockedAttempt>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT domain, count, last_attempt FROM blocked_attempts ORDER BY count DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let last_attempt: String = row.get(2)?;
+            Ok(BlockedAttempt {
+                domain: row.get(0)?,
+                count: row.get(1)?,
+                last_attempt: DateTime::parse_from_rfc3339(&last_attempt)
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_default(),
+            })
+        })?;
+
+        rows.collect()
+    }
+}
+
+impl Drop for BlockPageServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    unlocks_at: Option<Instant>,
+    matcher: &RwLock<RuleMatcher>,
+    db: &Mutex<Connection>,
+) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+
+    // Read until the end of the headers; the body is irrelevant
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while request.len() < MAX_REQUEST_BYTES {
+        // A client sending a byte at a time still only gets the one timeout
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || stream.set_read_timeout(Some(left)).is_err() {
+            break;
+        }
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+        if request.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let host = parse_host(&request).unwrap_or_else(|| "unknown".to_string());
+
+    if matcher.read().unwrap().is_blocked(&host) {
+        if let Err(e) = record_attempt(db, &host) {
+            log::error!("Failed to record blocked attempt: {}", e);
+        }
+        log::info!("Served block page for {}", host);
+    }
+
+    let remaining = unlocks_at.map(|at| at.saturating_duration_since(Instant::now()));
+    let body = render_page(&host, remaining);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+/// Extracts the Host header without its port, lowercased.
+fn parse_host(request: &str) -> Option<String> {
+    request.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("host") {
+            return None;
+        }
+        let value = value.trim();
+        // Keep IPv6 literals like [::1]:80 intact apart from the port
+        let host = match value.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => value,
+        };
+        Some(host.to_ascii_lowercase())
+    })
+}
+
+fn record_attempt(db: &Mutex<Connection>, host: &str) -> SqliteResult<()> {
+    let db = db.lock().unwrap();
+    db.execute(
+        "INSERT INTO blocked_attempts (domain, count, last_attempt) VALUES (?1, 1, ?2)
+         ON CONFLICT(domain) DO UPDATE SET count = count + 1, last_attempt = excluded.last_attempt",
+        params![host, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+fn render_page(host: &str, remaining: Option<Duration>) -> String {
+    let remaining = match remaining {
+        Some(remaining) if remaining.as_secs() > 0 => {
+            let secs = remaining.as_secs();
+            format!(
+                "남은 시간 {:02}:{:02}:{:02}",
+                secs / 3600,
+                (secs % 3600) / 60,
+                secs % 60
+            )
+        }
+        _ => "잠금이 곧 해제됩니다".to_string(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="ko">
+<head>
+<meta charset="utf-8">
+<title>FocusLock</title>
+<style>
+body {{ font-family: system-ui, sans-serif; background: #111827; color: #f9fafb;
+       display: flex; align-items: center; justify-content: center; height: 100vh; margin: 0; }}
+main {{ text-align: center; }}
+h1 {{ font-size: 2rem; margin-bottom: 0.5rem; }}
+p {{ color: #9ca3af; }}
+</style>
+</head>
+<body>
+<main>
+<h1>🔒 {host}</h1>
+<p>FocusLock이 차단한 사이트입니다. 인터넷 연결은 정상입니다.</p>
+<p>{remaining}</p>
+</main>
+</body>
+</html>
+"#,
+        host = escape_html(host),
+        remaining = remaining
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> BlockPageServer {
+        BlockPageServer::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    /// Sends `request` through `handle_connection` and returns the response.
+    fn serve(server: &BlockPageServer, request: &str, unlocks_at: Option<Instant>) -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(stream, unlocks_at, &server.matcher, &server.db);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn blocked_hosts_get_the_page_and_count() {
+        let server = server();
+        server.set_rules(&["*.example.com".to_string()]);
+
+        let response = serve(
+            &server,
+            "GET / HTTP/1.1\r\nHost: WWW.Example.com:80\r\n\r\n",
+            None,
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("🔒 www.example.com"));
+        serve(
+            &server,
+            "GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n",
+            None,
+        );
+
+        // Anything else reaching the address gets the page but isn't counted
+        let response = serve(&server, "GET / HTTP/1.1\r\nHost: <b>\r\n\r\n", None);
+        assert!(response.contains("🔒 &lt;b&gt;"));
+
+        let attempts = server.attempts().unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].domain, "www.example.com");
+        assert_eq!(attempts[0].count, 2);
+    }
+
+    #[test]
+    fn page_shows_the_time_left() {
+        let server = server();
+        let now = Utc::now();
+        server.set_unlock_time(now + chrono::Duration::seconds(3661), now);
+
+        let unlocks_at = *server.unlocks_at.read().unwrap();
+        let response = serve(&server, "GET / HTTP/1.1\r\n\r\n", unlocks_at);
+        // A second may pass while serving
+        assert!(response.contains("남은 시간 01:01:01") || response.contains("남은 시간 01:01:00"));
+
+        assert!(render_page("a", Some(Duration::ZERO)).contains("잠금이 곧 해제됩니다"));
+        assert!(render_page("a", None).contains("잠금이 곧 해제됩니다"));
+        // Counted from the `now` passed in, not from the system clock
+        server.set_unlock_time(
+            now + chrono::Duration::hours(1),
+            now - chrono::Duration::days(1),
+        );
+        let unlocks_at = server.unlocks_at.read().unwrap().unwrap();
+        assert!(unlocks_at.saturating_duration_since(Instant::now()) > Duration::from_secs(86000));
+    }
+}
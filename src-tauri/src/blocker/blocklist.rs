@@ -0,0 +1,234 @@
+use crate::db::{self, DbError};
+use rusqlite::{params, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BlocklistError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Invalid domain: {0}")]
+    InvalidDomain(String),
+    #[error("Domains cannot be removed or moved while a lock is active")]
+    Locked,
+    #[error("Domain is not in the blocklist: {0}")]
+    NotFound(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedDomain {
+    pub domain: String,
+    pub group: String,
+}
+
+pub struct Blocklist {
+    db: Mutex<Connection>,
+}
+
+impl Blocklist {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        let conn = db::open(&app_data_dir)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    pub fn entries(&self) -> SqliteResult<Vec<BlockedDomain>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT domain, group_name FROM blocked_domains ORDER BY group_name, domain",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(BlockedDomain {
+                domain: row.get(0)?,
+                group: row.get(1)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn domains(&self) -> SqliteResult<Vec<String>> {
+        Ok(self.entries()?.into_iter().map(|e| e.domain).collect())
+    }
+
+    pub fn groups(&self) -> SqliteResult<Vec<String>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt =
+            db.prepare("SELECT DISTINCT group_name FROM blocked_domains ORDER BY group_name")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+
+        rows.collect()
+    }
+
+    pub fn add_domain(&self, domain: &str, group: &str) -> Result<BlockedDomain, BlocklistError> {
+        let domain = normalize_domain(domain)?;
+        let group = group_or_default(group);
+
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "INSERT INTO blocked_domains (domain, group_name) VALUES (?1, ?2)
+             ON CONFLICT(domain) DO UPDATE SET group_name = excluded.group_name",
+            params![domain, group],
+        )?;
+
+        log::info!("Added {} to blocklist group {}", domain, group);
+        Ok(BlockedDomain {
+            domain,
+            group: group.to_string(),
+        })
+    }
+
+    /// Bulk insert for imported lists. Domains that are already listed keep
+    /// their current group. Returns how many were newly added.
+    pub fn add_domains(&self, domains: &[String], group: &str) -> SqliteResult<usize> {
+        let group = group_or_default(group);
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        let mut added = 0;
+        for domain in domains {
+            added += tx.execute(
+                "INSERT OR IGNORE INTO blocked_domains (domain, group_name) VALUES (?1, ?2)",
+                params![domain, group],
+            )?;
+        }
+        tx.commit()?;
+
+        log::info!("Imported {} domains into blocklist group {}", added, group);
+        Ok(added)
+    }
+
+    /// Refused while locked: a pomodoro break blocks one group only, so
+    /// moving a domain out of it would unblock it.
+    pub fn set_group(&self, domain: &str, group: &str, locked: bool) -> Result<(), BlocklistError> {
+        if locked {
+            return Err(BlocklistError::Locked);
+        }
+
+        let domain = normalize_domain(domain)?;
+        let group = group_or_default(group);
+        let db = self.db.lock().unwrap();
+        let updated = db.execute(
+            "UPDATE blocked_domains SET group_name = ?1 WHERE domain = ?2",
+            params![group, domain],
+        )?;
+        if updated == 0 {
+            return Err(BlocklistError::NotFound(domain));
+        }
+
+        log::info!("Moved {} to blocklist group {}", domain, group);
+        Ok(())
+    }
+
+    pub fn remove_domain(&self, domain: &str, locked: bool) -> Result<(), BlocklistError> {
+        if locked {
+            return Err(BlocklistError::Locked);
+        }
+
+        let domain = normalize_domain(domain)?;
+        let db = self.db.lock().unwrap();
+        db.execute("DELETE FROM blocked_domains WHERE domain = ?1", [&domain])?;
+
+        log::info!("Removed {} from blocklist", domain);
+        Ok(())
+    }
+
+    pub fn remove_group(&self, group: &str, locked: bool) -> Result<(), BlocklistError> {
+        if locked {
+            return Err(BlocklistError::Locked);
+        }
+
+        let db = self.db.lock().unwrap();
+        let removed = db.execute("DELETE FROM blocked_domains WHERE group_name = ?1", [group])?;
+
+        log::info!(
+            "Removed group {} ({} domains) from blocklist",
+            group,
+            removed
+        );
+        Ok(())
+    }
+}
+
+fn group_or_default(group: &str) -> &str {
+    let group = group.trim();
+    if group.is_empty() {
+        "custom"
+    } else {
+        group
+    }
+}
+
+/// Lowercases and validates a domain rule, either an exact name or a
+/// `*.example.com` suffix rule.
+pub fn normalize_domain(domain: &str) -> Result<String, BlocklistError> {
+    let normalized = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    let name = normalized.strip_prefix("*.").unwrap_or(&normalized);
+
+    let valid = !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+
+    if valid {
+        Ok(normalized)
+    } else {
+        Err(BlocklistError::InvalidDomain(domain.to_string()))
+    }
+}
+
+/// Built-in list that seeds a fresh database, as (group, domain) pairs.
+pub fn default_blocked_domains() -> Vec<(&'static str, &'static str)> {
+    vec![
+        // YouTube
+        ("YouTube", "www.youtube.com"),
+        ("YouTube", "youtube.com"),
+        ("YouTube", "m.youtube.com"),
+        ("YouTube", "youtu.be"),
+        ("YouTube", "www.youtube-nocookie.com"),
+        ("YouTube", "youtube-nocookie.com"),
+        ("YouTube", "youtubei.googleapis.com"),
+        ("YouTube", "yt3.ggpht.com"),
+        ("YouTube", "music.youtube.com"),
+        ("YouTube", "studio.youtube.com"),
+        ("YouTube", "*.youtube.com"),
+        ("YouTube", "*.googlevideo.com"),
+        // Chzzk
+        ("Chzzk", "chzzk.naver.com"),
+        ("Chzzk", "api.chzzk.naver.com"),
+        ("Chzzk", "live.chzzk.naver.com"),
+        ("Chzzk", "m.chzzk.naver.com"),
+        // Instagram
+        ("Instagram", "www.instagram.com"),
+        ("Instagram", "instagram.com"),
+        ("Instagram", "i.instagram.com"),
+        ("Instagram", "graph.instagram.com"),
+        ("Instagram", "api.instagram.com"),
+        ("Instagram", "l.instagram.com"),
+        ("Instagram", "static.cdninstagram.com"),
+        ("Instagram", "scontent.cdninstagram.com"),
+        ("Instagram", "*.cdninstagram.com"),
+        // League of Legends related
+        ("League of Legends", "www.leagueoflegends.com"),
+        ("League of Legends", "leagueoflegends.com"),
+        ("League of Legends", "signup.leagueoflegends.com"),
+        ("League of Legends", "authenticate.riotgames.com"),
+        ("League of Legends", "www.op.gg"),
+        ("League of Legends", "op.gg"),
+        ("League of Legends", "www.fow.kr"),
+        ("League of Legends", "fow.kr"),
+    ]
+}
@@ -0,0 +1,710 @@
+use super::hosts::SinkholeAddresses;
+use super::rules::RuleMatcher;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_OPT: u16 = 41;
+const BLOCKED_TTL: u32 = 60;
+/// Cap on the TTL of watched names, so a site in use keeps being looked up
+const WATCHED_TTL: u32 = 30;
+/// Threads forwarding queries upstream, and how many queries may wait for
+/// one before new ones are dropped (clients retry)
+const FORWARD_WORKERS: usize = 4;
+const FORWARD_QUEUE: usize = 64;
+/// How long `start_with_backoff` waits after the port couldn't be bound
+const START_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How long after our own `stop` the port being in use is no reason to
+/// back off
+const RESTART_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum DnsSinkholeError {
+    #[error("Invalid upstream resolver: {0}")]
+    InvalidUpstream(SocketAddr),
+}
+
+/// Whether the sinkhole runs and where it forwards to. It only takes effect
+/// once the system resolver is pointed at it, which FocusLock leaves to the
+/// user, so it is off until they opt in; until then suffix rules only block
+/// the bare domain and its `www.` through the hosts file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsSinkholeConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_upstream")]
+    pub upstream: SocketAddr,
+}
+
+impl Default for DnsSinkholeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            upstream: default_upstream(),
+        }
+    }
+}
+
+fn default_upstream() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::new(1, 1, 1, 1), 53))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsSinkholeStatus {
+    pub enabled: bool,
+    pub running: bool,
+    pub address: String,
+    pub upstream: String,
+}
+
+/// Loopback DNS resolver that answers blocked names (including suffix rules
+/// such as `*.googlevideo.com`) with the sinkhole addresses and forwards
+/// everything else upstream. The system resolver has to be pointed at
+/// `bind_addr` for it to take effect; see `DnsSinkholeConfig`.
+///
+/// Lookups of watched names are remembered, which is how usage budgets see
+/// that a site is being used.
+pub struct DnsSinkhole {
+    running: Arc<AtomicBool>,
+    enabled: AtomicBool,
+    matcher: Arc<RwLock<RuleMatcher>>,
+    watched: Arc<RwLock<RuleMatcher>>,
+    queried: Arc<Mutex<HashMap<String, Instant>>>,
+    answers: Arc<RwLock<SinkholeAddresses>>,
+    bind_addr: SocketAddr,
+    upstream: Arc<RwLock<SocketAddr>>,
+    /// When binding last failed, for `start_with_backoff`
+    start_failed_at: Mutex<Option<Instant>>,
+    stopped_at: Mutex<Option<Instant>>,
+    /// The receiving thread and the forwarding workers, all holding the socket
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// A query on its way to the upstream resolver.
+struct Forward {
+    query: Vec<u8>,
+    client: SocketAddr,
+    watched: bool,
+}
+
+impl DnsSinkhole {
+    pub fn new() -> Self {
+        Self::with_bind_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 53)))
+    }
+
+    fn with_bind_addr(bind_addr: SocketAddr) -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(false)),
+            enabled: AtomicBool::new(false),
+            matcher: Arc::new(RwLock::new(RuleMatcher::default())),
+            watched: Arc::new(RwLock::new(RuleMatcher::default())),
+            queried: Arc::new(Mutex::new(HashMap::new())),
+            answers: Arc::new(RwLock::new(SinkholeAddresses::default())),
+            bind_addr,
+            upstream: Arc::new(RwLock::new(default_upstream())),
+            start_failed_at: Mutex::new(None),
+            stopped_at: Mutex::new(None),
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn config(&self) -> DnsSinkholeConfig {
+        DnsSinkholeConfig {
+            enabled: self.is_enabled(),
+            upstream: *self.upstream.read().unwrap(),
+        }
+    }
+
+    /// A new upstream applies to the next forwarded query. Disabling stops
+    /// a running sinkhole; enabling leaves starting it to the caller.
+    pub fn set_config(&self, config: DnsSinkholeConfig) -> Result<(), DnsSinkholeError> {
+        let upstream = config.upstream;
+        if upstream.port() == 0 || upstream.ip().is_unspecified() || upstream == self.bind_addr {
+            return Err(DnsSinkholeError::InvalidUpstream(upstream));
+        }
+
+        *self.upstream.write().unwrap() = upstream;
+        self.enabled.store(config.enabled, Ordering::Relaxed);
+        // Worth trying the port again right away
+        *self.start_failed_at.lock().unwrap() = None;
+        if !config.enabled {
+            self.stop();
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rules(&self, domains: &[String]) {
+        *self.matcher.write().unwrap() = RuleMatcher::new(domains);
+    }
+
+    /// Names whose lookups are remembered, blocked or not.
+    pub fn set_watched(&self, domains: &[String]) {
+        *self.watched.write().unwrap() = RuleMatcher::new(domains);
+    }
+
+    /// Watched names looked up within the last `within`.
+    pub fn recent_queries(&self, within: Duration) -> Vec<String> {
+        let mut queried = self.queried.lock().unwrap();
+        queried.retain(|_, at| at.elapsed() <= within);
+        queried.keys().cloned().collect()
+    }
+
+    pub fn set_answers(&self, sinkhole: SinkholeAddresses) {
+        *self.answers.write().unwrap() = sinkhole;
+    }
+
+    pub fn start(&self) -> io::Result<()> {
+        if self.running.load(Ordering::Relaxed) {
+            return Ok(()); // Already running
+        }
+
+        // Bind before spawning so a privileged port failure reaches the caller
+        let socket = UdpSocket::bind(self.bind_addr)?;
+        socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+        let reply_sockets = (0..FORWARD_WORKERS)
+            .map(|_| socket.try_clone())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        self.running.store(true, Ordering::Relaxed);
+        let running = self.running.clone();
+        let matcher = self.matcher.clone();
+        let watched = self.watched.clone();
+        let queried = self.queried.clone();
+        let answers = self.answers.clone();
+
+        let (forwards, queue) = mpsc::sync_channel::<Forward>(FORWARD_QUEUE);
+        let queue = Arc::new(Mutex::new(queue));
+        let mut threads = self.threads.lock().unwrap();
+        for reply_socket in reply_sockets {
+            let queue = queue.clone();
+            let upstream = self.upstream.clone();
+            threads.push(thread::spawn(move || {
+                forward_queued(&queue, &reply_socket, &upstream)
+            }));
+        }
+
+        threads.push(thread::spawn(move || {
+            let mut buf = [0u8; 512];
+
+            while running.load(Ordering::Relaxed) {
+                let (len, client) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        continue
+                    }
+                    Err(e) => {
+                        log::warn!("DNS sinkhole receive failed: {}", e);
+                        continue;
+                    }
+                };
+                let query = buf[..len].to_vec();
+
+                let Some(question) = parse_question(&query) else {
+                    continue;
+                };
+
+                let is_watched = watched.read().unwrap().is_blocked(&question.name);
+                if is_watched {
+                    queried
+                        .lock()
+                        .unwrap()
+                        .insert(question.name.clone(), Instant::now());
+                }
+
+                if matcher.read().unwrap().is_blocked(&question.name) {
+                    log::debug!("Sinkholed DNS query for {}", question.name);
+                    let sinkhole = *answers.read().unwrap();
+                    let response = build_blocked_response(&query, &question, &sinkhole);
+                    let _ = socket.send_to(&response, client);
+                    continue;
+                }
+
+                let forward = Forward {
+                    query,
+                    client,
+                    watched: is_watched,
+                };
+                if let Err(TrySendError::Full(_)) = forwards.try_send(forward) {
+                    log::debug!(
+                        "DNS forward queue full, dropped query for {}",
+                        question.name
+                    );
+                }
+            }
+
+            // Dropping the sender lets the forwarding threads finish
+            log::info!("DNS sinkhole stopped");
+        }));
+
+        log::info!("DNS sinkhole listening on {}", self.bind_addr);
+        Ok(())
+    }
+
+    /// `start` for callers on a timer: after binding fails, further calls
+    /// return `Ok` without trying until `START_RETRY_INTERVAL` has passed.
+    /// The port still being in use right after `stop` doesn't count.
+    pub fn start_with_backoff(&self) -> io::Result<()> {
+        let mut failed_at = self.start_failed_at.lock().unwrap();
+        if failed_at.is_some_and(|at| at.elapsed() < START_RETRY_INTERVAL) {
+            return Ok(());
+        }
+        let result = self.start();
+        *failed_at = match &result {
+            Ok(()) => None,
+            // The port can linger a moment after our own stop; that is no
+            // reason to wait minutes
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && self.stopped_recently() => None,
+            Err(_) => Some(Instant::now()),
+        };
+        result
+    }
+
+    /// Stops answering and waits for the threads, so the port is free
+    /// again once this returns.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        let threads: Vec<_> = self.threads.lock().unwrap().drain(..).collect();
+        if threads.is_empty() {
+            return;
+        }
+        for handle in threads {
+            if handle.join().is_err() {
+                log::error!("DNS sinkhole thread panicked");
+            }
+        }
+        *self.stopped_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn stopped_recently(&self) -> bool {
+        self.stopped_at
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < RESTART_GRACE)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> DnsSinkholeStatus {
+        DnsSinkholeStatus {
+            enabled: self.is_enabled(),
+            running: self.is_running(),
+            address: self.bind_addr.to_string(),
+            upstream: self.upstream.read().unwrap().to_string(),
+        }
+    }
+}
+
+impl Default for DnsSinkhole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DnsSinkhole {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Question {
+    name: String,
+    qtype: u16,
+    /// Offset just past the question section
+    end: usize,
+}
+
+fn parse_question(packet: &[u8]) -> Option<Question> {
+    if packet.len() < 12 {
+        return None;
+    }
+    // Only standard queries with at least one question
+    let is_response = packet[2] & 0x80 != 0;
+    let qdcount = u16::from_be_bytes([packet[4], packet[5]]);
+    if is_response || qdcount == 0 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers never appear in a query's first question
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        let label = packet.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += len;
+    }
+
+    let qtype = u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]);
+    // Skip QTYPE and QCLASS
+    let end = pos + 4;
+    if end > packet.len() {
+        return None;
+    }
+
+    Some(Question {
+        name: labels.join("."),
+        qtype,
+        end,
+    })
+}
+
+fn build_blocked_response(
+    query: &[u8],
+    question: &Question,
+    sinkhole: &SinkholeAddresses,
+) -> Vec<u8> {
+    let mut response = Vec::with_capacity(question.end + 28);
+
+    // Header: same id, QR + opcode + RD from the query, RA set, NOERROR
+    response.extend_from_slice(&query[0..2]);
+    response.push(0x80 | (query[2] & 0x79));
+    response.push(0x80);
+
+    let rdata: Vec<u8> = match question.qtype {
+        TYPE_A => sinkhole.ipv4.octets().to_vec(),
+        TYPE_AAAA => sinkhole.ipv6.octets().to_vec(),
+        // Other record types (HTTPS, SVCB, ...) get an empty answer
+        _ => Vec::new(),
+    };
+    let ancount: u16 = if rdata.is_empty() { 0 } else { 1 };
+
+    response.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    response.extend_from_slice(&ancount.to_be_bytes());
+    response.extend_from_slice(&0u16.to_be_bytes()); // NSCOUNT
+    response.extend_from_slice(&0u16.to_be_bytes()); // ARCOUNT
+    response.extend_from_slice(&query[12..question.end]);
+
+    if ancount == 1 {
+        response.extend_from_slice(&[0xC0, 0x0C]); // Pointer to the question name
+        response.extend_from_slice(&question.qtype.to_be_bytes());
+        response.extend_from_slice(&1u16.to_be_bytes()); // Class IN
+        response.extend_from_slice(&BLOCKED_TTL.to_be_bytes());
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(&rdata);
+    }
+
+    response
+}
+
+/// Lowers every record TTL above `max` in place. Stops at the first record
+/// it can't parse, leaving the rest as they were.
+fn clamp_ttls(packet: &mut [u8], max: u32) {
+    if packet.len() < 12 {
+        return;
+    }
+    let count = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]) as usize;
+    let questions = count(4);
+    let records = count(6) + count(8) + count(10);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        let Some(end) = skip_name(packet, pos) else {
+            return;
+        };
+        pos = end + 4;
+    }
+
+    for _ in 0..records {
+        let Some(end) = skip_name(packet, pos) else {
+            return;
+        };
+        let Some(fixed) = packet.get(end..end + 10) else {
+            return;
+        };
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+
+        // OPT uses the TTL field for EDNS flags
+        if rtype != TYPE_OPT && ttl > max {
+            packet[end + 4..end + 8].copy_from_slice(&max.to_be_bytes());
+        }
+        pos = end + 10 + rdlength;
+    }
+}
+
+/// Offset just past the (possibly compressed) name at `pos`.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xC0 == 0xC0 {
+            return Some(pos + 2);
+        }
+        pos += 1 + len;
+    }
+}
+
+/// Forwards queued queries until the sinkhole drops its end of the queue.
+fn forward_queued(
+    queue: &Mutex<Receiver<Forward>>,
+    reply_socket: &UdpSocket,
+    upstream: &RwLock<SocketAddr>,
+) {
+    loop {
+        let Ok(job) = queue.lock().unwrap().recv() else {
+            return;
+        };
+        let upstream = *upstream.read().unwrap();
+        match forward(&job.query, upstream) {
+            Ok(mut response) => {
+                if job.watched {
+                    clamp_ttls(&mut response, WATCHED_TTL);
+                }
+                let _ = reply_socket.send_to(&response, job.client);
+            }
+            Err(e) => log::debug!("DNS forward to {} failed: {}", upstream, e),
+        }
+    }
+}
+
+fn forward(query: &[u8], upstream: SocketAddr) -> io::Result<Vec<u8>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(Duration::from_secs(3)))?;
+    socket.send_to(query, upstream)?;
+
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket.recv_from(&mut buf)?;
+    Ok(buf[..len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = id.to_be_bytes().to_vec();
+        packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet
+    }
+
+    fn answer_count(packet: &[u8]) -> u16 {
+        u16::from_be_bytes([packet[6], packet[7]])
+    }
+
+    #[test]
+    fn blocked_names_resolve_to_the_sinkhole() {
+        let packet = query(7, "WWW.YouTube.com", TYPE_A);
+        let question = parse_question(&packet).unwrap();
+        assert_eq!(question.name, "www.youtube.com");
+
+        let response = build_blocked_response(&packet, &question, &SinkholeAddresses::default());
+        assert_eq!(&response[..2], &7u16.to_be_bytes());
+        assert_eq!(answer_count(&response), 1);
+        assert!(response.ends_with(&[127, 0, 0, 1]));
+
+        // HTTPS records get an empty answer rather than an address
+        let packet = query(8, "youtube.com", 65);
+        let question = parse_question(&packet).unwrap();
+        let response = build_blocked_response(&packet, &question, &SinkholeAddresses::default());
+        assert_eq!(answer_count(&response), 0);
+
+        // Responses are never answered
+        let mut packet = query(9, "youtube.com", TYPE_A);
+        packet[2] |= 0x80;
+        assert!(parse_question(&packet).is_none());
+    }
+
+    #[test]
+    fn watched_answers_get_short_ttls() {
+        let mut packet = query(1, "example.com", TYPE_A);
+        packet[2] |= 0x80;
+        packet[7] = 1; // ANCOUNT
+        packet[11] = 1; // ARCOUNT
+                        // A record with a one hour TTL, compressed name
+        packet.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+        let answer_ttl = packet.len();
+        packet.extend_from_slice(&3600u32.to_be_bytes());
+        packet.extend_from_slice(&[0, 4, 93, 184, 216, 34]);
+        // OPT record, whose TTL field holds EDNS flags
+        packet.extend_from_slice(&[0, 0, 41, 0x10, 0]);
+        let opt_ttl = packet.len();
+        packet.extend_from_slice(&0x8000u32.to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+
+        clamp_ttls(&mut packet, WATCHED_TTL);
+
+        assert_eq!(
+            &packet[answer_ttl..answer_ttl + 4],
+            &WATCHED_TTL.to_be_bytes()
+        );
+        assert_eq!(&packet[opt_ttl..opt_ttl + 4], &0x8000u32.to_be_bytes());
+    }
+
+    #[test]
+    fn failed_starts_are_retried_after_a_while() {
+        let taken = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let sinkhole = DnsSinkhole::with_bind_addr(taken.local_addr().unwrap());
+
+        assert!(sinkhole.start_with_backoff().is_err());
+        assert!(sinkhole.start_with_backoff().is_ok());
+        assert!(!sinkhole.is_running());
+
+        // Changing the settings tries again
+        sinkhole.set_config(DnsSinkholeConfig::default()).unwrap();
+        assert!(sinkhole.start_with_backoff().is_err());
+    }
+
+    #[test]
+    fn restarts_right_after_stopping() {
+        let sinkhole = enabled_sinkhole();
+        sinkhole.start().unwrap();
+        sinkhole.stop();
+        assert!(!sinkhole.is_running());
+
+        sinkhole.start_with_backoff().unwrap();
+        assert!(sinkhole.is_running());
+        sinkhole.stop();
+    }
+
+    #[test]
+    fn upstream_must_be_a_resolver() {
+        let sinkhole = DnsSinkhole::new();
+        assert!(!sinkhole.is_enabled());
+
+        let own = DnsSinkholeConfig {
+            enabled: true,
+            upstream: SocketAddr::from((Ipv4Addr::LOCALHOST, 53)),
+        };
+        assert!(sinkhole.set_config(own).is_err());
+        let unspecified = DnsSinkholeConfig {
+            enabled: true,
+            upstream: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 53)),
+        };
+        assert!(sinkhole.set_config(unspecified).is_err());
+        assert_eq!(sinkhole.config(), DnsSinkholeConfig::default());
+
+        let quad9 = DnsSinkholeConfig {
+            enabled: true,
+            upstream: SocketAddr::from((Ipv4Addr::new(9, 9, 9, 9), 53)),
+        };
+        sinkhole.set_config(quad9).unwrap();
+        assert_eq!(sinkhole.config(), quad9);
+        assert_eq!(sinkhole.status().upstream, "9.9.9.9:53");
+    }
+
+    /// A sinkhole on a free port forwarding to a resolver that answers
+    /// every query with its own header flipped to a response.
+    fn enabled_sinkhole() -> DnsSinkhole {
+        let upstream = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, from)) = upstream.recv_from(&mut buf) {
+                buf[2] |= 0x80;
+                let _ = upstream.send_to(&buf[..len], from);
+            }
+        });
+
+        let free = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let bind_addr = free.local_addr().unwrap();
+        drop(free);
+        let sinkhole = DnsSinkhole::with_bind_addr(bind_addr);
+        sinkhole
+            .set_config(DnsSinkholeConfig {
+                enabled: true,
+                upstream: upstream_addr,
+            })
+            .unwrap();
+        sinkhole
+    }
+
+    fn client() -> UdpSocket {
+        let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn sinkholes_blocked_names_and_forwards_the_rest() {
+        let sinkhole = enabled_sinkhole();
+        let bind_addr = sinkhole.bind_addr;
+        sinkhole.set_rules(&["*.example.com".to_string()]);
+        sinkhole.set_watched(&["news.site".to_string()]);
+        sinkhole.start().unwrap();
+
+        let client = client();
+        let mut buf = [0u8; 512];
+
+        client
+            .send_to(&query(1, "cdn.example.com", TYPE_A), bind_addr)
+            .unwrap();
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(answer_count(&buf[..len]), 1);
+        assert!(buf[..len].ends_with(&[127, 0, 0, 1]));
+
+        let forwarded = query(2, "news.site", TYPE_A);
+        client.send_to(&forwarded, bind_addr).unwrap();
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(len, forwarded.len());
+        assert_eq!(&buf[..2], &2u16.to_be_bytes());
+        assert_eq!(answer_count(&buf[..len]), 0);
+        assert_eq!(
+            sinkhole.recent_queries(Duration::from_secs(60)),
+            ["news.site"]
+        );
+
+        sinkhole.stop();
+    }
+    #[test]
+    fn released_names_still_forward() {
+        let sinkhole = enabled_sinkhole();
+        let bind_addr = sinkhole.bind_addr;
+        sinkhole.set_rules(&["example.com".to_string()]);
+        sinkhole.start().unwrap();
+
+        let client = client();
+        let mut buf = [0u8; 512];
+        let blocked = query(1, "example.com", TYPE_A);
+        client.send_to(&blocked, bind_addr).unwrap();
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(answer_count(&buf[..len]), 1);
+
+        // What releasing the block does; the resolver still points here
+        sinkhole.set_rules(&[]);
+        assert!(sinkhole.is_running());
+
+        client.send_to(&blocked, bind_addr).unwrap();
+        let (len, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(len, blocked.len());
+        assert_eq!(answer_count(&buf[..len]), 0);
+
+        sinkhole.stop();
+    }
+}
@@ -0,0 +1,210 @@
+use serde::Serialize;
+use std::io;
+use std::process::{Command, Stdio};
+
+/// Runs external programs; swapped for a fake in tests.
+pub trait CommandRunner {
+    /// Runs `program` to completion and reports whether it exited successfully.
+    /// An `Err` means the program could not be started (usually not installed).
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<bool>;
+}
+
+pub struct SystemCommandRunner;
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<bool> {
+        Command::new(program)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsResolver {
+    WindowsDnsClient,
+    DirectoryServices,
+    MdnsResponder,
+    SystemdResolved,
+    Nscd,
+    Dnsmasq,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FlushReport {
+    pub flushed: Vec<DnsResolver>,
+    pub failed: Vec<DnsResolver>,
+}
+
+impl FlushReport {
+    fn record(&mut self, resolver: DnsResolver, ok: bool) {
+        if ok {
+            self.flushed.push(resolver);
+        } else {
+            log::warn!("Failed to flush {:?} DNS cache", resolver);
+            self.failed.push(resolver);
+        }
+    }
+}
+
+/// Flushes every DNS cache found on this platform so hosts changes apply immediately.
+pub fn flush_dns_cache(runner: &dyn CommandRunner) -> FlushReport {
+    #[cfg(target_os = "windows")]
+    let report = flush_windows(runner);
+    #[cfg(target_os = "macos")]
+    let report = flush_macos(runner);
+    #[cfg(target_os = "linux")]
+    let report = flush_linux(runner);
+    #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+    let report = {
+        let _ = runner;
+        FlushReport::default()
+    };
+
+    log::info!("Flushed DNS caches: {:?}", report.flushed);
+    report
+}
+
+#[cfg(target_os = "windows")]
+fn flush_windows(runner: &dyn CommandRunner) -> FlushReport {
+    let mut report = FlushReport::default();
+    let ok = runner.run("ipconfig", &["/flushdns"]).unwrap_or(false);
+    report.record(DnsResolver::WindowsDnsClient, ok);
+    report
+}
+
+#[cfg(target_os = "macos")]
+fn flush_macos(runner: &dyn CommandRunner) -> FlushReport {
+    let mut report = FlushReport::default();
+
+    let ok = runner.run("dscacheutil", &["-flushcache"]).unwrap_or(false);
+    report.record(DnsResolver::DirectoryServices, ok);
+
+    let ok = runner
+        .run("killall", &["-HUP", "mDNSResponder"])
+        .unwrap_or(false);
+    report.record(DnsResolver::MdnsResponder, ok);
+
+    report
+}
+
+/// Linux has no single resolver cache, so each known daemon is detected and
+/// flushed only if it is actually running.
+#[cfg(target_os = "linux")]
+fn flush_linux(runner: &dyn CommandRunner) -> FlushReport {
+    let mut report = FlushReport::default();
+
+    let resolved_active = runner
+        .run("systemctl", &["is-active", "--quiet", "systemd-resolved"])
+        .unwrap_or(false);
+    if resolved_active {
+        // `resolvectl` replaced `systemd-resolve` in systemd 239
+        let ok = runner
+            .run("resolvectl", &["flush-caches"])
+            .or_else(|_| runner.run("systemd-resolve", &["--flush-caches"]))
+            .unwrap_or(false);
+        report.record(DnsResolver::SystemdResolved, ok);
+    }
+
+    if is_process_running(runner, "nscd") {
+        let ok = runner.run("nscd", &["-i", "hosts"]).unwrap_or(false);
+        report.record(DnsResolver::Nscd, ok);
+    }
+
+    if is_process_running(runner, "dnsmasq") {
+        // dnsmasq clears its cache and re-reads /etc/hosts on SIGHUP
+        let ok = runner
+            .run("pkill", &["-HUP", "-x", "dnsmasq"])
+            .unwrap_or(false);
+        report.record(DnsResolver::Dnsmasq, ok);
+    }
+
+    report
+}
+
+#[cfg(target_os = "linux")]
+fn is_process_running(runner: &dyn CommandRunner, name: &str) -> bool {
+    runner.run("pgrep", &["-x", name]).unwrap_or(false)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Pretends the listed programs succeed and records every invocation.
+    struct FakeRunner {
+        succeeds: Vec<&'static str>,
+        missing: Vec<&'static str>,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl FakeRunner {
+        fn new(succeeds: &[&'static str], missing: &[&'static str]) -> Self {
+            Self {
+                succeeds: succeeds.to_vec(),
+                missing: missing.to_vec(),
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run(&self, program: &str, args: &[&str]) -> io::Result<bool> {
+            let call = format!("{} {}", program, args.join(" "));
+            self.calls.borrow_mut().push(call.clone());
+
+            if self.missing.contains(&program) {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            Ok(self.succeeds.iter().any(|s| call.starts_with(s)))
+        }
+    }
+
+    #[test]
+    fn flushes_only_running_resolvers() {
+        let runner = FakeRunner::new(
+            &[
+                "systemctl is-active",
+                "resolvectl",
+                "pgrep -x nscd",
+                "nscd -i",
+            ],
+            &[],
+        );
+
+        let report = flush_linux(&runner);
+
+        assert_eq!(
+            report.flushed,
+            vec![DnsResolver::SystemdResolved, DnsResolver::Nscd]
+        );
+        assert!(report.failed.is_empty());
+        assert!(!runner.calls.borrow().iter().any(|c| c.starts_with("pkill")));
+    }
+
+    #[test]
+    fn falls_back_to_systemd_resolve() {
+        let runner = FakeRunner::new(
+            &["systemctl is-active", "systemd-resolve --flush-caches"],
+            &["resolvectl"],
+        );
+
+        let report = flush_linux(&runner);
+
+        assert_eq!(report.flushed, vec![DnsResolver::SystemdResolved]);
+    }
+
+    #[test]
+    fn reports_failed_flush() {
+        let runner = FakeRunner::new(&["pgrep -x dnsmasq"], &[]);
+
+        let report = flush_linux(&runner);
+
+        assert!(report.flushed.is_empty());
+        assert_eq!(report.failed, vec![DnsResolver::Dnsmasq]);
+    }
+}
@@ -0,0 +1,60 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// SHA-256 of a file as lowercase hex.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Remembers executable hashes keyed by path, invalidated when the file's
+/// mtime or size changes, so the watcher loop doesn't rehash every pass.
+#[derive(Default)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, (SystemTime, u64, String)>,
+}
+
+impl HashCache {
+    pub fn hash(&mut self, path: &Path) -> Option<String> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?;
+        let len = metadata.len();
+
+        if let Some((cached_modified, cached_len, hash)) = self.entries.get(path) {
+            if *cached_modified == modified && *cached_len == len {
+                return Some(hash.clone());
+            }
+        }
+
+        match sha256_file(path) {
+            Ok(hash) => {
+                self.entries
+                    .insert(path.to_path_buf(), (modified, len, hash.clone()));
+                Some(hash)
+            }
+            Err(e) => {
+                log::debug!("Cannot hash {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
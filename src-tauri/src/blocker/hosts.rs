@@ -1,26 +1,103 @@
-use std::fs::{self, OpenOptions};
+use super::dns_flush::{flush_dns_cache, SystemCommandRunner};
+use super::rules::RuleMatcher;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use thiserror::Error;
 
+/// Number of pre-FocusLock hosts backups kept in the app data dir.
+const MAX_BACKUPS: usize = 5;
+
 #[derive(Error, Debug)]
 pub enum HostsError {
     #[error("Failed to read hosts file: {0}")]
     ReadError(#[from] std::io::Error),
+    #[error("Failed to write hosts file: {0}")]
+    WriteError(std::io::Error),
     #[error("Permission denied. Run as administrator.")]
     PermissionDenied,
     #[error("Failed to backup hosts file")]
     BackupFailed,
+    #[error("No hosts file backup found")]
+    NoBackup,
+    #[error("Sinkhole address must be a loopback or unspecified address: {0}")]
+    InvalidSinkhole(IpAddr),
+}
+
+/// Where blocked names are pointed, once for A and once for AAAA lookups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SinkholeAddresses {
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Ipv6Addr,
+}
+
+impl SinkholeAddresses {
+    /// Only local addresses are accepted; anything else could route blocked
+    /// names to a real server.
+    pub fn validate(&self) -> Result<(), HostsError> {
+        if !(self.ipv4.is_loopback() || self.ipv4.is_unspecified()) {
+            return Err(HostsError::InvalidSinkhole(self.ipv4.into()));
+        }
+        if !(self.ipv6.is_loopback() || self.ipv6.is_unspecified()) {
+            return Err(HostsError::InvalidSinkhole(self.ipv6.into()));
+        }
+        Ok(())
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        addr == IpAddr::V4(self.ipv4) || addr == IpAddr::V6(self.ipv6)
+    }
+}
+
+impl Default for SinkholeAddresses {
+    fn default() -> Self {
+        Self {
+            ipv4: Ipv4Addr::LOCALHOST,
+            ipv6: Ipv6Addr::LOCALHOST,
+        }
+    }
+}
+
+/// A blocked name that still resolves to a routable address.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedLeak {
+    pub domain: String,
+    pub addresses: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockVerification {
+    pub checked: usize,
+    pub leaks: Vec<ResolvedLeak>,
 }
 
+/// What the startup check found in the hosts file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostsRecovery {
+    Clean,
+    RemovedUnterminatedBlock,
+    RemovedStrayMarker,
+    RestoredBackup,
+}
+
+/// Clones share the sinkhole configuration, so a watcher holding a clone
+/// writes the same entries as the blocker it came from.
+#[derive(Clone)]
 pub struct HostsBlocker {
     hosts_path: PathBuf,
+    backup_dir: PathBuf,
+    sinkhole: Arc<RwLock<SinkholeAddresses>>,
     marker_start: String,
     marker_end: String,
 }
 
 impl HostsBlocker {
-    pub fn new() -> Self {
+    pub fn new(app_data_dir: PathBuf) -> Self {
         let hosts_path = if cfg!(target_os = "windows") {
             PathBuf::from(r"C:\Windows\System32\drivers\etc\hosts")
         } else if cfg!(target_os = "macos") {
@@ -31,89 +108,49 @@ impl HostsBlocker {
 
         Self {
             hosts_path,
+            backup_dir: app_data_dir.join("hosts-backups"),
+            sinkhole: Arc::new(RwLock::new(SinkholeAddresses::default())),
             marker_start: "# === FOCUSLOCK START ===".to_string(),
             marker_end: "# === FOCUSLOCK END ===".to_string(),
         }
     }
 
-    pub fn get_blocked_domains() -> Vec<&'static str> {
-        vec![
-            // YouTube
-            "www.youtube.com",
-            "youtube.com",
-            "m.youtube.com",
-            "youtu.be",
-            "www.youtube-nocookie.com",
-            "youtube-nocookie.com",
-            "youtubei.googleapis.com",
-            "yt3.ggpht.com",
-            "music.youtube.com",
-            "studio.youtube.com",
-            // Chzzk
-            "chzzk.naver.com",
-            "api.chzzk.naver.com",
-            "live.chzzk.naver.com",
-            "m.chzzk.naver.com",
-            // Instagram
-            "www.instagram.com",
-            "instagram.com",
-            "i.instagram.com",
-            "graph.instagram.com",
-            "api.instagram.com",
-            "l.instagram.com",
-            "static.cdninstagram.com",
-            "scontent.cdninstagram.com",
-            // League of Legends related
-            "www.leagueoflegends.com",
-            "leagueoflegends.com",
-            "signup.leagueoflegends.com",
-            "authenticate.riotgames.com",
-            "www.op.gg",
-            "op.gg",
-            "www.fow.kr",
-            "fow.kr",
-        ]
-    }
-
-    pub fn block(&self) -> Result<(), HostsError> {
-        let domains = Self::get_blocked_domains();
+    pub fn hosts_path(&self) -> &Path {
+        &self.hosts_path
+    }
+
+    pub fn sinkhole(&self) -> SinkholeAddresses {
+        *self.sinkhole.read().unwrap()
+    }
+
+    /// Takes effect on the next `block`.
+    pub fn set_sinkhole(&self, sinkhole: SinkholeAddresses) -> Result<(), HostsError> {
+        sinkhole.validate()?;
+        *self.sinkhole.write().unwrap() = sinkhole;
+        Ok(())
+    }
+
+    pub fn block(&self, domains: &[String]) -> Result<(), HostsError> {
+        // The hosts file only understands exact names
+        let domains = RuleMatcher::new(domains).host_names();
 
         // Read current hosts file
         let content = fs::read_to_string(&self.hosts_path)?;
 
-        // Check if already blocked
-        if content.contains(&self.marker_start) {
-            // Already has our block, remove it first
-            self.unblock()?;
+        // Keep a copy of the file as it was before FocusLock touched it
+        if !content.contains(&self.marker_start) {
+            self.backup(&content)?;
         }
 
-        // Re-read after potential unblock
-        let _content = fs::read_to_string(&self.hosts_path)?;
+        // Drop any existing block so it is replaced rather than duplicated
+        let mut new_content = self.strip_block(&content);
+        new_content.push('\n');
+        new_content.push_str(&self.block_section(&domains));
 
-        // Build block entries
-        let mut block_entries = String::new();
-        block_entries.push_str(&format!("\n{}\n", self.marker_start));
-        for domain in &domains {
-            block_entries.push_str(&format!("127.0.0.1 {}\n", domain));
-        }
-        block_entries.push_str(&format!("{}\n", self.marker_end));
-
-        // Append to hosts file
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(&self.hosts_path)
-            .map_err(|_| HostsError::PermissionDenied)?;
-
-        file.write_all(block_entries.as_bytes())?;
-
-        // Flush DNS cache on Windows
-        #[cfg(target_os = "windows")]
-        {
-            let _ = std::process::Command::new("ipconfig")
-                .args(["/flushdns"])
-                .output();
-        }
+        self.write_hosts(&new_content)?;
+
+        // Flush DNS cache
+        flush_dns_cache(&SystemCommandRunner);
 
         log::info!("Blocked {} domains", domains.len());
         Ok(())
@@ -122,6 +159,141 @@ impl HostsBlocker {
     pub fn unblock(&self) -> Result<(), HostsError> {
         let content = fs::read_to_string(&self.hosts_path)?;
 
+        self.write_hosts(&self.strip_block(&content))?;
+
+        // Flush DNS cache
+        flush_dns_cache(&SystemCommandRunner);
+
+        log::info!("Unblocked all domains");
+        Ok(())
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        if let Ok(content) = fs::read_to_string(&self.hosts_path) {
+            content.contains(&self.marker_start)
+        } else {
+            false
+        }
+    }
+
+    /// Whether the hosts file still holds exactly the section `block` would
+    /// write for these domains.
+    pub fn is_block_intact(&self, domains: &[String]) -> bool {
+        let expected = self.block_section(&RuleMatcher::new(domains).host_names());
+        fs::read_to_string(&self.hosts_path)
+            .map(|content| content.contains(&expected))
+            .unwrap_or(false)
+    }
+
+    /// Resolves every blocked name through the system resolver and reports
+    /// those that still reach a real address, e.g. from a stale DNS cache or
+    /// a browser using its own DNS-over-HTTPS.
+    pub fn verify(&self, domains: &[String]) -> BlockVerification {
+        let sinkhole = self.sinkhole();
+        let host_names = RuleMatcher::new(domains).host_names();
+
+        let leaks: Vec<ResolvedLeak> = host_names
+            .iter()
+            .filter_map(|domain| {
+                // Names that no longer resolve at all are blocked as well
+                let resolved = (domain.as_str(), 0).to_socket_addrs().ok()?;
+                let addresses: Vec<IpAddr> = resolved
+                    .map(|addr| addr.ip())
+                    .filter(|ip| {
+                        !(sinkhole.contains(*ip) || ip.is_loopback() || ip.is_unspecified())
+                    })
+                    .collect();
+
+                if addresses.is_empty() {
+                    None
+                } else {
+                    Some(ResolvedLeak {
+                        domain: domain.clone(),
+                        addresses,
+                    })
+                }
+            })
+            .collect();
+
+        if !leaks.is_empty() {
+            log::warn!("{} blocked domains still resolve", leaks.len());
+        }
+
+        BlockVerification {
+            checked: host_names.len(),
+            leaks,
+        }
+    }
+
+    /// Repairs the damage an interrupted write can leave behind: a missing or
+    /// empty hosts file, or a START marker without its END (or vice versa).
+    pub fn recover(&self) -> Result<HostsRecovery, HostsError> {
+        // A leftover temp file means the last write never reached the rename
+        let _ = fs::remove_file(self.temp_path()?);
+
+        let content = match fs::read_to_string(&self.hosts_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        if content.trim().is_empty() {
+            return match self.latest_backup() {
+                Some(_) => {
+                    self.restore_backup()?;
+                    log::warn!("Hosts file was empty, restored from backup");
+                    Ok(HostsRecovery::RestoredBackup)
+                }
+                None => Ok(HostsRecovery::Clean),
+            };
+        }
+
+        let starts = content
+            .lines()
+            .filter(|line| line.contains(&self.marker_start))
+            .count();
+        let ends = content
+            .lines()
+            .filter(|line| line.contains(&self.marker_end))
+            .count();
+
+        let recovery = if starts > ends {
+            HostsRecovery::RemovedUnterminatedBlock
+        } else if ends > starts {
+            HostsRecovery::RemovedStrayMarker
+        } else {
+            return Ok(HostsRecovery::Clean);
+        };
+
+        // Everything after an unterminated START was written by us
+        self.write_hosts(&self.strip_block(&content))?;
+        log::warn!("Repaired hosts file: {:?}", recovery);
+        Ok(recovery)
+    }
+
+    /// Replaces the hosts file with the most recent pre-FocusLock backup.
+    pub fn restore_backup(&self) -> Result<PathBuf, HostsError> {
+        let backup = self.latest_backup().ok_or(HostsError::NoBackup)?;
+        let content = fs::read_to_string(&backup)?;
+
+        self.write_hosts(&content)?;
+        log::info!("Restored hosts file from {}", backup.display());
+        Ok(backup)
+    }
+
+    fn block_section(&self, host_names: &[String]) -> String {
+        let sinkhole = self.sinkhole();
+
+        let mut section = format!("{}\n", self.marker_start);
+        for domain in host_names {
+            section.push_str(&format!("{} {}\n", sinkhole.ipv4, domain));
+            section.push_str(&format!("{} {}\n", sinkhole.ipv6, domain));
+        }
+        section.push_str(&format!("{}\n", self.marker_end));
+        section
+    }
+
+    fn strip_block(&self, content: &str) -> String {
         let mut new_content = String::new();
         let mut in_block = false;
 
@@ -145,32 +317,106 @@ impl HostsBlocker {
             new_content.pop();
         }
 
-        fs::write(&self.hosts_path, new_content)
-            .map_err(|_| HostsError::PermissionDenied)?;
+        new_content
+    }
 
-        // Flush DNS cache
-        #[cfg(target_os = "windows")]
-        {
-            let _ = std::process::Command::new("ipconfig")
-                .args(["/flushdns"])
-                .output();
+    /// Writes via temp file + fsync + rename so the hosts file is never seen
+    /// half-written, even after a crash or power loss.
+    fn write_hosts(&self, content: &str) -> Result<(), HostsError> {
+        let target = self.target_path();
+        let temp_path = self.temp_path()?;
+
+        let result: std::io::Result<()> = (|| {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+            drop(file);
+
+            if let Ok(metadata) = fs::metadata(&target) {
+                fs::set_permissions(&temp_path, metadata.permissions())?;
+            }
+            fs::rename(&temp_path, &target)?;
+
+            // Persist the rename itself
+            #[cfg(unix)]
+            if let Some(dir) = target.parent() {
+                File::open(dir)?.sync_all()?;
+            }
+
+            Ok(())
+        })();
+
+        result.map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                HostsError::PermissionDenied
+            } else {
+                HostsError::WriteError(e)
+            }
+        })
+    }
+
+    /// Resolves symlinks (macOS /etc -> /private/etc) so the rename replaces the real file.
+    fn target_path(&self) -> PathBuf {
+        fs::canonicalize(&self.hosts_path).unwrap_or_else(|_| self.hosts_path.clone())
+    }
+
+    fn temp_path(&self) -> Result<PathBuf, HostsError> {
+        let target = self.target_path();
+        let dir = target.parent().ok_or(HostsError::PermissionDenied)?;
+        Ok(dir.join(".hosts.focuslock.tmp"))
+    }
+
+    fn backup(&self, content: &str) -> Result<(), HostsError> {
+        // Skip if nothing changed since the last backup
+        if let Some(latest) = self.latest_backup() {
+            if fs::read_to_string(&latest).ok().as_deref() == Some(content) {
+                return Ok(());
+            }
         }
 
-        log::info!("Unblocked all domains");
+        let name = format!("hosts-{}.bak", Local::now().format("%Y%m%d-%H%M%S"));
+        fs::create_dir_all(&self.backup_dir)
+            .and_then(|_| fs::write(self.backup_dir.join(&name), content))
+            .map_err(|e| {
+                log::error!("Failed to back up hosts file: {}", e);
+                HostsError::BackupFailed
+            })?;
+
+        // Prune old backups
+        let backups = self.backups();
+        if backups.len() > MAX_BACKUPS {
+            for old in &backups[..backups.len() - MAX_BACKUPS] {
+                let _ = fs::remove_file(old);
+            }
+        }
+
+        log::info!("Backed up hosts file to {}", name);
         Ok(())
     }
 
-    pub fn is_blocked(&self) -> bool {
-        if let Ok(content) = fs::read_to_string(&self.hosts_path) {
-            content.contains(&self.marker_start)
-        } else {
-            false
-        }
+    /// Backups sorted oldest first; the timestamped names sort chronologically.
+    fn backups(&self) -> Vec<PathBuf> {
+        let mut backups: Vec<PathBuf> = fs::read_dir(&self.backup_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| is_backup_file(path))
+                    .collect()
+            })
+            .unwrap_or_default();
+        backups.sort();
+        backups
     }
-}
 
-impl Default for HostsBlocker {
-    fn default() -> Self {
-        Self::new()
+    fn latest_backup(&self) -> Option<PathBuf> {
+        self.backups().pop()
     }
 }
+
+fn is_backup_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with("hosts-") && name.ends_with(".bak"))
+        .unwrap_or(false)
+}
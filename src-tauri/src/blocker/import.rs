@@ -0,0 +1,230 @@
+use super::blocklist::normalize_domain;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+/// Names that appear in most hosts files and must never be imported.
+const RESERVED_HOSTS: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineError {
+    pub line: usize,
+    pub content: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ParsedBlocklist {
+    pub domains: Vec<String>,
+    pub duplicates: usize,
+    pub errors: Vec<LineError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub parsed: usize,
+    pub added: usize,
+    pub duplicates: usize,
+    pub errors: Vec<LineError>,
+}
+
+/// Parses a third-party list in hosts-file, AdBlock/uBlock or plain
+/// one-domain-per-line syntax. Formats may be mixed within one file.
+pub fn parse_blocklist(content: &str) -> ParsedBlocklist {
+    let mut parsed = ParsedBlocklist::default();
+    let mut seen = HashSet::new();
+
+    for (idx, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+
+        // Blank lines, hosts/plain comments, AdBlock comments and headers
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with('!')
+            || line.starts_with('[')
+        {
+            continue;
+        }
+
+        match parse_line(line) {
+            Ok(domains) => {
+                for domain in domains {
+                    if seen.insert(domain.clone()) {
+                        parsed.domains.push(domain);
+                    } else {
+                        parsed.duplicates += 1;
+                    }
+                }
+            }
+            Err(reason) => parsed.errors.push(LineError {
+                line: idx + 1,
+                content: raw.to_string(),
+                reason,
+            }),
+        }
+    }
+
+    parsed
+}
+
+fn parse_line(line: &str) -> Result<Vec<String>, String> {
+    if line.starts_with("@@") {
+        return Err("Exception rules are not supported".to_string());
+    }
+
+    if let Some(rule) = line.strip_prefix("||") {
+        return parse_adblock_rule(rule).map(|domain| vec![domain]);
+    }
+
+    if ["##", "#@#", "#?#", "#$#"]
+        .iter()
+        .any(|sep| line.contains(sep))
+    {
+        return Err("Cosmetic filters are not supported".to_string());
+    }
+
+    // Drop trailing comments: "0.0.0.0 example.com # ads"
+    let line = line.split('#').next().unwrap_or_default().trim();
+    let mut tokens = line.split_whitespace().peekable();
+
+    let is_hosts_line = tokens
+        .peek()
+        .map(|first| first.parse::<IpAddr>().is_ok())
+        .unwrap_or(false);
+
+    if is_hosts_line {
+        tokens.next();
+        let names: Vec<&str> = tokens.collect();
+        if names.is_empty() {
+            return Err("Hosts entry has no host name".to_string());
+        }
+        return names
+            .into_iter()
+            .filter(|name| !RESERVED_HOSTS.contains(&name.to_ascii_lowercase().as_str()))
+            .map(normalize_import)
+            .collect();
+    }
+
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    match tokens.as_slice() {
+        [domain] => normalize_import(domain).map(|domain| vec![domain]),
+        _ => Err("Expected a single domain per line".to_string()),
+    }
+}
+
+/// `||example.com^` blocks the domain and every subdomain, which maps to our
+/// `*.example.com` suffix rule.
+fn parse_adblock_rule(rule: &str) -> Result<String, String> {
+    let (pattern, options) = match rule.split_once('$') {
+        Some((pattern, options)) => (pattern, Some(options)),
+        None => (rule, None),
+    };
+
+    if let Some(options) = options {
+        // Rules scoped to particular resources or sites cannot be expressed as a domain block
+        if options
+            .split(',')
+            .any(|opt| opt.starts_with("domain=") || opt.starts_with("denyallow="))
+        {
+            return Err("Rule is scoped to specific sites".to_string());
+        }
+    }
+
+    let domain = pattern.strip_suffix('^').unwrap_or(pattern);
+    if domain.contains(['/', '^', '*', '|']) {
+        return Err("Only domain-level rules (||example.com^) are supported".to_string());
+    }
+
+    let domain = normalize_import(domain)?;
+    if domain.starts_with("*.") {
+        Ok(domain)
+    } else {
+        Ok(format!("*.{}", domain))
+    }
+}
+
+fn normalize_import(domain: &str) -> Result<String, String> {
+    let ascii = to_ascii_domain(domain.trim().trim_end_matches('.'))
+        .ok_or_else(|| format!("Cannot convert {} to punycode", domain))?;
+    normalize_domain(&ascii).map_err(|e| e.to_string())
+}
+
+/// Converts internationalized labels to their `xn--` punycode form after
+/// IDNA mapping (case folding and normalization), keeping a leading `*.`.
+pub fn to_ascii_domain(domain: &str) -> Option<String> {
+    match domain.strip_prefix("*.") {
+        Some(base) => idna::domain_to_ascii(base)
+            .ok()
+            .map(|base| format!("*.{}", base)),
+        None => idna::domain_to_ascii(domain).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3492_samples_encode() {
+        // Sample strings from RFC 3492 section 7.1, lowercased as IDNA maps them
+        assert_eq!(
+            to_ascii_domain("他们为什么不说中文").unwrap(),
+            "xn--ihqwcrb4cv8a8dqg056pqjye"
+        );
+        assert_eq!(
+            to_ascii_domain("почемужеонинеговорятпорусски").unwrap(),
+            "xn--b1abfaaepdrnnbgefbadotcwatmq2g4l"
+        );
+        assert_eq!(
+            to_ascii_domain("3年B組金八先生").unwrap(),
+            "xn--3b-ww4c5e180e575a65lsy2b"
+        );
+        assert_eq!(
+            to_ascii_domain("そのスピードで").unwrap(),
+            "xn--d9juau41awczczp"
+        );
+    }
+
+    #[test]
+    fn names_are_case_folded_and_normalized() {
+        assert_eq!(to_ascii_domain("BÜCHER.de").unwrap(), "xn--bcher-kva.de");
+        // u + combining diaeresis is the same name as ü
+        assert_eq!(
+            to_ascii_domain("bu\u{308}cher.de").unwrap(),
+            "xn--bcher-kva.de"
+        );
+        assert_eq!(
+            to_ascii_domain("*.Bücher.de").unwrap(),
+            "*.xn--bcher-kva.de"
+        );
+        assert_eq!(to_ascii_domain("Example.COM").unwrap(), "example.com");
+    }
+
+    #[test]
+    fn lists_import_internationalized_names() {
+        let parsed = parse_blocklist(
+            "0.0.0.0 BÜCHER.de localhost\n||müller.example^\nbücher.de\n@@||ok.com^\n",
+        );
+
+        assert_eq!(
+            parsed.domains,
+            ["xn--bcher-kva.de", "*.xn--mller-kva.example"]
+        );
+        assert_eq!(parsed.duplicates, 1);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.errors[0].line, 4);
+    }
+}
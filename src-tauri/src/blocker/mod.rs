@@ -1,5 +1,27 @@
+pub mod block_page;
+pub mod blocklist;
+pub mod dns;
+pub mod dns_flush;
+pub mod exe_hash;
 pub mod hosts;
+pub mod import;
+#[cfg(target_os = "linux")]
+pub mod proc_connector;
 pub mod process;
+pub mod process_rules;
+pub mod rules;
+pub mod suspend;
+pub mod tamper;
+pub mod terminate;
 
-pub use hosts::HostsBlocker;
+pub use block_page::{BlockPageServer, BlockedAttempt};
+pub use blocklist::{BlockedDomain, Blocklist};
+pub use dns::{DnsSinkhole, DnsSinkholeConfig, DnsSinkholeStatus};
+pub use dns_flush::FlushReport;
+pub use hosts::{BlockVerification, HostsBlocker, HostsRecovery, SinkholeAddresses};
 pub use process::ProcessWatcher;
+pub use process_rules::{ProcessAction, ProcessRule, ProcessRuleStore, RuleKind, RuleSource};
+pub use rules::RuleMatcher;
+pub use suspend::SuspendedProcess;
+pub use tamper::{HostsWatcher, TamperEvent};
+pub use terminate::{KillEvent, KillPolicy, KillStep};
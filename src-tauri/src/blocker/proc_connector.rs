@@ -0,0 +1,316 @@
+use super::process::{refresh_kind, PollingBackend, ProcessBackend, WatcherShared};
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+// From linux/connector.h and linux/cn_proc.h
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_CN_MCAST_IGNORE: u32 = 2;
+const PROC_EVENT_EXEC: u32 = 2;
+
+const NLMSG_HDR_LEN: usize = 16;
+const CN_MSG_LEN: usize = 20;
+/// `what`, `cpu` and the 8-byte aligned timestamp precede the event data
+const PROC_EVENT_DATA_OFFSET: usize = 16;
+
+/// Receive timeout, so the loop notices `running` being cleared.
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+/// Full scans catch processes that were running before the rules changed
+/// and anything missed when the socket buffer overflowed.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Subscribes to exec events through the netlink process connector so a
+/// blocked program is killed as soon as it starts, without polling the
+/// process table. Subscribing needs CAP_NET_ADMIN; `open` fails without it
+/// and the watcher falls back to polling.
+pub struct ProcConnectorBackend {
+    socket: OwnedFd,
+}
+
+impl ProcConnectorBackend {
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: plain socket(2) call; the descriptor is owned right away
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a freshly created descriptor nobody else owns
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain data, all-zero is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = CN_IDX_PROC;
+        // SAFETY: `addr` is a valid sockaddr_nl of the given length
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let timeout = libc::timeval {
+            tv_sec: RECV_TIMEOUT.as_secs() as libc::time_t,
+            tv_usec: 0,
+        };
+        // SAFETY: `timeout` is a valid timeval of the given length
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let backend = Self { socket };
+        backend.send_op(PROC_CN_MCAST_LISTEN)?;
+        Ok(backend)
+    }
+
+    fn send_op(&self, op: u32) -> io::Result<()> {
+        let message = control_message(op);
+        // SAFETY: `message` is a valid buffer of the given length
+        let sent = unsafe {
+            libc::send(
+                self.socket.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: `buf` is a valid writable buffer of the given length
+        let received = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(received as usize)
+    }
+}
+
+impl ProcessBackend for ProcConnectorBackend {
+    fn name(&self) -> &'static str {
+        "proc connector"
+    }
+
+    fn run(self: Box<Self>, shared: WatcherShared) {
+        let mut sys = System::new();
+        let mut enforcer = shared.enforcer();
+        // The exe of an exec'd PID changes, so event refreshes always re-read it
+        let exec_refresh_kind = ProcessRefreshKind::new()
+            .with_exe(UpdateKind::Always)
+            .with_cmd(UpdateKind::Always);
+        let mut buf = vec![0u8; 8192];
+        let mut next_sweep = Instant::now();
+
+        while shared.running.load(Ordering::Relaxed) {
+            if Instant::now() >= next_sweep {
+                sys.refresh_processes_specifics(ProcessesToUpdate::All, refresh_kind());
+                enforcer.check_all(&sys, &shared.matcher.read().unwrap());
+                next_sweep = Instant::now() + SWEEP_INTERVAL;
+            }
+            // Runs at least once per receive timeout
+            enforcer.escalate(&mut sys);
+
+            let len = match self.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    log::warn!("Proc connector dropped events, rescanning");
+                    next_sweep = Instant::now();
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("Proc connector failed, falling back to polling: {}", e);
+                    drop(self);
+                    Box::new(PollingBackend).run(shared);
+                    return;
+                }
+            };
+
+            let pids: Vec<Pid> = parse_exec_events(&buf[..len])
+                .into_iter()
+                .map(Pid::from_u32)
+                .collect();
+            if pids.is_empty() {
+                continue;
+            }
+
+            sys.refresh_processes_specifics(ProcessesToUpdate::Some(&pids), exec_refresh_kind);
+            let matcher = shared.matcher.read().unwrap();
+            let mut matched = false;
+            for pid in &pids {
+                if let Some(process) = sys.process(*pid) {
+                    matched |= enforcer.check(&sys, process, &matcher);
+                }
+            }
+            // Only a full scan sees the children of a matched launcher
+            if matched {
+                next_sweep = Instant::now();
+            }
+        }
+    }
+}
+
+impl Drop for ProcConnectorBackend {
+    fn drop(&mut self) {
+        // The kernel counts listeners and only builds events while any remain
+        let _ = self.send_op(PROC_CN_MCAST_IGNORE);
+    }
+}
+
+/// A netlink message carrying a proc connector control op.
+fn control_message(op: u32) -> Vec<u8> {
+    let payload_len = mem::size_of::<u32>();
+    let total_len = NLMSG_HDR_LEN + CN_MSG_LEN + payload_len;
+
+    let mut message = Vec::with_capacity(total_len);
+    // nlmsghdr
+    message.extend_from_slice(&(total_len as u32).to_ne_bytes());
+    message.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+    message.extend_from_slice(&0u16.to_ne_bytes()); // flags
+    message.extend_from_slice(&0u32.to_ne_bytes()); // seq
+    message.extend_from_slice(&std::process::id().to_ne_bytes());
+    // cn_msg
+    message.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    message.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes()); // seq
+    message.extend_from_slice(&0u32.to_ne_bytes()); // ack
+    message.extend_from_slice(&(payload_len as u16).to_ne_bytes());
+    message.extend_from_slice(&0u16.to_ne_bytes()); // flags
+    message.extend_from_slice(&op.to_ne_bytes()); // enum proc_cn_mcast_op
+    message
+}
+
+/// Thread group IDs of the processes that called exec, from one datagram.
+fn parse_exec_events(buf: &[u8]) -> Vec<u32> {
+    let read_u32 = |offset: usize| -> Option<u32> {
+        buf.get(offset..offset + 4)
+            .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+    };
+
+    let mut tgids = Vec::new();
+    let mut offset = 0;
+    while offset + NLMSG_HDR_LEN <= buf.len() {
+        let Some(msg_len) = read_u32(offset).map(|len| len as usize) else {
+            break;
+        };
+        if msg_len < NLMSG_HDR_LEN || offset + msg_len > buf.len() {
+            break;
+        }
+
+        let cn = offset + NLMSG_HDR_LEN;
+        let event = cn + CN_MSG_LEN;
+        if read_u32(cn) == Some(CN_IDX_PROC) && read_u32(event) == Some(PROC_EVENT_EXEC) {
+            // exec_proc_event { pid, tgid }
+            if let Some(tgid) = read_u32(event + PROC_EVENT_DATA_OFFSET + 4) {
+                tgids.push(tgid);
+            }
+        }
+
+        // Messages are padded to 4 bytes
+        offset += (msg_len + 3) & !3;
+    }
+    tgids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // Captured on x86_64 while `/bin/true` was spawned: the listen ack, the
+    // fork of pid 26779 and its exec
+    const ACK: &str = "4c00000003000000a1d00000000000000100000001000000a1d0000001000000\
+                       2800000000000000000000007bf1d73843070000000000000000000000000000\
+                       000000000000000000000000";
+    const FORK: &str = "4c00000003000000a2d00000000000000100000001000000a2d0000000000000\
+                        28000000010000000000000008c3d9384307000072680000726800009b680000\
+                        9b6800000000000000000000";
+    const EXEC: &str = "4c00000003000000a3d00000000000000100000001000000a3d0000000000000\
+                        2800000002000000000000008fd1df38430700009b6800009b68000000000000\
+                        000000000000000000000000";
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn exec_events_yield_their_tgid() {
+        assert_eq!(parse_exec_events(&hex(EXEC)), [26779]);
+        assert!(parse_exec_events(&hex(ACK)).is_empty());
+        assert!(parse_exec_events(&hex(FORK)).is_empty());
+
+        // Several messages in one datagram
+        let batch = hex(&format!("{}{}{}", ACK, FORK, EXEC));
+        assert_eq!(parse_exec_events(&batch), [26779]);
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn truncated_messages_are_ignored() {
+        let exec = hex(EXEC);
+        assert!(parse_exec_events(&exec[..exec.len() - 8]).is_empty());
+        assert!(parse_exec_events(&exec[..10]).is_empty());
+
+        // A length field shorter than the header itself
+        let mut bogus = exec.clone();
+        bogus[..4].copy_from_slice(&4u32.to_ne_bytes());
+        assert!(parse_exec_events(&bogus).is_empty());
+    }
+
+    #[test]
+    fn control_message_carries_the_op() {
+        let message = control_message(PROC_CN_MCAST_LISTEN);
+
+        assert_eq!(message.len(), NLMSG_HDR_LEN + CN_MSG_LEN + 4);
+        assert_eq!(&message[..4], &(message.len() as u32).to_ne_bytes());
+        assert_eq!(&message[16..20], &CN_IDX_PROC.to_ne_bytes());
+        assert_eq!(&message[36..], &PROC_CN_MCAST_LISTEN.to_ne_bytes());
+    }
+}
@@ -1,88 +1,205 @@
+use super::process_rules::{ProcessMatcher, ProcessRule};
+use super::suspend::{SuspendStore, SuspendedProcess};
+use super::terminate::{self, Enforcer, KillEventSink, KillPolicy};
+use crate::db::DbError;
+use rusqlite::Result as SqliteResult;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
-use sysinfo::System;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+/// State a running watcher shares with its backend thread.
+#[derive(Clone)]
+pub struct WatcherShared {
+    pub running: Arc<AtomicBool>,
+    pub matcher: Arc<RwLock<ProcessMatcher>>,
+    pub policy: Arc<RwLock<KillPolicy>>,
+    pub sink: Arc<RwLock<Option<KillEventSink>>>,
+    pub suspended: Arc<SuspendStore>,
+}
 
-pub struct ProcessWatcher {
-    running: Arc<AtomicBool>,
-    targets: Vec<String>,
+impl WatcherShared {
+    pub fn enforcer(&self) -> Enforcer {
+        Enforcer::new(
+            self.policy.clone(),
+            self.sink.clone(),
+            self.suspended.clone(),
+        )
+    }
 }
 
-impl ProcessWatcher {
-    pub fn new() -> Self {
-        Self {
-            running: Arc::new(AtomicBool::new(false)),
-            targets: Self::get_target_processes(),
+/// How the watcher learns about new processes. Backends run on the watcher
+/// thread until `running` is cleared and read the current rules and policy
+/// on every check, so changes need no restart.
+pub trait ProcessBackend: Send {
+    fn name(&self) -> &'static str;
+
+    fn run(self: Box<Self>, shared: WatcherShared);
+}
+
+/// Refreshes the whole process table every 2 seconds. Works everywhere
+/// without privileges, so it is the default and the fallback.
+pub struct PollingBackend;
+
+impl ProcessBackend for PollingBackend {
+    fn name(&self) -> &'static str {
+        "polling"
+    }
+
+    fn run(self: Box<Self>, shared: WatcherShared) {
+        let mut sys = System::new();
+        let mut enforcer = shared.enforcer();
+
+        while shared.running.load(Ordering::Relaxed) {
+            sys.refresh_processes_specifics(ProcessesToUpdate::All, refresh_kind());
+            enforcer.check_all(&sys, &shared.matcher.read().unwrap());
+            enforcer.escalate(&mut sys);
+
+            // In short steps, so `stop` doesn't wait out the whole interval
+            for _ in 0..20 {
+                if !shared.running.load(Ordering::Relaxed) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
         }
     }
+}
+
+pub struct ProcessWatcher {
+    shared: WatcherShared,
+    rules: Mutex<Vec<ProcessRule>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
 
-    fn get_target_processes() -> Vec<String> {
-        vec![
-            // League of Legends - Windows
-            "LeagueClient.exe".to_string(),
-            "LeagueClientUx.exe".to_string(),
-            "League of Legends.exe".to_string(),
-            "RiotClientServices.exe".to_string(),
-            "RiotClientUx.exe".to_string(),
-            "RiotClientCrashHandler.exe".to_string(),
-            // League of Legends - macOS
-            "LeagueClient".to_string(),
-            "League of Legends".to_string(),
-            "RiotClient".to_string(),
-            // Chzzk related
-            "NaverGameLauncher.exe".to_string(),
-            "CHZZK.exe".to_string(),
-        ]
+impl ProcessWatcher {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Ok(Self {
+            shared: WatcherShared {
+                running: Arc::new(AtomicBool::new(false)),
+                matcher: Arc::new(RwLock::new(ProcessMatcher::default())),
+                policy: Arc::new(RwLock::new(KillPolicy::default())),
+                sink: Arc::new(RwLock::new(None)),
+                suspended: Arc::new(SuspendStore::new(app_data_dir)?),
+            },
+            rules: Mutex::new(Vec::new()),
+            thread: Mutex::new(None),
+        })
     }
 
-    pub fn start(&self) {
-        if self.running.load(Ordering::Relaxed) {
-            return; // Already running
+    /// Swaps the rule set; a running watcher picks it up on its next check.
+    /// Returns whether the rules differ from the previous set.
+    pub fn set_rules(&self, rules: &[ProcessRule]) -> bool {
+        let mut current = self.rules.lock().unwrap();
+        if *current == rules {
+            return false;
         }
+        *self.shared.matcher.write().unwrap() = ProcessMatcher::new(rules);
+        *current = rules.to_vec();
+        true
+    }
 
-        self.running.store(true, Ordering::Relaxed);
-        let running = self.running.clone();
-        let targets = self.targets.clone();
-
-        thread::spawn(move || {
-            let mut sys = System::new();
+    pub fn policy(&self) -> KillPolicy {
+        *self.shared.policy.read().unwrap()
+    }
 
-            while running.load(Ordering::Relaxed) {
-                sys.refresh_processes(sysinfo::ProcessesToUpdate::All);
+    /// Applies to processes matched from now on; the grace period is capped
+    /// at `MAX_GRACE_PERIOD_SECS`.
+    pub fn set_policy(&self, policy: KillPolicy) {
+        *self.shared.policy.write().unwrap() = policy.clamped();
+    }
 
-                for (pid, process) in sys.processes() {
-                    let name = process.name().to_string_lossy().to_string();
+    /// Receives every terminate/kill/exit step.
+    pub fn set_event_sink(&self, sink: KillEventSink) {
+        *self.shared.sink.write().unwrap() = Some(sink);
+    }
 
-                    for target in &targets {
-                        if name.eq_ignore_ascii_case(target) {
-                            log::info!("Killing blocked process: {} (PID: {})", name, pid);
-                            process.kill();
-                        }
-                    }
-                }
+    pub fn start(&self) {
+        if self.shared.running.load(Ordering::Relaxed) {
+            return; // Already running
+        }
 
-                thread::sleep(Duration::from_secs(2));
-            }
+        self.shared.running.store(true, Ordering::Relaxed);
+        let shared = self.shared.clone();
 
+        let backend = select_backend();
+        let name = backend.name();
+        let handle = thread::spawn(move || {
+            backend.run(shared);
             log::info!("Process watcher stopped");
         });
+        *self.thread.lock().unwrap() = Some(handle);
 
-        log::info!("Process watcher started");
+        log::info!("Process watcher started ({})", name);
     }
 
+    /// Executable paths of the running processes named `name`, used to learn
+    /// hash rules from an app the user has open.
+    pub fn find_executables(name: &str) -> Vec<PathBuf> {
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(
+            ProcessesToUpdate::All,
+            ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet),
+        );
+
+        let mut paths: Vec<PathBuf> = sys
+            .processes()
+            .values()
+            .filter(|process| process.name().to_string_lossy().eq_ignore_ascii_case(name))
+            .filter_map(|process| process.exe().map(|exe| exe.to_path_buf()))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Names of the running processes, used to count budgeted app time.
+    pub fn running_names() -> Vec<String> {
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::All, ProcessRefreshKind::new());
+
+        let mut names: Vec<String> = sys
+            .processes()
+            .values()
+            .map(|process| process.name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Waits for the watcher thread to finish its current pass, so nothing
+    /// is killed or suspended after this returns.
     pub fn stop(&self) {
-        self.running.store(false, Ordering::Relaxed);
+        self.shared.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.thread.lock().unwrap().take() {
+            if handle.join().is_err() {
+                log::error!("Process watcher thread panicked");
+            }
+        }
     }
 
     pub fn is_running(&self) -> bool {
-        self.running.load(Ordering::Relaxed)
+        self.shared.running.load(Ordering::Relaxed)
+    }
+
+    pub fn suspended_processes(&self) -> SqliteResult<Vec<SuspendedProcess>> {
+        self.shared.suspended.list()
     }
-}
 
-impl Default for ProcessWatcher {
-    fn default() -> Self {
-        Self::new()
+    /// Resumes everything suspended during the lock. Call after `stop`, when
+    /// the lock has ended or was cleared, and at startup when no lock is
+    /// active in case the app died mid-lock. A watcher still running would
+    /// only suspend them again.
+    pub fn resume_suspended(&self) -> SqliteResult<Vec<SuspendedProcess>> {
+        let resumed = self.shared.suspended.resume_all()?;
+        let sink = self.shared.sink.read().unwrap();
+        for process in &resumed {
+            terminate::report_resumed(&sink, process);
+        }
+        Ok(resumed)
     }
 }
 
@@ -91,3 +208,26 @@ impl Drop for ProcessWatcher {
         self.stop();
     }
 }
+
+/// Exec events on Linux when we may subscribe to them, polling otherwise.
+#[cfg(target_os = "linux")]
+fn select_backend() -> Box<dyn ProcessBackend> {
+    match super::proc_connector::ProcConnectorBackend::open() {
+        Ok(backend) => Box::new(backend),
+        Err(e) => {
+            log::info!("Proc connector unavailable, polling instead: {}", e);
+            Box::new(PollingBackend)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn select_backend() -> Box<dyn ProcessBackend> {
+    Box::new(PollingBackend)
+}
+
+pub(super) fn refresh_kind() -> ProcessRefreshKind {
+    ProcessRefreshKind::new()
+        .with_exe(UpdateKind::OnlyIfNotSet)
+        .with_cmd(UpdateKind::OnlyIfNotSet)
+}
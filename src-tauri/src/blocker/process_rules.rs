@@ -0,0 +1,373 @@
+use super::exe_hash::is_sha256_hex;
+use crate::db::{self, DbError};
+use regex::{Regex, RegexBuilder};
+use rusqlite::{params, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProcessRuleError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+    #[error("Process rules cannot be removed while a lock is active")]
+    Locked,
+}
+
+/// What a rule's pattern is compared against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    /// Executable name, e.g. `LeagueClient.exe`
+    Name,
+    /// Full executable path
+    Path,
+    /// `*`/`?` wildcard matched against the name and the full path
+    Glob,
+    /// Regular expression matched against the name, path and command line
+    Regex,
+    /// Substring of the joined command line
+    CommandLine,
+    /// SHA-256 of the executable file, which survives renaming the binary
+    Sha256,
+}
+
+impl RuleKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            RuleKind::Name => "name",
+            RuleKind::Path => "path",
+            RuleKind::Glob => "glob",
+            RuleKind::Regex => "regex",
+            RuleKind::CommandLine => "command_line",
+            RuleKind::Sha256 => "sha256",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(RuleKind::Name),
+            "path" => Some(RuleKind::Path),
+            "glob" => Some(RuleKind::Glob),
+            "regex" => Some(RuleKind::Regex),
+            "command_line" => Some(RuleKind::CommandLine),
+            "sha256" => Some(RuleKind::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a process a rule matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessAction {
+    /// Terminate, then kill after the grace period
+    #[default]
+    Kill,
+    /// Freeze for the rest of the lock and resume afterwards, so apps like
+    /// browsers keep their state
+    Suspend,
+}
+
+impl ProcessAction {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ProcessAction::Kill => "kill",
+            ProcessAction::Suspend => "suspend",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "kill" => Some(ProcessAction::Kill),
+            "suspend" => Some(ProcessAction::Suspend),
+            _ => None,
+        }
+    }
+}
+
+/// Where an enforced rule comes from, so kill events can point back to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleSource {
+    /// A stored rule or one of the running profile's; `id` is the rule's
+    #[default]
+    Rule,
+    /// A program of a used-up budget; `id` is the budget's
+    Budget,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessRule {
+    pub id: i64,
+    pub kind: RuleKind,
+    pub pattern: String,
+    #[serde(default)]
+    pub action: ProcessAction,
+    #[serde(default)]
+    pub source: RuleSource,
+}
+
+/// The parts of a running process the rules look at.
+pub struct ProcessInfo<'a> {
+    pub name: &'a str,
+    pub exe: Option<&'a Path>,
+    pub cmdline: &'a str,
+    /// Only computed when the matcher has hash rules
+    pub sha256: Option<&'a str>,
+}
+
+enum CompiledRule {
+    Name(String),
+    Path(String),
+    Glob(String),
+    Regex(Regex),
+    CommandLine(String),
+    Sha256(String),
+}
+
+/// Rules compiled once per reload so the watcher loop only does comparisons.
+/// All comparisons are case-insensitive.
+#[derive(Default)]
+pub struct ProcessMatcher {
+    rules: Vec<(ProcessRule, CompiledRule)>,
+}
+
+impl ProcessMatcher {
+    /// Rules that fail to compile are skipped; they were validated when added.
+    pub fn new(rules: &[ProcessRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match compile(rule.kind, &rule.pattern) {
+                Ok(compiled) => Some((rule.clone(), compiled)),
+                Err(e) => {
+                    log::warn!("Skipping process rule {}: {}", rule.id, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    pub fn matches(&self, process: &ProcessInfo) -> Option<&ProcessRule> {
+        let name = process.name.to_lowercase();
+        let path = process
+            .exe
+            .map(|exe| exe.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let cmdline = process.cmdline.to_lowercase();
+
+        self.rules
+            .iter()
+            .find(|(_, compiled)| match compiled {
+                CompiledRule::Name(target) => name == *target,
+                CompiledRule::Path(target) => !path.is_empty() && path == *target,
+                CompiledRule::Glob(pattern) => {
+                    glob_match(pattern, &name) || (!path.is_empty() && glob_match(pattern, &path))
+                }
+                CompiledRule::Regex(regex) => {
+                    regex.is_match(process.name)
+                        || (!path.is_empty() && regex.is_match(&path))
+                        || (!cmdline.is_empty() && regex.is_match(process.cmdline))
+                }
+                CompiledRule::CommandLine(needle) => cmdline.contains(needle.as_str()),
+                CompiledRule::Sha256(hash) => process.sha256 == Some(hash.as_str()),
+            })
+            .map(|(rule, _)| rule)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether callers need to hash executables before matching.
+    pub fn needs_hashes(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|(_, compiled)| matches!(compiled, CompiledRule::Sha256(_)))
+    }
+}
+
+/// Rejects patterns the watcher could not use and returns the pattern as
+/// stored.
+pub(crate) fn normalize_rule(kind: RuleKind, pattern: &str) -> Result<String, ProcessRuleError> {
+    compile(kind, pattern)?;
+    Ok(match kind {
+        RuleKind::Sha256 => pattern.trim().to_ascii_lowercase(),
+        _ => pattern.trim().to_string(),
+    })
+}
+
+fn compile(kind: RuleKind, pattern: &str) -> Result<CompiledRule, ProcessRuleError> {
+    let pattern = pattern.trim();
+    if pattern.is_empty() {
+        return Err(ProcessRuleError::InvalidPattern(
+            "empty pattern".to_string(),
+        ));
+    }
+
+    let lowered = pattern.to_lowercase();
+    Ok(match kind {
+        RuleKind::Name => CompiledRule::Name(lowered),
+        RuleKind::Path => CompiledRule::Path(lowered),
+        RuleKind::Glob => CompiledRule::Glob(lowered),
+        RuleKind::Regex => CompiledRule::Regex(
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| ProcessRuleError::InvalidPattern(e.to_string()))?,
+        ),
+        RuleKind::CommandLine => CompiledRule::CommandLine(lowered),
+        RuleKind::Sha256 if is_sha256_hex(pattern) => CompiledRule::Sha256(lowered),
+        RuleKind::Sha256 => {
+            return Err(ProcessRuleError::InvalidPattern(
+                "expected a 64-character hex SHA-256".to_string(),
+            ))
+        }
+    })
+}
+
+/// Matches `*` (any run of characters) and `?` (exactly one character).
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub struct ProcessRuleStore {
+    db: Mutex<Connection>,
+}
+
+impl ProcessRuleStore {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        let conn = db::open(&app_data_dir)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    pub fn rules(&self) -> SqliteResult<Vec<ProcessRule>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt =
+            db.prepare("SELECT id, kind, pattern, action FROM process_rules ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+
+        let mut rules = Vec::new();
+        for row in rows {
+            let (id, kind, pattern, action): (i64, String, String, String) = row?;
+            match (RuleKind::parse(&kind), ProcessAction::parse(&action)) {
+                (Some(kind), Some(action)) => rules.push(ProcessRule {
+                    id,
+                    kind,
+                    pattern,
+                    action,
+                    source: RuleSource::Rule,
+                }),
+                _ => log::warn!(
+                    "Ignoring process rule {} with unknown kind {} or action {}",
+                    id,
+                    kind,
+                    action
+                ),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Adds a rule, or changes the action of an existing identical one.
+    pub fn add_rule(
+        &self,
+        kind: RuleKind,
+        pattern: &str,
+        action: ProcessAction,
+    ) -> Result<ProcessRule, ProcessRuleError> {
+        let pattern = normalize_rule(kind, pattern)?;
+
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "INSERT INTO process_rules (kind, pattern, action) VALUES (?1, ?2, ?3)
+             ON CONFLICT(kind, pattern) DO UPDATE SET action = excluded.action",
+            params![kind.as_str(), pattern, action.as_str()],
+        )?;
+        let id = db.query_row(
+            "SELECT id FROM process_rules WHERE kind = ?1 AND pattern = ?2",
+            params![kind.as_str(), pattern],
+            |row| row.get(0),
+        )?;
+
+        log::info!(
+            "Added {} process rule ({}): {}",
+            kind.as_str(),
+            action.as_str(),
+            pattern
+        );
+        Ok(ProcessRule {
+            id,
+            kind,
+            pattern,
+            action,
+            source: RuleSource::Rule,
+        })
+    }
+
+    pub fn remove_rule(&self, id: i64, locked: bool) -> Result<(), ProcessRuleError> {
+        if locked {
+            return Err(ProcessRuleError::Locked);
+        }
+
+        let db = self.db.lock().unwrap();
+        db.execute("DELETE FROM process_rules WHERE id = ?1", [id])?;
+
+        log::info!("Removed process rule {}", id);
+        Ok(())
+    }
+}
+
+/// Built-in targets that seed a fresh database as name rules.
+pub fn default_target_processes() -> Vec<&'static str> {
+    vec![
+        // League of Legends - Windows
+        "LeagueClient.exe",
+        "LeagueClientUx.exe",
+        "League of Legends.exe",
+        "RiotClientServices.exe",
+        "RiotClientUx.exe",
+        "RiotClientCrashHandler.exe",
+        // League of Legends - macOS
+        "LeagueClient",
+        "League of Legends",
+        "RiotClient",
+        // Chzzk related
+        "NaverGameLauncher.exe",
+        "CHZZK.exe",
+    ]
+}
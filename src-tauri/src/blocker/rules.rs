@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+
+/// A single blocklist entry. `*.example.com` blocks `example.com` and every
+/// name below it; anything else must match exactly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DomainRule {
+    Exact(String),
+    Suffix(String),
+}
+
+impl DomainRule {
+    pub fn parse(rule: &str) -> Self {
+        let rule = rule.trim().trim_end_matches('.').to_ascii_lowercase();
+        match rule.strip_prefix("*.") {
+            Some(base) => DomainRule::Suffix(base.to_string()),
+            None => DomainRule::Exact(rule),
+        }
+    }
+}
+
+/// Shared matcher used by both the hosts file writer and the DNS sinkhole.
+#[derive(Debug, Clone, Default)]
+pub struct RuleMatcher {
+    exact: HashSet<String>,
+    suffixes: HashSet<String>,
+}
+
+impl RuleMatcher {
+    pub fn new<S: AsRef<str>>(rules: &[S]) -> Self {
+        let mut matcher = Self::default();
+        for rule in rules {
+            match DomainRule::parse(rule.as_ref()) {
+                DomainRule::Exact(name) => matcher.exact.insert(name),
+                DomainRule::Suffix(base) => matcher.suffixes.insert(base),
+            };
+        }
+        matcher
+    }
+
+    pub fn is_blocked(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if self.exact.contains(&name) {
+            return true;
+        }
+
+        // Walk up the labels: a.b.example.com, b.example.com, example.com, com
+        let mut candidate = name.as_str();
+        loop {
+            if self.suffixes.contains(candidate) {
+                return true;
+            }
+            match candidate.find('.') {
+                Some(idx) => candidate = &candidate[idx + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    /// Concrete names to write to the hosts file, which cannot express
+    /// wildcards. Suffix rules fall back to the bare domain and its `www.`.
+    pub fn host_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.exact.iter().cloned().collect();
+        for base in &self.suffixes {
+            names.push(base.clone());
+            names.push(format!("www.{}", base));
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.suffixes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_rules_match_only_that_name() {
+        let matcher = RuleMatcher::new(&["YouTube.com."]);
+
+        assert!(matcher.is_blocked("youtube.com"));
+        assert!(matcher.is_blocked("YOUTUBE.COM."));
+        assert!(!matcher.is_blocked("www.youtube.com"));
+        assert!(!matcher.is_blocked("youtube.co"));
+    }
+
+    #[test]
+    fn suffix_rules_match_the_domain_and_everything_below() {
+        let matcher = RuleMatcher::new(&["*.googlevideo.com"]);
+
+        assert!(matcher.is_blocked("googlevideo.com"));
+        assert!(matcher.is_blocked("rr3---sn-abc.googlevideo.com"));
+        assert!(matcher.is_blocked("a.b.googlevideo.com."));
+        // Whole labels only
+        assert!(!matcher.is_blocked("notgooglevideo.com"));
+        assert!(!matcher.is_blocked("com"));
+    }
+
+    #[test]
+    fn hosts_names_stand_in_for_suffix_rules() {
+        let matcher = RuleMatcher::new(&["*.reddit.com", "x.com", "x.com"]);
+
+        assert_eq!(
+            matcher.host_names(),
+            ["reddit.com", "www.reddit.com", "x.com"]
+        );
+        assert!(!matcher.is_empty());
+        assert!(RuleMatcher::new::<&str>(&[]).is_empty());
+    }
+}
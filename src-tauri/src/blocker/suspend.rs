@@ -0,0 +1,185 @@
+use crate::db::{self, DbError};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspendedProcess {
+    pub pid: u32,
+    /// Tells the original process apart from a later one reusing the PID
+    pub start_time: u64,
+    pub name: String,
+    pub rule_id: i64,
+    pub root_pid: u32,
+    pub suspended_at: DateTime<Utc>,
+}
+
+/// Processes the watcher has frozen. Kept in the database so a restart
+/// (or a crash) during a lock doesn't leave them suspended forever.
+pub struct SuspendStore {
+    db: Mutex<Connection>,
+}
+
+impl SuspendStore {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        let conn = db::open(&app_data_dir)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    pub fn record(&self, process: &SuspendedProcess) -> SqliteResult<()> {
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "INSERT OR REPLACE INTO suspended_processes
+                (pid, start_time, name, rule_id, root_pid, suspended_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                process.pid,
+                process.start_time as i64,
+                process.name,
+                process.rule_id,
+                process.root_pid,
+                process.suspended_at.to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove(&self, pid: u32) -> SqliteResult<()> {
+        let db = self.db.lock().unwrap();
+        db.execute("DELETE FROM suspended_processes WHERE pid = ?1", [pid])?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> SqliteResult<Vec<SuspendedProcess>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT pid, start_time, name, rule_id, root_pid, suspended_at
+             FROM suspended_processes ORDER BY suspended_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let start_time: i64 = row.get(1)?;
+            let suspended_at: String = row.get(5)?;
+            Ok(SuspendedProcess {
+                pid: row.get(0)?,
+                start_time: start_time as u64,
+                name: row.get(2)?,
+                rule_id: row.get(3)?,
+                root_pid: row.get(4)?,
+                suspended_at: DateTime::parse_from_rfc3339(&suspended_at)
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_default(),
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    /// Resumes every recorded process that is still the one we suspended
+    /// and forgets them all. Returns the ones that were resumed.
+    pub fn resume_all(&self) -> SqliteResult<Vec<SuspendedProcess>> {
+        let recorded = self.list()?;
+        if recorded.is_empty() {
+            return Ok(recorded);
+        }
+
+        let pids: Vec<Pid> = recorded.iter().map(|p| Pid::from_u32(p.pid)).collect();
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(ProcessesToUpdate::Some(&pids), ProcessRefreshKind::new());
+
+        let mut resumed = Vec::new();
+        for suspended in recorded {
+            match sys.process(Pid::from_u32(suspended.pid)) {
+                Some(process) if process.start_time() == suspended.start_time => {
+                    if resume(process) {
+                        log::info!("Resumed {} (PID {})", suspended.name, suspended.pid);
+                        resumed.push(suspended);
+                    } else {
+                        log::warn!(
+                            "Failed to resume {} (PID {})",
+                            suspended.name,
+                            suspended.pid
+                        );
+                    }
+                }
+                _ => log::info!("Suspended process {} is gone", suspended.pid),
+            }
+        }
+
+        self.db
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM suspended_processes", [])?;
+        Ok(resumed)
+    }
+}
+
+#[cfg(unix)]
+pub fn suspend(process: &Process) -> bool {
+    process.kill_with(sysinfo::Signal::Stop) == Some(true)
+}
+
+#[cfg(unix)]
+pub fn resume(process: &Process) -> bool {
+    process.kill_with(sysinfo::Signal::Continue) == Some(true)
+}
+
+#[cfg(windows)]
+pub fn suspend(process: &Process) -> bool {
+    windows::with_process_handle(process, |handle| unsafe {
+        windows::NtSuspendProcess(handle)
+    })
+}
+
+#[cfg(windows)]
+pub fn resume(process: &Process) -> bool {
+    windows::with_process_handle(process, |handle| unsafe {
+        windows::NtResumeProcess(handle)
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn suspend(_process: &Process) -> bool {
+    false
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn resume(_process: &Process) -> bool {
+    false
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::Process;
+    use winapi::shared::minwindef::FALSE;
+    use winapi::shared::ntdef::{HANDLE, NTSTATUS};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcess;
+    use winapi::um::winnt::PROCESS_SUSPEND_RESUME;
+
+    // Undocumented but stable since XP; what Process Explorer uses
+    #[link(name = "ntdll")]
+    extern "system" {
+        pub fn NtSuspendProcess(handle: HANDLE) -> NTSTATUS;
+        pub fn NtResumeProcess(handle: HANDLE) -> NTSTATUS;
+    }
+
+    pub fn with_process_handle(process: &Process, call: impl FnOnce(HANDLE) -> NTSTATUS) -> bool {
+        // SAFETY: the handle is checked for null and closed after the call
+        unsafe {
+            let handle = OpenProcess(PROCESS_SUSPEND_RESUME, FALSE, process.pid().as_u32());
+            if handle.is_null() {
+                return false;
+            }
+            let status = call(handle);
+            CloseHandle(handle);
+            status >= 0
+        }
+    }
+}
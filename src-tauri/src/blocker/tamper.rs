@@ -0,0 +1,160 @@
+use super::hosts::HostsBlocker;
+use chrono::{DateTime, Utc};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the hosts file is checked when no change notification arrives.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Lets a burst of writes from an editor settle before checking.
+const SETTLE_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TamperEvent {
+    pub detected_at: DateTime<Utc>,
+    pub kind: String,
+    pub restored: bool,
+}
+
+pub type TamperEventSink = Arc<dyn Fn(&TamperEvent) + Send + Sync>;
+
+/// Re-applies the hosts block whenever our marked section is removed or
+/// edited while a lock is running. Tampering is reported to the event sink,
+/// which records it.
+pub struct HostsWatcher {
+    running: Arc<AtomicBool>,
+    domains: Arc<RwLock<Vec<String>>>,
+    sink: Arc<RwLock<Option<TamperEventSink>>>,
+    /// Wakes the thread from its wait so `stop` needn't wait a poll out
+    wake: Mutex<Option<Sender<()>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HostsWatcher {
+    pub fn new() -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(false)),
+            domains: Arc::new(RwLock::new(Vec::new())),
+            sink: Arc::new(RwLock::new(None)),
+            wake: Mutex::new(None),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Receives every detected tamper.
+    pub fn set_event_sink(&self, sink: TamperEventSink) {
+        *self.sink.write().unwrap() = Some(sink);
+    }
+
+    pub fn set_domains(&self, domains: &[String]) {
+        *self.domains.write().unwrap() = domains.to_vec();
+    }
+
+    pub fn start(&self, hosts: HostsBlocker) {
+        if self.running.load(Ordering::Relaxed) {
+            return; // Already running
+        }
+
+        self.running.store(true, Ordering::Relaxed);
+        let running = self.running.clone();
+        let domains = self.domains.clone();
+        let sink = self.sink.clone();
+        let (tx, rx) = mpsc::channel();
+        *self.wake.lock().unwrap() = Some(tx.clone());
+
+        let handle = thread::spawn(move || {
+            // Watch the directory: atomic replaces swap the file's inode
+            let watcher = hosts.hosts_path().parent().and_then(|dir| {
+                let mut watcher = notify::recommended_watcher(move |_| {
+                    let _ = tx.send(());
+                })
+                .ok()?;
+                watcher.watch(dir, RecursiveMode::NonRecursive).ok()?;
+                Some::<RecommendedWatcher>(watcher)
+            });
+            if watcher.is_none() {
+                log::warn!("Hosts file notifications unavailable, polling only");
+            }
+
+            while running.load(Ordering::Relaxed) {
+                if rx.recv_timeout(POLL_INTERVAL).is_ok() && running.load(Ordering::Relaxed) {
+                    thread::sleep(SETTLE_DELAY);
+                    // Coalesce the rest of the burst
+                    while rx.try_recv().is_ok() {}
+                }
+
+                if !running.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let domains = domains.read().unwrap().clone();
+                if domains.is_empty() || hosts.is_block_intact(&domains) {
+                    continue;
+                }
+
+                let kind = if hosts.is_blocked() {
+                    "modified"
+                } else {
+                    "removed"
+                };
+                log::warn!("Hosts file block was {}, restoring", kind);
+
+                let restored = match hosts.block(&domains) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::error!("Failed to restore hosts block: {}", e);
+                        false
+                    }
+                };
+
+                let event = TamperEvent {
+                    detected_at: Utc::now(),
+                    kind: kind.to_string(),
+                    restored,
+                };
+                if let Some(sink) = sink.read().unwrap().as_ref() {
+                    sink(&event);
+                }
+            }
+
+            log::info!("Hosts watcher stopped");
+        });
+        *self.thread.lock().unwrap() = Some(handle);
+
+        log::info!("Hosts watcher started");
+    }
+
+    /// Stops watching and waits for the thread, so a `start` right after
+    /// never runs two watchers.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(wake) = self.wake.lock().unwrap().take() {
+            let _ = wake.send(());
+        }
+        if let Some(handle) = self.thread.lock().unwrap().take() {
+            if handle.join().is_err() {
+                log::error!("Hosts watcher thread panicked");
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for HostsWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for HostsWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,622 @@
+use super::exe_hash::HashCache;
+use super::process_rules::{ProcessAction, ProcessInfo, ProcessMatcher, RuleSource};
+use super::suspend::{self, SuspendStore, SuspendedProcess};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessStatus, ProcessesToUpdate, System};
+
+/// Upper bound on the grace period so it can't be used to keep a blocked
+/// program running indefinitely.
+pub const MAX_GRACE_PERIOD_SECS: u64 = 60;
+
+const KILL_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+/// Failed kills and suspends are retried at doubling intervals up to this
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How a matched process is stopped.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KillPolicy {
+    /// Time between the polite request and the hard kill
+    pub grace_period_secs: u64,
+    /// Also stop every descendant of a matched process, so a launcher
+    /// can't respawn the client it started
+    pub kill_tree: bool,
+}
+
+impl Default for KillPolicy {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: 5,
+            kill_tree: true,
+        }
+    }
+}
+
+impl KillPolicy {
+    pub fn clamped(self) -> Self {
+        Self {
+            grace_period_secs: self.grace_period_secs.min(MAX_GRACE_PERIOD_SECS),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KillStep {
+    /// A rule matched this process; the steps for it and its tree follow
+    Matched,
+    /// SIGTERM or WM_CLOSE was sent
+    TerminateRequested,
+    /// The polite request could not be delivered, so the kill came at once
+    TerminateUnsupported,
+    /// SIGKILL or TerminateProcess after the grace period
+    Killed,
+    /// The hard kill failed, typically for lack of permission
+    KillFailed,
+    /// SIGSTOP or NtSuspendProcess, for rules with the suspend action
+    Suspended,
+    SuspendFailed,
+    /// A suspended process was resumed after the lock
+    Resumed,
+    /// The process is gone
+    Exited,
+}
+
+/// One step of stopping a process, reported to the UI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillEvent {
+    pub pid: u32,
+    pub name: String,
+    pub path: Option<String>,
+    pub rule_id: i64,
+    pub source: RuleSource,
+    /// The matched process whose tree this one belongs to
+    pub root_pid: u32,
+    pub step: KillStep,
+    pub at: DateTime<Utc>,
+}
+
+pub type KillEventSink = Arc<dyn Fn(&KillEvent) + Send + Sync>;
+
+struct Pending {
+    name: String,
+    path: Option<String>,
+    rule_id: i64,
+    source: RuleSource,
+    root_pid: u32,
+    start_time: u64,
+    action: ProcessAction,
+    /// When to escalate, or to retry a kill or suspend that failed
+    deadline: Instant,
+    /// The process was killed or suspended; only its exit is left to see
+    done: bool,
+    /// Failed kills or suspends in a row
+    failures: u32,
+}
+
+/// The rule a process is stopped for, carried down to its children.
+#[derive(Clone, Copy)]
+struct Origin {
+    rule_id: i64,
+    source: RuleSource,
+    root_pid: u32,
+    action: ProcessAction,
+}
+
+impl Pending {
+    fn origin(&self) -> Origin {
+        Origin {
+            rule_id: self.rule_id,
+            source: self.source,
+            root_pid: self.root_pid,
+            action: self.action,
+        }
+    }
+
+    /// Notes the outcome of a kill or suspend and schedules the retry of a
+    /// failed one. Returns whether to report the step: a process that can't
+    /// be stopped is reported once, not on every retry.
+    fn attempted(&mut self, step: KillStep) -> bool {
+        let failed = matches!(step, KillStep::KillFailed | KillStep::SuspendFailed);
+        self.done = !failed;
+        if failed {
+            self.failures += 1;
+        }
+        self.deadline = Instant::now() + retry_delay(self.failures);
+        !failed || self.failures == 1
+    }
+}
+
+fn retry_delay(failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(6);
+    (KILL_RETRY_INTERVAL * 2u32.pow(doublings)).min(MAX_RETRY_INTERVAL)
+}
+
+/// Matches processes against the rules and walks each match through
+/// terminate, grace period, kill, or suspends it for suspend rules. Owned by
+/// the watcher thread; call `escalate` regularly so expired grace periods
+/// turn into kills.
+pub struct Enforcer {
+    policy: Arc<RwLock<KillPolicy>>,
+    sink: Arc<RwLock<Option<KillEventSink>>>,
+    suspended: Arc<SuspendStore>,
+    hashes: HashCache,
+    pending: HashMap<Pid, Pending>,
+}
+
+impl Enforcer {
+    pub fn new(
+        policy: Arc<RwLock<KillPolicy>>,
+        sink: Arc<RwLock<Option<KillEventSink>>>,
+        suspended: Arc<SuspendStore>,
+    ) -> Self {
+        // Processes suspended before a restart are still frozen
+        let pending = suspended
+            .list()
+            .unwrap_or_else(|e| {
+                log::error!("Failed to load suspended processes: {}", e);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|p| {
+                let pending = Pending {
+                    name: p.name,
+                    path: None,
+                    rule_id: p.rule_id,
+                    // Budget rules only kill, so only rules suspend
+                    source: RuleSource::Rule,
+                    root_pid: p.root_pid,
+                    start_time: p.start_time,
+                    action: ProcessAction::Suspend,
+                    deadline: Instant::now(),
+                    done: true,
+                    failures: 0,
+                };
+                (Pid::from_u32(p.pid), pending)
+            })
+            .collect();
+
+        Self {
+            policy,
+            sink,
+            suspended,
+            hashes: HashCache::default(),
+            pending,
+        }
+    }
+
+    pub fn check_all(&mut self, sys: &System, matcher: &ProcessMatcher) {
+        for process in sys.processes().values() {
+            self.check(sys, process, matcher);
+        }
+    }
+
+    /// Starts stopping `process` (and its tree) if a rule matches it or it
+    /// descends from a process being stopped. Returns whether it newly did.
+    pub fn check(&mut self, sys: &System, process: &Process, matcher: &ProcessMatcher) -> bool {
+        if let Some(pending) = self.pending.get(&process.pid()) {
+            // Children may have appeared since the tree was collected
+            let origin = pending.origin();
+            self.stop_tree(sys, process, origin);
+            return false;
+        }
+
+        if let Some(origin) = self.pending_ancestor(sys, process) {
+            self.stop_tree(sys, process, origin);
+            return true;
+        }
+
+        let name = process.name().to_string_lossy();
+        let cmdline = process
+            .cmd()
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let sha256 = if matcher.needs_hashes() {
+            process.exe().and_then(|exe| self.hashes.hash(exe))
+        } else {
+            None
+        };
+
+        let info = ProcessInfo {
+            name: &name,
+            exe: process.exe(),
+            cmdline: &cmdline,
+            sha256: sha256.as_deref(),
+        };
+
+        let Some(rule) = matcher.matches(&info) else {
+            return false;
+        };
+
+        log::info!(
+            "Stopping blocked process: {} (PID: {}, rule {})",
+            name,
+            process.pid(),
+            rule.id
+        );
+        let origin = Origin {
+            rule_id: rule.id,
+            source: rule.source,
+            root_pid: process.pid().as_u32(),
+            action: rule.action,
+        };
+        self.stop_tree(sys, process, origin);
+        true
+    }
+
+    /// Hard-kills processes whose grace period ran out, retries failed
+    /// suspends and reports the processes that exited.
+    pub fn escalate(&mut self, sys: &mut System) {
+        let now = Instant::now();
+        let pids: Vec<Pid> = self.pending.keys().copied().collect();
+
+        for pid in pids {
+            let refreshed = sys.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&[pid]),
+                ProcessRefreshKind::new(),
+            );
+            let alive = refreshed > 0
+                && sys.process(pid).is_some_and(|process| {
+                    process.start_time() == self.pending[&pid].start_time
+                        && process.status() != ProcessStatus::Zombie
+                });
+
+            if !alive {
+                let pending = self.pending.remove(&pid).unwrap();
+                if pending.action == ProcessAction::Suspend {
+                    self.forget_suspended(pid);
+                }
+                self.report(pid, &pending, KillStep::Exited);
+                continue;
+            }
+
+            let pending = &self.pending[&pid];
+            if pending.done || now < pending.deadline {
+                continue;
+            }
+
+            let process = sys.process(pid).unwrap();
+            let step = match pending.action {
+                ProcessAction::Kill => self.kill(process),
+                ProcessAction::Suspend => self.suspend(process, pending),
+            };
+            let pending = self.pending.get_mut(&pid).unwrap();
+            if pending.attempted(step) {
+                let pending = &self.pending[&pid];
+                self.report(pid, pending, step);
+            } else {
+                log::debug!(
+                    "Still cannot stop {} (PID {}), {} attempts",
+                    pending.name,
+                    pid,
+                    pending.failures
+                );
+            }
+        }
+    }
+
+    fn stop_tree(&mut self, sys: &System, root: &Process, origin: Origin) {
+        let policy = *self.policy.read().unwrap();
+
+        let mut targets = vec![root.pid()];
+        if policy.kill_tree {
+            targets.extend(descendants(sys, root.pid()));
+        }
+
+        for pid in targets {
+            if self.pending.contains_key(&pid) {
+                continue;
+            }
+            let Some(process) = sys.process(pid) else {
+                continue;
+            };
+            if is_protected(sys, process) {
+                log::debug!(
+                    "Not touching protected process {} (PID {})",
+                    process.name().to_string_lossy(),
+                    pid
+                );
+                continue;
+            }
+
+            let mut pending = Pending {
+                name: process.name().to_string_lossy().into_owned(),
+                path: process.exe().map(|exe| exe.to_string_lossy().into_owned()),
+                rule_id: origin.rule_id,
+                source: origin.source,
+                root_pid: origin.root_pid,
+                start_time: process.start_time(),
+                action: origin.action,
+                deadline: Instant::now() + Duration::from_secs(policy.grace_period_secs),
+                done: false,
+                failures: 0,
+            };
+
+            if pid.as_u32() == origin.root_pid {
+                self.report(pid, &pending, KillStep::Matched);
+            }
+            match origin.action {
+                ProcessAction::Suspend => {
+                    let step = self.suspend(process, &pending);
+                    pending.attempted(step);
+                    self.report(pid, &pending, step);
+                }
+                ProcessAction::Kill
+                    if policy.grace_period_secs > 0 && request_terminate(process) =>
+                {
+                    self.report(pid, &pending, KillStep::TerminateRequested);
+                }
+                ProcessAction::Kill => {
+                    if policy.grace_period_secs > 0 {
+                        self.report(pid, &pending, KillStep::TerminateUnsupported);
+                    }
+                    let step = self.kill(process);
+                    pending.attempted(step);
+                    self.report(pid, &pending, step);
+                }
+            }
+            self.pending.insert(pid, pending);
+        }
+    }
+
+    fn kill(&self, process: &Process) -> KillStep {
+        if process.kill() {
+            KillStep::Killed
+        } else {
+            KillStep::KillFailed
+        }
+    }
+
+    /// Suspends and records the process, so it is resumed even if the app
+    /// restarts before the lock ends.
+    fn suspend(&self, process: &Process, pending: &Pending) -> KillStep {
+        if !suspend::suspend(process) {
+            return KillStep::SuspendFailed;
+        }
+
+        let record = SuspendedProcess {
+            pid: process.pid().as_u32(),
+            start_time: pending.start_time,
+            name: pending.name.clone(),
+            rule_id: pending.rule_id,
+            root_pid: pending.root_pid,
+            suspended_at: Utc::now(),
+        };
+        if let Err(e) = self.suspended.record(&record) {
+            log::error!("Failed to record suspended process {}: {}", record.pid, e);
+        }
+        KillStep::Suspended
+    }
+
+    fn forget_suspended(&self, pid: Pid) {
+        if let Err(e) = self.suspended.remove(pid.as_u32()) {
+            log::error!("Failed to forget suspended process {}: {}", pid, e);
+        }
+    }
+
+    /// The origin of the closest ancestor already being stopped.
+    fn pending_ancestor(&self, sys: &System, process: &Process) -> Option<Origin> {
+        if !self.policy.read().unwrap().kill_tree {
+            return None;
+        }
+
+        let mut seen = HashSet::new();
+        let mut parent = process.parent();
+        while let Some(pid) = parent {
+            if !seen.insert(pid) {
+                break;
+            }
+            if let Some(pending) = self.pending.get(&pid) {
+                return Some(pending.origin());
+            }
+            parent = sys.process(pid).and_then(|p| p.parent());
+        }
+        None
+    }
+
+    fn report(&self, pid: Pid, pending: &Pending, step: KillStep) {
+        let event = KillEvent {
+            pid: pid.as_u32(),
+            name: pending.name.clone(),
+            path: pending.path.clone(),
+            rule_id: pending.rule_id,
+            source: pending.source,
+            root_pid: pending.root_pid,
+            step,
+            at: Utc::now(),
+        };
+        log::info!("{} (PID {}): {:?}", event.name, event.pid, event.step);
+
+        if let Some(sink) = self.sink.read().unwrap().as_ref() {
+            sink(&event);
+        }
+    }
+}
+
+/// Sends a report for a process resumed outside the watcher thread.
+pub fn report_resumed(sink: &Option<KillEventSink>, process: &SuspendedProcess) {
+    let event = KillEvent {
+        pid: process.pid,
+        name: process.name.clone(),
+        path: None,
+        rule_id: process.rule_id,
+        source: RuleSource::Rule,
+        root_pid: process.root_pid,
+        step: KillStep::Resumed,
+        at: Utc::now(),
+    };
+    if let Some(sink) = sink {
+        sink(&event);
+    }
+}
+
+/// Our own process and its children (the webview), init, and the pieces of
+/// the OS session that would take the desktop down with them.
+fn is_protected(sys: &System, process: &Process) -> bool {
+    const CRITICAL: &[&str] = &[
+        // Windows
+        "system",
+        "smss.exe",
+        "csrss.exe",
+        "wininit.exe",
+        "winlogon.exe",
+        "services.exe",
+        "lsass.exe",
+        "svchost.exe",
+        "dwm.exe",
+        "explorer.exe",
+        // macOS
+        "kernel_task",
+        "launchd",
+        "windowserver",
+        "loginwindow",
+        "dock",
+        "finder",
+        // Linux
+        "systemd",
+        "init",
+        "dbus-daemon",
+        "xorg",
+        "xwayland",
+        "gnome-shell",
+        "kwin_x11",
+        "kwin_wayland",
+        "plasmashell",
+    ];
+
+    let pid = process.pid().as_u32();
+    if pid <= 1 || (cfg!(windows) && pid == 4) {
+        return true;
+    }
+
+    let name = process.name().to_string_lossy().to_lowercase();
+    if CRITICAL.contains(&name.as_str()) {
+        return true;
+    }
+
+    let own_pid = Pid::from_u32(std::process::id());
+    let mut seen = HashSet::new();
+    let mut current = Some(process.pid());
+    while let Some(pid) = current {
+        if pid == own_pid {
+            return true;
+        }
+        if !seen.insert(pid) {
+            break;
+        }
+        current = sys.process(pid).and_then(|p| p.parent());
+    }
+    false
+}
+
+fn descendants(sys: &System, root: Pid) -> Vec<Pid> {
+    let mut tree = HashSet::from([root]);
+    let mut found = Vec::new();
+
+    // Repeat until no new children turn up; the table has no child links
+    loop {
+        let children: Vec<Pid> = sys
+            .processes()
+            .values()
+            .filter(|p| !tree.contains(&p.pid()))
+            .filter(|p| p.parent().is_some_and(|parent| tree.contains(&parent)))
+            .map(|p| p.pid())
+            .collect();
+        if children.is_empty() {
+            break;
+        }
+        tree.extend(children.iter().copied());
+        found.extend(children);
+    }
+    found
+}
+
+#[cfg(unix)]
+fn request_terminate(process: &Process) -> bool {
+    process.kill_with(sysinfo::Signal::Term) == Some(true)
+}
+
+/// Posts WM_CLOSE to every top-level window of the process, which is what
+/// clicking the close button does.
+#[cfg(windows)]
+fn request_terminate(process: &Process) -> bool {
+    use winapi::shared::minwindef::{BOOL, DWORD, LPARAM, TRUE};
+    use winapi::shared::windef::HWND;
+    use winapi::um::winuser::{EnumWindows, GetWindowThreadProcessId, PostMessageW, WM_CLOSE};
+
+    struct Search {
+        pid: DWORD,
+        posted: bool,
+    }
+
+    unsafe extern "system" fn close_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let search = &mut *(lparam as *mut Search);
+        let mut pid: DWORD = 0;
+        GetWindowThreadProcessId(hwnd, &mut pid);
+        if pid == search.pid && PostMessageW(hwnd, WM_CLOSE, 0, 0) != 0 {
+            search.posted = true;
+        }
+        TRUE
+    }
+
+    let mut search = Search {
+        pid: process.pid().as_u32(),
+        posted: false,
+    };
+    // SAFETY: `search` outlives the synchronous enumeration
+    unsafe {
+        EnumWindows(Some(close_window), &mut search as *mut Search as LPARAM);
+    }
+    search.posted
+}
+
+#[cfg(not(any(unix, windows)))]
+fn request_terminate(_process: &Process) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending() -> Pending {
+        Pending {
+            name: "game".to_string(),
+            path: None,
+            rule_id: 1,
+            source: RuleSource::Rule,
+            root_pid: 100,
+            start_time: 0,
+            action: ProcessAction::Kill,
+            deadline: Instant::now(),
+            done: false,
+            failures: 0,
+        }
+    }
+
+    #[test]
+    fn failures_are_reported_once_and_retried_less_often() {
+        let mut pending = pending();
+
+        assert!(pending.attempted(KillStep::KillFailed));
+        assert!(!pending.done);
+        assert!(!pending.attempted(KillStep::KillFailed));
+        assert!(!pending.attempted(KillStep::KillFailed));
+        assert_eq!(pending.failures, 3);
+
+        assert_eq!(retry_delay(1), KILL_RETRY_INTERVAL);
+        assert_eq!(retry_delay(3), KILL_RETRY_INTERVAL * 4);
+        assert_eq!(retry_delay(50), MAX_RETRY_INTERVAL);
+
+        // Success is always reported
+        assert!(pending.attempted(KillStep::Killed));
+        assert!(pending.done);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::blocker::blocklist::normalize_domain;
+use crate::blocker::RuleMatcher;
+use crate::db::{self, DbError};
+use crate::schedule::resolve;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Longest allowance a budget can have; anything more is no limit at all.
+const MAX_DAILY_MINUTES: u32 = 24 * 60;
+
+#[derive(Error, Debug)]
+pub enum BudgetError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("A daily allowance must be between 1 and {MAX_DAILY_MINUTES} minutes")]
+    InvalidAllowance,
+    #[error("A budget needs at least one domain or app")]
+    NoTargets,
+    #[error("Invalid domain: {0}")]
+    InvalidDomain(String),
+    #[error("Budget {0} not found")]
+    NotFound(i64),
+    #[error("A used-up budget cannot be changed until it resets")]
+    Exhausted,
+}
+
+/// A daily allowance shared by a set of domains and apps, e.g. 30 minutes
+/// of YouTube whether in the browser or the desktop app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Budget {
+    pub id: i64,
+    pub name: String,
+    /// Blocklist-style rules; `*.youtube.com` covers every name below it
+    pub domains: Vec<String>,
+    /// Executable names, compared case-insensitively
+    pub processes: Vec<String>,
+    pub daily_minutes: u32,
+}
+
+impl Budget {
+    pub fn limit_seconds(&self) -> i64 {
+        self.daily_minutes as i64 * 60
+    }
+
+    /// Whether one of the budget's apps is among `processes` or one of its
+    /// domains among the `queried` names.
+    pub fn in_use(&self, processes: &[String], queried: &[String]) -> bool {
+        let running = self
+            .processes
+            .iter()
+            .any(|name| processes.iter().any(|p| p.eq_ignore_ascii_case(name)));
+        if running || self.domains.is_empty() {
+            return running;
+        }
+
+        let matcher = RuleMatcher::new(&self.domains);
+        queried.iter().any(|name| matcher.is_blocked(name))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BudgetInput {
+    pub name: String,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub processes: Vec<String>,
+    pub daily_minutes: u32,
+}
+
+/// A budget with what is left of it for one budget day.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetUsage {
+    #[serde(flatten)]
+    pub budget: Budget,
+    pub used_seconds: i64,
+    pub remaining_seconds: i64,
+    pub exhausted: bool,
+}
+
+impl BudgetUsage {
+    fn new(budget: Budget, used_seconds: i64) -> Self {
+        let remaining_seconds = (budget.limit_seconds() - used_seconds).max(0);
+        Self {
+            budget,
+            used_seconds,
+            remaining_seconds,
+            exhausted: remaining_seconds == 0,
+        }
+    }
+}
+
+/// The budget day `now` falls in: the local date of the most recent reset.
+pub fn budget_day<Z: TimeZone>(tz: &Z, reset: NaiveTime, now: DateTime<Utc>) -> NaiveDate {
+    let local = now.with_timezone(tz).naive_local();
+    if local.time() < reset {
+        local.date().pred_opt().unwrap_or(local.date())
+    } else {
+        local.date()
+    }
+}
+
+/// When budget day `day` is over and every allowance starts afresh.
+pub fn next_reset<Z: TimeZone>(tz: &Z, reset: NaiveTime, day: NaiveDate) -> DateTime<Utc> {
+    let next = day.succ_opt().unwrap_or(day);
+    resolve(tz, next.and_time(reset))
+}
+
+/// Daily usage budgets and what each has used per budget day, kept in
+/// focuslock.db. The caller measures usage and says which day it is.
+pub struct BudgetStore {
+    db: Mutex<Connection>,
+}
+
+impl BudgetStore {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    pub fn budgets(&self) -> SqliteResult<Vec<Budget>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT id, name, domains, processes, daily_minutes FROM budgets ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let domains: String = row.get(2)?;
+            let processes: String = row.get(3)?;
+            Ok(Budget {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                domains: split_list(&domains),
+                processes: split_list(&processes),
+                daily_minutes: row.get(4)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn usage(&self, day: NaiveDate) -> SqliteResult<Vec<BudgetUsage>> {
+        let budgets = self.budgets()?;
+        let db = self.db.lock().unwrap();
+
+        let mut usage = Vec::with_capacity(budgets.len());
+        for budget in budgets {
+            let used = used_seconds(&db, budget.id, day)?;
+            usage.push(BudgetUsage::new(budget, used));
+        }
+        Ok(usage)
+    }
+
+    /// Budgets with nothing left on `day`.
+    pub fn exhausted(&self, day: NaiveDate) -> SqliteResult<Vec<Budget>> {
+        Ok(self
+            .usage(day)?
+            .into_iter()
+            .filter(|u| u.exhausted)
+            .map(|u| u.budget)
+            .collect())
+    }
+
+    /// Adds `seconds` of use on `day` to each of `ids` and returns the
+    /// budgets this used up, so each is reported once.
+    pub fn record(&self, ids: &[i64], seconds: i64, day: NaiveDate) -> SqliteResult<Vec<Budget>> {
+        if ids.is_empty() || seconds <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let budgets = self.budgets()?;
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        let mut used_up = Vec::new();
+        for budget in budgets.into_iter().filter(|b| ids.contains(&b.id)) {
+            let before = used_seconds(&tx, budget.id, day)?;
+            tx.execute(
+                "INSERT INTO budget_usage (budget_id, day, used_seconds) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(budget_id, day) DO UPDATE
+                 SET used_seconds = used_seconds + excluded.used_seconds",
+                params![budget.id, format_day(day), seconds],
+            )?;
+
+            let limit = budget.limit_seconds();
+            if before < limit && before + seconds >= limit {
+                used_up.push(budget);
+            }
+        }
+        tx.commit()?;
+
+        Ok(used_up)
+    }
+
+    /// Adding is always allowed; a fresh budget starts with its full
+    /// allowance.
+    pub fn add_budget(&self, input: &BudgetInput) -> Result<Budget, BudgetError> {
+        let budget = validate(0, input)?;
+        let db = self.db.lock().unwrap();
+
+        db.execute(
+            "INSERT INTO budgets (name, domains, processes, daily_minutes)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                budget.name,
+                budget.domains.join("\n"),
+                budget.processes.join("\n"),
+                budget.daily_minutes
+            ],
+        )?;
+
+        let id = db.last_insert_rowid();
+        log::info!("Added budget {} ({})", id, budget.name);
+        Ok(Budget { id, ..budget })
+    }
+
+    /// Refused while the budget is used up on `day`, since raising the
+    /// allowance or dropping a target would lift the block.
+    pub fn update_budget(
+        &self,
+        id: i64,
+        input: &BudgetInput,
+        day: NaiveDate,
+    ) -> Result<Budget, BudgetError> {
+        let budget = validate(id, input)?;
+        self.ensure_not_exhausted(id, day)?;
+        let db = self.db.lock().unwrap();
+
+        db.execute(
+            "UPDATE budgets SET name = ?1, domains = ?2, processes = ?3, daily_minutes = ?4
+             WHERE id = ?5",
+            params![
+                budget.name,
+                budget.domains.join("\n"),
+                budget.processes.join("\n"),
+                budget.daily_minutes,
+                id
+            ],
+        )?;
+
+        log::info!("Updated budget {}", id);
+        Ok(budget)
+    }
+
+    pub fn remove_budget(&self, id: i64, day: NaiveDate) -> Result<(), BudgetError> {
+        self.ensure_not_exhausted(id, day)?;
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        tx.execute("DELETE FROM budget_usage WHERE budget_id = ?1", [id])?;
+        tx.execute("DELETE FROM budgets WHERE id = ?1", [id])?;
+        tx.commit()?;
+
+        log::info!("Removed budget {}", id);
+        Ok(())
+    }
+
+    fn ensure_not_exhausted(&self, id: i64, day: NaiveDate) -> Result<(), BudgetError> {
+        let usage = self
+            .usage(day)?
+            .into_iter()
+            .find(|u| u.budget.id == id)
+            .ok_or(BudgetError::NotFound(id))?;
+
+        if usage.exhausted {
+            Err(BudgetError::Exhausted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn used_seconds(db: &Connection, id: i64, day: NaiveDate) -> SqliteResult<i64> {
+    Ok(db
+        .query_row(
+            "SELECT used_seconds FROM budget_usage WHERE budget_id = ?1 AND day = ?2",
+            params![id, format_day(day)],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0))
+}
+
+/// Normalizes the input into the budget it describes.
+fn validate(id: i64, input: &BudgetInput) -> Result<Budget, BudgetError> {
+    if input.daily_minutes == 0 || input.daily_minutes > MAX_DAILY_MINUTES {
+        return Err(BudgetError::InvalidAllowance);
+    }
+
+    let mut domains = input
+        .domains
+        .iter()
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| normalize_domain(d).map_err(|_| BudgetError::InvalidDomain(d.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    domains.sort();
+    domains.dedup();
+
+    let mut processes: Vec<String> = input
+        .processes
+        .iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    processes.sort_by_key(|p| p.to_lowercase());
+    processes.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+
+    if domains.is_empty() && processes.is_empty() {
+        return Err(BudgetError::NoTargets);
+    }
+
+    Ok(Budget {
+        id,
+        name: input.name.trim().to_string(),
+        domains,
+        processes,
+        daily_minutes: input.daily_minutes,
+    })
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn format_day(day: NaiveDate) -> String {
+    day.format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn store() -> BudgetStore {
+        BudgetStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn youtube(daily_minutes: u32) -> BudgetInput {
+        BudgetInput {
+            name: "YouTube".to_string(),
+            domains: vec!["*.YouTube.com".to_string(), "*.googlevideo.com".to_string()],
+            processes: vec!["FreeTube.exe".to_string()],
+            daily_minutes,
+        }
+    }
+
+    #[test]
+    fn budget_day_turns_over_at_the_reset_time() {
+        let seoul = FixedOffset::east_opt(9 * 3600).unwrap();
+        let reset = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
+
+        // 03:30 in Seoul still counts towards the previous day
+        let day = budget_day(&seoul, reset, utc("2026-03-10T18:30:00Z"));
+        assert_eq!(day, date("2026-03-10"));
+        assert_eq!(
+            budget_day(&seoul, reset, utc("2026-03-10T19:00:00Z")),
+            date("2026-03-11")
+        );
+        assert_eq!(next_reset(&seoul, reset, day), utc("2026-03-10T19:00:00Z"));
+    }
+
+    #[test]
+    fn usage_exhausts_a_budget_once_and_resets_the_next_day() {
+        let store = store();
+        let budget = store.add_budget(&youtube(1)).unwrap();
+        let today = date("2026-03-10");
+
+        assert!(store.record(&[budget.id], 40, today).unwrap().is_empty());
+        let used_up = store.record(&[budget.id], 40, today).unwrap();
+        assert_eq!(used_up.len(), 1);
+        // Already used up; not reported again
+        assert!(store.record(&[budget.id], 10, today).unwrap().is_empty());
+
+        let usage = &store.usage(today).unwrap()[0];
+        assert_eq!(usage.used_seconds, 90);
+        assert_eq!(usage.remaining_seconds, 0);
+        assert_eq!(store.exhausted(today).unwrap().len(), 1);
+
+        let tomorrow = date("2026-03-11");
+        assert!(store.exhausted(tomorrow).unwrap().is_empty());
+        assert_eq!(store.usage(tomorrow).unwrap()[0].remaining_seconds, 60);
+    }
+
+    #[test]
+    fn used_up_budget_cannot_be_loosened() {
+        let store = store();
+        let budget = store.add_budget(&youtube(1)).unwrap();
+        let today = date("2026-03-10");
+        store.record(&[budget.id], 60, today).unwrap();
+
+        assert!(matches!(
+            store.update_budget(budget.id, &youtube(60), today),
+            Err(BudgetError::Exhausted)
+        ));
+        assert!(matches!(
+            store.remove_budget(budget.id, today),
+            Err(BudgetError::Exhausted)
+        ));
+
+        let tomorrow = date("2026-03-11");
+        store
+            .update_budget(budget.id, &youtube(60), tomorrow)
+            .unwrap();
+        store.remove_budget(budget.id, tomorrow).unwrap();
+        assert!(store.budgets().unwrap().is_empty());
+    }
+
+    #[test]
+    fn budget_matches_its_apps_and_domains() {
+        let store = store();
+        let budget = store.add_budget(&youtube(30)).unwrap();
+        assert_eq!(budget.domains, ["*.googlevideo.com", "*.youtube.com"]);
+
+        let none: Vec<String> = Vec::new();
+        assert!(budget.in_use(&["freetube.exe".to_string()], &none));
+        assert!(budget.in_use(&none, &["rr3---sn-abc.googlevideo.com".to_string()]));
+        assert!(!budget.in_use(&["code.exe".to_string()], &["github.com".to_string()]));
+
+        assert!(matches!(
+            store.add_budget(&BudgetInput {
+                domains: vec!["not a domain".to_string()],
+                ..youtube(30)
+            }),
+            Err(BudgetError::InvalidDomain(_))
+        ));
+        assert!(matches!(
+            store.add_budget(&youtube(0)),
+            Err(BudgetError::InvalidAllowance)
+        ));
+    }
+}
@@ -0,0 +1,784 @@
+use super::rrule::RecurrenceRule;
+use super::zones::named_zone;
+use crate::schedule::resolve;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventError {
+    /// Line of the event's BEGIN:VEVENT
+    pub line: usize,
+    pub summary: Option<String>,
+    pub reason: String,
+}
+
+/// A start or end as written in the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventTime {
+    pub local: NaiveDateTime,
+    pub zone: Zone,
+    /// A `VALUE=DATE` day rather than a moment
+    pub all_day: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Utc,
+    Named(chrono_tz::Tz),
+    /// No timezone given; follows the system timezone
+    Floating,
+}
+
+impl EventTime {
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        match self.zone {
+            Zone::Utc => self.local.and_utc(),
+            Zone::Named(tz) => resolve(&tz, self.local),
+            Zone::Floating => resolve(&Local, self.local),
+        }
+    }
+
+    fn with_local(&self, local: NaiveDateTime) -> Self {
+        Self { local, ..*self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EventLength {
+    Exact(Duration),
+    /// All-day events last whole local days, whatever DST does to them
+    Days(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct CalendarEvent {
+    pub uid: String,
+    pub summary: String,
+    pub categories: Vec<String>,
+    start: EventTime,
+    length: EventLength,
+    rule: Option<RecurrenceRule>,
+    exdates: Vec<EventTime>,
+    /// Set on an event that replaces one occurrence of a recurring event
+    recurrence_id: Option<EventTime>,
+    cancelled: bool,
+}
+
+/// One concrete time span of an event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Occurrence {
+    pub uid: String,
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParsedCalendar {
+    pub events: Vec<CalendarEvent>,
+    pub errors: Vec<EventError>,
+}
+
+impl ParsedCalendar {
+    /// Every occurrence overlapping `from..until`, with exceptions and
+    /// moved or cancelled instances applied. Events are expanded per UID
+    /// so an override replaces the occurrence it names.
+    pub fn occurrences<F>(
+        &self,
+        mut select: F,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Vec<Occurrence>
+    where
+        F: FnMut(&CalendarEvent) -> bool,
+    {
+        let mut uids: Vec<&str> = self.events.iter().map(|e| e.uid.as_str()).collect();
+        uids.sort();
+        uids.dedup();
+
+        let mut occurrences: Vec<Occurrence> = uids
+            .into_iter()
+            .flat_map(|uid| {
+                let group: Vec<&CalendarEvent> =
+                    self.events.iter().filter(|e| e.uid == uid).collect();
+                expand(&group, until)
+            })
+            .filter(|(event, occurrence)| {
+                occurrence.end > from && occurrence.start < until && select(event)
+            })
+            .map(|(_, occurrence)| occurrence)
+            .collect();
+
+        occurrences.sort_by_key(|o| o.start);
+        occurrences
+    }
+}
+
+/// Expands the master event of one UID and its overrides, paired with the
+/// event each occurrence came from.
+fn expand<'a>(
+    group: &[&'a CalendarEvent],
+    until: DateTime<Utc>,
+) -> Vec<(&'a CalendarEvent, Occurrence)> {
+    let overrides: Vec<&CalendarEvent> = group
+        .iter()
+        .copied()
+        .filter(|e| e.recurrence_id.is_some())
+        .collect();
+    let mut occurrences = Vec::new();
+
+    for master in group.iter().filter(|e| e.recurrence_id.is_none()) {
+        if master.cancelled {
+            continue;
+        }
+
+        let starts = match &master.rule {
+            Some(rule) => {
+                let until_time = rule
+                    .until
+                    .as_deref()
+                    .and_then(|value| parse_time(value, None, master.start.zone).ok());
+                let last = until.date_naive() + Duration::days(1);
+                rule.dates(master.start.local.date(), last)
+                    .into_iter()
+                    .map(|date| {
+                        master
+                            .start
+                            .with_local(date.and_time(master.start.local.time()))
+                    })
+                    .filter(|start| match until_time {
+                        Some(u) if u.all_day => start.local.date() <= u.local.date(),
+                        Some(u) => start.to_utc() <= u.to_utc(),
+                        None => true,
+                    })
+                    .collect()
+            }
+            None => vec![master.start],
+        };
+
+        for start in starts {
+            let excluded = master.exdates.iter().any(|ex| same_instance(ex, &start));
+            let overridden = overrides
+                .iter()
+                .any(|o| o.recurrence_id.is_some_and(|id| same_instance(&id, &start)));
+            if !excluded && !overridden {
+                occurrences.push((*master, occurrence(master, start)));
+            }
+        }
+    }
+
+    for event in overrides.into_iter().filter(|e| !e.cancelled) {
+        occurrences.push((event, occurrence(event, event.start)));
+    }
+
+    occurrences
+}
+
+fn occurrence(event: &CalendarEvent, start: EventTime) -> Occurrence {
+    let end = match event.length {
+        EventLength::Exact(length) => start
+            .to_utc()
+            .checked_add_signed(length)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        EventLength::Days(days) => start
+            .with_local(start.local + Duration::days(days))
+            .to_utc(),
+    };
+
+    Occurrence {
+        uid: event.uid.clone(),
+        summary: event.summary.clone(),
+        start: start.to_utc(),
+        end,
+    }
+}
+
+/// EXDATE and RECURRENCE-ID name an occurrence by its start; a date names
+/// the whole day.
+fn same_instance(id: &EventTime, start: &EventTime) -> bool {
+    if id.all_day {
+        id.local.date() == start.local.date()
+    } else {
+        id.to_utc() == start.to_utc()
+    }
+}
+
+/// Reads the VEVENTs of an iCalendar file. Events that can't be used are
+/// reported and skipped; the rest still import.
+pub fn parse_calendar(content: &str) -> ParsedCalendar {
+    let mut parsed = ParsedCalendar::default();
+    let mut components: Vec<String> = Vec::new();
+    let mut event: Option<EventBuilder> = None;
+    let lines = unfold(content);
+    let locations = zone_locations(&lines);
+
+    for (line_no, line) in lines {
+        let Some(mut property) = Property::parse(&line) else {
+            continue;
+        };
+
+        match property.name.as_str() {
+            "BEGIN" => {
+                let name = property.value.to_ascii_uppercase();
+                if name == "VEVENT" {
+                    event = Some(EventBuilder::new(line_no));
+                }
+                components.push(name);
+            }
+            "END" if in_event(&components) => {
+                components.pop();
+                if let Some(builder) = event.take() {
+                    let line = builder.line;
+                    let summary = builder.summary.clone();
+                    match builder.build() {
+                        Ok(built) => parsed.events.push(built),
+                        Err(reason) => parsed.errors.push(EventError {
+                            line,
+                            summary,
+                            reason,
+                        }),
+                    }
+                }
+            }
+            "END" => {
+                components.pop();
+            }
+            // Properties of alarms and other nested components don't count
+            _ if in_event(&components) => {
+                if let Some(builder) = event.as_mut() {
+                    property.locate_tzid(&locations);
+                    builder.add(&property);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    parsed
+}
+
+/// The IANA zones VTIMEZONEs name for their TZIDs in `X-LIC-LOCATION`, as
+/// Google Calendar and Thunderbird write them.
+fn zone_locations(lines: &[(usize, String)]) -> Vec<(String, String)> {
+    let mut locations = Vec::new();
+    let mut tzid: Option<String> = None;
+
+    for (_, line) in lines {
+        let Some(property) = Property::parse(line) else {
+            continue;
+        };
+        match property.name.as_str() {
+            "BEGIN" | "END" if property.value.eq_ignore_ascii_case("VTIMEZONE") => tzid = None,
+            "TZID" => tzid = Some(property.value.trim().to_string()),
+            "X-LIC-LOCATION" => {
+                if let Some(tzid) = &tzid {
+                    locations.push((tzid.clone(), property.value.trim().to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    locations
+}
+
+fn in_event(components: &[String]) -> bool {
+    components.last().map(String::as_str) == Some("VEVENT")
+}
+
+/// Joins folded lines, numbering each logical line by where it starts.
+fn unfold(content: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+
+    for (idx, raw) in content.lines().enumerate() {
+        match (raw.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, previous))) => previous.push_str(rest),
+            _ => lines.push((idx + 1, raw.to_string())),
+        }
+    }
+
+    lines
+}
+
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        // The value starts at the first colon outside a quoted parameter
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                quoted = !quoted;
+                None
+            }
+            ':' if !quoted => Some(i),
+            _ => None,
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+        let mut parts = head.split(';');
+        let name = parts.next()?.trim().to_ascii_uppercase();
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| {
+                (
+                    k.trim().to_ascii_uppercase(),
+                    v.trim_matches('"').to_string(),
+                )
+            })
+            .collect();
+
+        Some(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    /// Replaces a TZID that only the file's own VTIMEZONE explains with
+    /// the zone it names.
+    fn locate_tzid(&mut self, locations: &[(String, String)]) {
+        for (key, value) in &mut self.params {
+            if key != "TZID" || named_zone(value).is_some() {
+                continue;
+            }
+            if let Some((_, location)) = locations.iter().find(|(tzid, _)| tzid == value) {
+                *value = location.clone();
+            }
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct EventBuilder {
+    line: usize,
+    uid: Option<String>,
+    summary: Option<String>,
+    categories: Vec<String>,
+    start: Option<EventTime>,
+    end: Option<EventTime>,
+    duration: Option<Duration>,
+    rule: Option<RecurrenceRule>,
+    exdates: Vec<EventTime>,
+    recurrence_id: Option<EventTime>,
+    cancelled: bool,
+    /// The first problem found; reported once the event ends
+    error: Option<String>,
+}
+
+impl EventBuilder {
+    fn new(line: usize) -> Self {
+        Self {
+            line,
+            uid: None,
+            summary: None,
+            categories: Vec::new(),
+            start: None,
+            end: None,
+            duration: None,
+            rule: None,
+            exdates: Vec::new(),
+            recurrence_id: None,
+            cancelled: false,
+            error: None,
+        }
+    }
+
+    fn add(&mut self, property: &Property) {
+        let result = match property.name.as_str() {
+            "UID" => {
+                self.uid = Some(property.value.trim().to_string());
+                Ok(())
+            }
+            "SUMMARY" => {
+                self.summary = Some(unescape(&property.value));
+                Ok(())
+            }
+            "CATEGORIES" => {
+                self.categories
+                    .extend(split_list(&property.value).iter().map(|c| unescape(c)));
+                Ok(())
+            }
+            "STATUS" => {
+                self.cancelled = property.value.eq_ignore_ascii_case("CANCELLED");
+                Ok(())
+            }
+            "DTSTART" => event_time(property).map(|t| self.start = Some(t)),
+            "DTEND" => event_time(property).map(|t| self.end = Some(t)),
+            "RECURRENCE-ID" => event_time(property).map(|t| self.recurrence_id = Some(t)),
+            "DURATION" => parse_duration(&property.value).map(|d| self.duration = Some(d)),
+            "RRULE" => RecurrenceRule::parse(&property.value).map(|r| self.rule = Some(r)),
+            "EXDATE" => property
+                .value
+                .split(',')
+                .map(|value| parse_time(value, property.param("TZID"), Zone::Floating))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|times| self.exdates.extend(times)),
+            "RDATE" => Err("RDATE is not supported".to_string()),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn build(self) -> Result<CalendarEvent, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let uid = self.uid.ok_or("Event has no UID")?;
+        let start = self.start.ok_or("Event has no DTSTART")?;
+
+        let length = match (self.end, self.duration) {
+            (Some(end), _) if start.all_day => {
+                EventLength::Days((end.local.date() - start.local.date()).num_days())
+            }
+            (Some(end), _) => EventLength::Exact(end.to_utc() - start.to_utc()),
+            (None, Some(duration)) => EventLength::Exact(duration),
+            // A date with no end is that one day
+            (None, None) if start.all_day => EventLength::Days(1),
+            (None, None) => EventLength::Exact(Duration::zero()),
+        };
+        let positive = match length {
+            EventLength::Exact(d) => d > Duration::zero(),
+            EventLength::Days(days) => days > 0,
+        };
+        if !positive {
+            return Err("Event has no duration".to_string());
+        }
+
+        Ok(CalendarEvent {
+            uid,
+            summary: self.summary.unwrap_or_default(),
+            categories: self.categories,
+            start,
+            length,
+            rule: self.rule,
+            exdates: self.exdates,
+            recurrence_id: self.recurrence_id,
+            cancelled: self.cancelled,
+        })
+    }
+}
+
+fn event_time(property: &Property) -> Result<EventTime, String> {
+    let time = parse_time(&property.value, property.param("TZID"), Zone::Floating)?;
+    match property.param("VALUE") {
+        Some(v) if v.eq_ignore_ascii_case("DATE") && !time.all_day => {
+            Err(format!("Expected a date, got {}", property.value))
+        }
+        _ => Ok(time),
+    }
+}
+
+/// `20240301`, `20240301T090000Z`, or a local time in `tzid` (or in
+/// `floating` when there is none).
+fn parse_time(value: &str, tzid: Option<&str>, floating: Zone) -> Result<EventTime, String> {
+    let value = value.trim();
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Ok(EventTime {
+            local: date.and_time(NaiveTime::MIN),
+            zone: floating,
+            all_day: true,
+        });
+    }
+
+    let (local, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(local) => (local, true),
+        None => (value, false),
+    };
+    let local = NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S")
+        .map_err(|_| format!("Invalid date-time {}", value))?;
+
+    let zone = match (utc, tzid) {
+        (true, _) => Zone::Utc,
+        (false, Some(tzid)) => {
+            Zone::Named(named_zone(tzid).ok_or_else(|| format!("Unknown time zone {}", tzid))?)
+        }
+        (false, None) => floating,
+    };
+
+    Ok(EventTime {
+        local,
+        zone,
+        all_day: false,
+    })
+}
+
+/// `P1W`, `P1DT2H`, `PT90M` and the like.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration {}", value);
+    let value = value.trim();
+    let (negative, rest) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.trim_start_matches('+')),
+    };
+    let rest = rest.strip_prefix('P').ok_or_else(invalid)?;
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() => in_time = true,
+            _ => {
+                let n: i64 = number.parse().map_err(|_| invalid())?;
+                number.clear();
+                let part = match (c, in_time) {
+                    ('W', false) => Duration::try_weeks(n),
+                    ('D', false) => Duration::try_days(n),
+                    ('H', true) => Duration::try_hours(n),
+                    ('M', true) => Duration::try_minutes(n),
+                    ('S', true) => Duration::try_seconds(n),
+                    _ => return Err(invalid()),
+                };
+                total = part
+                    .and_then(|part| total.checked_add(&part))
+                    .ok_or_else(invalid)?;
+            }
+        }
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(if negative { -total } else { total })
+}
+
+/// Splits a comma-separated text value, leaving escaped commas in place.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(String::new()),
+            _ => {
+                if let Some(item) = items.last_mut() {
+                    item.push(c);
+                }
+            }
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    items
+        .into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn spans(parsed: &ParsedCalendar, from: &str, until: &str) -> Vec<(String, String)> {
+        parsed
+            .occurrences(|_| true, utc(from), utc(until))
+            .iter()
+            .map(|o| (o.start.to_rfc3339(), o.end.to_rfc3339()))
+            .collect()
+    }
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:deep-work@example.com\r
+SUMMARY:Deep work\\, no meetings\r
+CATEGORIES:Focus,Work\r
+DTSTART;TZID=Europe/Berlin:20240325T090000\r
+DTEND;TZID=Europe/Berlin:20240325T113000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE;UNTIL=20240410T000000Z\r
+EXDATE;TZID=Europe/Berlin:20240327T090000\r
+BEGIN:VALARM\r
+TRIGGER:-PT15M\r
+DESCRIPTION:Should not replace the summary\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:deep-work@example.com\r
+RECURRENCE-ID;TZID=Europe/Berlin:20240401T090000\r
+SUMMARY:Deep work (late)\r
+DTSTART;TZID=Europe/Berlin:20240401T130000\r
+DURATION:PT1H\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:exam@example.com\r
+SUMMARY:Exam\r
+ ination\r
+CATEGORIES:School\r
+DTSTART;VALUE=DATE:20240405\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:No uid\r
+DTSTART:20240405T100000Z\r
+DURATION:PT1H\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn recurring_event_with_exceptions_and_override() {
+        let parsed = parse_calendar(CALENDAR);
+        assert_eq!(parsed.events.len(), 3);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.errors[0].summary.as_deref(), Some("No uid"));
+
+        let work = &parsed.events[0];
+        assert_eq!(work.summary, "Deep work, no meetings");
+        assert_eq!(work.categories, ["Focus", "Work"]);
+        assert_eq!(parsed.events[2].summary, "Examination");
+
+        let work_only = parsed.occurrences(
+            |e| e.uid.starts_with("deep-work"),
+            utc("2024-03-01T00:00:00Z"),
+            utc("2024-05-01T00:00:00Z"),
+        );
+        let starts: Vec<String> = work_only.iter().map(|o| o.start.to_rfc3339()).collect();
+        // CET before 2024-03-31, CEST after; the 27th is excluded, the 1st
+        // moved to the afternoon, and UNTIL stops before the 10th
+        assert_eq!(
+            starts,
+            [
+                "2024-03-25T08:00:00+00:00",
+                "2024-04-01T11:00:00+00:00",
+                "2024-04-03T07:00:00+00:00",
+                "2024-04-08T07:00:00+00:00",
+            ]
+        );
+        assert_eq!(work_only[1].summary, "Deep work (late)");
+        assert_eq!(work_only[1].end, utc("2024-04-01T12:00:00Z"));
+        assert_eq!(work_only[2].end, utc("2024-04-03T09:30:00Z"));
+    }
+
+    #[test]
+    fn occurrences_are_limited_to_the_range() {
+        let parsed = parse_calendar(CALENDAR);
+        assert_eq!(
+            spans(&parsed, "2024-04-08T09:00:00Z", "2024-04-30T00:00:00Z"),
+            [(
+                "2024-04-08T07:00:00+00:00".to_string(),
+                "2024-04-08T09:30:00+00:00".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn cancelled_and_unknown_zones() {
+        let parsed = parse_calendar(
+            "BEGIN:VCALENDAR
+BEGIN:VEVENT
+UID:a
+DTSTART:20240301T100000Z
+DTEND:20240301T110000Z
+STATUS:CANCELLED
+END:VEVENT
+BEGIN:VEVENT
+UID:b
+DTSTART;TZID=Nowhere Standard Time:20240301T100000
+DTEND;TZID=Nowhere Standard Time:20240301T110000
+END:VEVENT
+END:VCALENDAR",
+        );
+
+        assert!(spans(&parsed, "2024-01-01T00:00:00Z", "2025-01-01T00:00:00Z").is_empty());
+        assert_eq!(parsed.errors.len(), 1);
+        assert!(parsed.errors[0].reason.contains("Unknown time zone"));
+    }
+
+    #[test]
+    fn windows_and_vtimezone_zones() {
+        let parsed = parse_calendar(
+            "BEGIN:VCALENDAR
+BEGIN:VTIMEZONE
+TZID:Customized Time Zone
+X-LIC-LOCATION:America/New_York
+BEGIN:STANDARD
+TZOFFSETFROM:-0400
+TZOFFSETTO:-0500
+END:STANDARD
+END:VTIMEZONE
+BEGIN:VEVENT
+UID:outlook
+DTSTART;TZID=W. Europe Standard Time:20240301T100000
+DTEND;TZID=W. Europe Standard Time:20240301T110000
+END:VEVENT
+BEGIN:VEVENT
+UID:custom
+DTSTART;TZID=Customized Time Zone:20240301T100000
+DTEND;TZID=Customized Time Zone:20240301T110000
+END:VEVENT
+END:VCALENDAR",
+        );
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            spans(&parsed, "2024-01-01T00:00:00Z", "2025-01-01T00:00:00Z"),
+            [
+                (
+                    "2024-03-01T09:00:00+00:00".to_string(),
+                    "2024-03-01T10:00:00+00:00".to_string()
+                ),
+                (
+                    "2024-03-01T15:00:00+00:00".to_string(),
+                    "2024-03-01T16:00:00+00:00".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT90M").unwrap(), Duration::minutes(90));
+        assert_eq!(
+            parse_duration("P1DT2H").unwrap(),
+            Duration::days(1) + Duration::hours(2)
+        );
+        assert_eq!(parse_duration("-P1W").unwrap(), -Duration::weeks(1));
+        assert!(parse_duration("PT").is_ok());
+        assert!(parse_duration("P1H").is_err());
+        assert!(parse_duration("1H").is_err());
+        // Out of range instead of overflowing
+        assert!(parse_duration("P9223372036854775807W").is_err());
+        assert!(parse_duration("P100000000000000DT1H").is_err());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod ics;
+pub mod planned;
+pub mod rrule;
+mod zones;
+
+pub use ics::{parse_calendar, Occurrence};
+pub use planned::{CalendarImportReport, EventFilter, PlannedLock, PlannedLockStore};
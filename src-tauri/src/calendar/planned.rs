@@ -0,0 +1,419 @@
+use super::ics::{CalendarEvent, EventError, Occurrence, ParsedCalendar};
+use crate::db::{self, DbError};
+use chrono::{DateTime, Duration, Utc};
+use regex::{Regex, RegexBuilder};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// How far ahead an import plans. Open-ended rules are cut off here;
+/// importing the file again plans the next stretch.
+const IMPORT_HORIZON_DAYS: i64 = 366;
+
+#[derive(Error, Debug)]
+pub enum CalendarError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+}
+
+/// Which events to import. Each pattern is a case-insensitive regular
+/// expression; an empty filter takes every event.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventFilter {
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Matches if any of the event's categories match
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+struct CompiledFilter {
+    summary: Option<Regex>,
+    category: Option<Regex>,
+}
+
+impl EventFilter {
+    fn compile(&self) -> Result<CompiledFilter, CalendarError> {
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    RegexBuilder::new(p)
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| CalendarError::InvalidPattern(e.to_string()))
+                })
+                .transpose()
+        };
+
+        Ok(CompiledFilter {
+            summary: compile(&self.summary)?,
+            category: compile(&self.category)?,
+        })
+    }
+}
+
+impl CompiledFilter {
+    fn matches(&self, event: &CalendarEvent) -> bool {
+        let summary = self
+            .summary
+            .as_ref()
+            .is_none_or(|re| re.is_match(&event.summary));
+        let category = self
+            .category
+            .as_ref()
+            .is_none_or(|re| event.categories.iter().any(|c| re.is_match(c)));
+        summary && category
+    }
+}
+
+/// A lock that starts by itself when its time comes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedLock {
+    pub id: i64,
+    pub uid: String,
+    pub summary: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    /// The calendar file it was imported from
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarImportReport {
+    /// Occurrences of the selected events within the import horizon
+    pub matched: usize,
+    pub added: usize,
+    /// Planned locks whose occurrence moved or left the calendar
+    pub removed: usize,
+    pub unchanged: usize,
+    pub errors: Vec<EventError>,
+}
+
+/// Lock windows imported from calendars, kept in focuslock.db until they
+/// have started.
+pub struct PlannedLockStore {
+    db: Mutex<Connection>,
+}
+
+impl PlannedLockStore {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    /// The occurrences an import with `filter` would plan.
+    pub fn preview(
+        parsed: &ParsedCalendar,
+        filter: &EventFilter,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Occurrence>, CalendarError> {
+        Ok(upcoming(parsed, &filter.compile()?, now))
+    }
+
+    /// Plans the selected events' upcoming occurrences. Importing the same
+    /// file again only applies what changed: occurrences are keyed by UID
+    /// and start, and plans from this file whose occurrence no longer
+    /// exists are dropped. Locks that already started are left alone.
+    pub fn import(
+        &self,
+        source: &str,
+        parsed: &ParsedCalendar,
+        filter: &EventFilter,
+        now: DateTime<Utc>,
+    ) -> Result<CalendarImportReport, CalendarError> {
+        let filter = filter.compile()?;
+        let occurrences = upcoming(parsed, &filter, now);
+        let wanted: HashSet<(&str, DateTime<Utc>, DateTime<Utc>)> = occurrences
+            .iter()
+            .map(|o| (o.uid.as_str(), o.start, o.end))
+            .collect();
+        // Selected UIDs are replaced wholesale; UIDs gone from the file too
+        let selected: HashSet<&str> = parsed
+            .events
+            .iter()
+            .filter(|e| filter.matches(e))
+            .map(|e| e.uid.as_str())
+            .collect();
+        let in_file: HashSet<&str> = parsed.events.iter().map(|e| e.uid.as_str()).collect();
+
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        let existing = pending(&tx, now)?;
+        let mut removed = 0;
+        let mut unchanged = HashSet::new();
+        for plan in &existing {
+            let key = (plan.uid.as_str(), plan.starts_at, plan.ends_at);
+            let stale = selected.contains(plan.uid.as_str())
+                || (plan.source == source && !in_file.contains(plan.uid.as_str()));
+            if wanted.contains(&key) {
+                unchanged.insert(key);
+            } else if stale {
+                tx.execute("DELETE FROM planned_locks WHERE id = ?1", [plan.id])?;
+                removed += 1;
+            }
+        }
+
+        let mut added = 0;
+        for occurrence in &occurrences {
+            let key = (occurrence.uid.as_str(), occurrence.start, occurrence.end);
+            if unchanged.contains(&key) {
+                continue;
+            }
+            // A lock from this occurrence that already started stays as it is
+            added += tx.execute(
+                "INSERT OR IGNORE INTO planned_locks (uid, summary, starts_at, ends_at, source)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    occurrence.uid,
+                    occurrence.summary,
+                    occurrence.start.to_rfc3339(),
+                    occurrence.end.to_rfc3339(),
+                    source
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        log::info!(
+            "Imported calendar {}: {} added, {} removed, {} unchanged",
+            source,
+            added,
+            removed,
+            unchanged.len()
+        );
+        Ok(CalendarImportReport {
+            matched: occurrences.len(),
+            added,
+            removed,
+            unchanged: unchanged.len(),
+            errors: parsed.errors.clone(),
+        })
+    }
+
+    /// Planned locks that haven't started and haven't been missed.
+    pub fn planned(&self, now: DateTime<Utc>) -> SqliteResult<Vec<PlannedLock>> {
+        let db = self.db.lock().unwrap();
+        pending(&db, now)
+    }
+
+    pub fn remove(&self, id: i64) -> SqliteResult<()> {
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "DELETE FROM planned_locks WHERE id = ?1 AND started = 0",
+            [id],
+        )?;
+
+        log::info!("Removed planned lock {}", id);
+        Ok(())
+    }
+
+    /// Marks every plan whose window is open at `now` as started and
+    /// returns the one ending last, for the caller to lock until its end.
+    pub fn take_due(&self, now: DateTime<Utc>) -> SqliteResult<Option<PlannedLock>> {
+        let db = self.db.lock().unwrap();
+        let now_str = now.to_rfc3339();
+
+        let due = db
+            .query_row(
+                "SELECT id, uid, summary, starts_at, ends_at, source FROM planned_locks
+                 WHERE started = 0 AND starts_at <= ?1 AND ends_at > ?1
+                 ORDER BY ends_at DESC LIMIT 1",
+                [&now_str],
+                |row| Ok(read_plan(row)),
+            )
+            .optional()?
+            .flatten();
+
+        if due.is_some() {
+            db.execute(
+                "UPDATE planned_locks SET started = 1
+                 WHERE started = 0 AND starts_at <= ?1 AND ends_at > ?1",
+                [&now_str],
+            )?;
+        }
+        Ok(due)
+    }
+}
+
+fn upcoming(
+    parsed: &ParsedCalendar,
+    filter: &CompiledFilter,
+    now: DateTime<Utc>,
+) -> Vec<Occurrence> {
+    parsed.occurrences(
+        |event| filter.matches(event),
+        now,
+        now + Duration::days(IMPORT_HORIZON_DAYS),
+    )
+}
+
+fn pending(db: &Connection, now: DateTime<Utc>) -> SqliteResult<Vec<PlannedLock>> {
+    let mut stmt = db.prepare(
+        "SELECT id, uid, summary, starts_at, ends_at, source FROM planned_locks
+         WHERE started = 0 AND ends_at > ?1
+         ORDER BY starts_at",
+    )?;
+    let rows = stmt.query_map([now.to_rfc3339()], |row| Ok(read_plan(row)))?;
+
+    let mut plans = Vec::new();
+    for plan in rows {
+        plans.extend(plan?);
+    }
+    Ok(plans)
+}
+
+/// Rows with unreadable times are skipped rather than failing the list.
+fn read_plan(row: &rusqlite::Row) -> Option<PlannedLock> {
+    let parse = |idx| {
+        row.get::<_, String>(idx)
+            .ok()
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|t| t.with_timezone(&Utc))
+    };
+
+    Some(PlannedLock {
+        id: row.get(0).ok()?,
+        uid: row.get(1).ok()?,
+        summary: row.get(2).ok()?,
+        starts_at: parse(3)?,
+        ends_at: parse(4)?,
+        source: row.get(5).ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::ics::parse_calendar;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn store() -> PlannedLockStore {
+        PlannedLockStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn calendar(exam_start: &str) -> ParsedCalendar {
+        parse_calendar(&format!(
+            "BEGIN:VCALENDAR
+BEGIN:VEVENT
+UID:exam
+SUMMARY:Exam
+CATEGORIES:School
+DTSTART:{}
+DURATION:PT2H
+END:VEVENT
+BEGIN:VEVENT
+UID:study
+SUMMARY:Study block
+CATEGORIES:School
+DTSTART:20240304T080000Z
+DURATION:PT3H
+RRULE:FREQ=DAILY;COUNT=3
+END:VEVENT
+BEGIN:VEVENT
+UID:lunch
+SUMMARY:Lunch
+DTSTART:20240304T120000Z
+DURATION:PT1H
+END:VEVENT
+END:VCALENDAR",
+            exam_start
+        ))
+    }
+
+    #[test]
+    fn reimport_is_idempotent_and_follows_changes() {
+        let store = store();
+        let now = utc("2024-03-01T00:00:00Z");
+        let filter = EventFilter {
+            category: Some("^school$".to_string()),
+            ..Default::default()
+        };
+
+        let report = store
+            .import("exams.ics", &calendar("20240305T090000Z"), &filter, now)
+            .unwrap();
+        assert_eq!((report.matched, report.added), (4, 4));
+
+        let report = store
+            .import("exams.ics", &calendar("20240305T090000Z"), &filter, now)
+            .unwrap();
+        assert_eq!((report.added, report.removed, report.unchanged), (0, 0, 4));
+        assert_eq!(store.planned(now).unwrap().len(), 4);
+
+        // The exam moved: its old plan goes, the new one comes in
+        let report = store
+            .import("exams.ics", &calendar("20240306T130000Z"), &filter, now)
+            .unwrap();
+        assert_eq!((report.added, report.removed, report.unchanged), (1, 1, 3));
+        let exam: Vec<_> = store
+            .planned(now)
+            .unwrap()
+            .into_iter()
+            .filter(|p| p.uid == "exam")
+            .collect();
+        assert_eq!(exam.len(), 1);
+        assert_eq!(exam[0].starts_at, utc("2024-03-06T13:00:00Z"));
+    }
+
+    #[test]
+    fn due_plans_start_once() {
+        let store = store();
+        let filter = EventFilter {
+            summary: Some("study".to_string()),
+            ..Default::default()
+        };
+        store
+            .import(
+                "exams.ics",
+                &calendar("20240305T090000Z"),
+                &filter,
+                utc("2024-03-01T00:00:00Z"),
+            )
+            .unwrap();
+
+        let during = utc("2024-03-05T09:00:00Z");
+        let due = store.take_due(during).unwrap().unwrap();
+        assert_eq!(due.ends_at, utc("2024-03-05T11:00:00Z"));
+        assert!(store.take_due(during).unwrap().is_none());
+
+        // A re-import while it runs doesn't plan it again
+        let report = store
+            .import("exams.ics", &calendar("20240305T090000Z"), &filter, during)
+            .unwrap();
+        assert_eq!(report.added, 0);
+        assert!(store.take_due(during).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let filter = EventFilter {
+            summary: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            store().import("a.ics", &ParsedCalendar::default(), &filter, Utc::now()),
+            Err(CalendarError::InvalidPattern(_))
+        ));
+    }
+}
@@ -0,0 +1,420 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+/// Stops runaway rules, e.g. a yearly rule on February 30th that never
+/// produces a date.
+const MAX_PERIODS: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The supported subset of an RFC 5545 RRULE: whole days repeated by
+/// FREQ/INTERVAL, narrowed or expanded with BYDAY, BYMONTHDAY and BYMONTH,
+/// ending after COUNT. UNTIL is kept as written since comparing it needs
+/// the event's timezone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurrenceRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<String>,
+    /// Weekdays, with an optional ordinal such as the -1 in `-1FR`
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+impl RecurrenceRule {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut freq = None;
+        let mut rule = RecurrenceRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+
+        for part in value.split(';').filter(|p| !p.is_empty()) {
+            let (key, val) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Malformed RRULE part {}", part))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match val.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => return Err(format!("Unsupported RRULE frequency {}", other)),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = val
+                        .parse()
+                        .ok()
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| format!("Invalid RRULE interval {}", val))?
+                }
+                "COUNT" => {
+                    rule.count = Some(
+                        val.parse()
+                            .map_err(|_| format!("Invalid RRULE count {}", val))?,
+                    )
+                }
+                "UNTIL" => rule.until = Some(val.to_string()),
+                "BYDAY" => {
+                    rule.by_day = val.split(',').map(parse_by_day).collect::<Result<_, _>>()?
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = val
+                        .split(',')
+                        .map(|d| {
+                            d.parse()
+                                .ok()
+                                .filter(|d: &i32| (1..=31).contains(&d.abs()))
+                                .ok_or_else(|| format!("Invalid RRULE month day {}", d))
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTH" => {
+                    rule.by_month = val
+                        .split(',')
+                        .map(|m| {
+                            m.parse()
+                                .ok()
+                                .filter(|m| (1..=12).contains(m))
+                                .ok_or_else(|| format!("Invalid RRULE month {}", m))
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                // Only changes which weeks an INTERVAL skips; Monday is assumed
+                "WKST" => {}
+                other => return Err(format!("Unsupported RRULE part {}", other)),
+            }
+        }
+
+        rule.freq = freq.ok_or("RRULE has no FREQ")?;
+
+        let has_ordinals = rule.by_day.iter().any(|(n, _)| n.is_some());
+        match rule.freq {
+            Frequency::Daily | Frequency::Weekly if has_ordinals => {
+                return Err("Numbered BYDAY needs a monthly or yearly RRULE".to_string())
+            }
+            Frequency::Yearly if !rule.by_day.is_empty() && rule.by_month.is_empty() => {
+                return Err("Yearly BYDAY without BYMONTH is not supported".to_string())
+            }
+            _ => {}
+        }
+
+        Ok(rule)
+    }
+
+    /// Occurrence dates from `start` (always the first) through `last`,
+    /// cut off after COUNT.
+    pub fn dates(&self, start: NaiveDate, last: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = vec![start];
+        let limit = self.count.map(|c| c as usize).unwrap_or(usize::MAX);
+        let interval = self.interval as i64;
+
+        for period in 0..MAX_PERIODS {
+            if dates.len() >= limit {
+                break;
+            }
+            let step = period * interval;
+
+            let (period_start, mut candidates) = match self.freq {
+                Frequency::Daily => {
+                    let day = start + Duration::days(step);
+                    (day, vec![day])
+                }
+                Frequency::Weekly => {
+                    let monday = start
+                        - Duration::days(start.weekday().num_days_from_monday() as i64)
+                        + Duration::weeks(step);
+                    let days = (0..7)
+                        .map(|i| monday + Duration::days(i))
+                        .filter(|d| {
+                            if self.by_day.is_empty() {
+                                d.weekday() == start.weekday()
+                            } else {
+                                self.by_day.iter().any(|(_, wd)| *wd == d.weekday())
+                            }
+                        })
+                        .collect();
+                    (monday, days)
+                }
+                Frequency::Monthly => {
+                    let months = start.year() as i64 * 12 + start.month0() as i64 + step;
+                    let (year, month) = ((months / 12) as i32, (months % 12) as u32 + 1);
+                    let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+                        break;
+                    };
+                    (first, self.month_days(year, month, start.day()))
+                }
+                Frequency::Yearly => {
+                    let year = start.year() + step as i32;
+                    let Some(first) = NaiveDate::from_ymd_opt(year, 1, 1) else {
+                        break;
+                    };
+                    let months = if self.by_month.is_empty() {
+                        vec![start.month()]
+                    } else {
+                        self.by_month.clone()
+                    };
+                    let days = months
+                        .into_iter()
+                        .flat_map(|month| self.month_days(year, month, start.day()))
+                        .collect();
+                    (first, days)
+                }
+            };
+
+            if period_start > last {
+                break;
+            }
+
+            candidates.sort();
+            for date in candidates {
+                if date <= start || !self.keeps(date) {
+                    continue;
+                }
+                if date > last || dates.len() >= limit {
+                    break;
+                }
+                dates.push(date);
+            }
+        }
+
+        dates
+    }
+
+    /// BY* parts that only narrow the frequency's own dates.
+    fn keeps(&self, date: NaiveDate) -> bool {
+        if !self.by_month.is_empty() && !self.by_month.contains(&date.month()) {
+            return false;
+        }
+        if self.freq == Frequency::Daily {
+            if !self.by_day.is_empty() && !self.by_day.iter().any(|(_, wd)| *wd == date.weekday()) {
+                return false;
+            }
+            if !self.by_month_day.is_empty() && !self.month_day_matches(date) {
+                return false;
+            }
+        }
+        if self.freq == Frequency::Weekly
+            && !self.by_month_day.is_empty()
+            && !self.month_day_matches(date)
+        {
+            return false;
+        }
+        true
+    }
+
+    fn month_day_matches(&self, date: NaiveDate) -> bool {
+        let len = days_in_month(date.year(), date.month()) as i32;
+        self.by_month_day.iter().any(|&d| {
+            let day = if d < 0 { len + d + 1 } else { d };
+            day == date.day() as i32
+        })
+    }
+
+    /// The days of one month a monthly (or yearly, per month) rule lands on.
+    fn month_days(&self, year: i32, month: u32, default_day: u32) -> Vec<NaiveDate> {
+        let len = days_in_month(year, month);
+        let all: Vec<NaiveDate> = (1..=len)
+            .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+            .collect();
+
+        let by_month_day: Vec<NaiveDate> = all
+            .iter()
+            .copied()
+            .filter(|d| self.month_day_matches(*d))
+            .collect();
+
+        let by_day: Vec<NaiveDate> = self
+            .by_day
+            .iter()
+            .flat_map(|&(ordinal, weekday)| {
+                let matching: Vec<NaiveDate> = all
+                    .iter()
+                    .copied()
+                    .filter(|d| d.weekday() == weekday)
+                    .collect();
+                match ordinal {
+                    None => matching,
+                    Some(n) if n > 0 => matching.get(n as usize - 1).copied().into_iter().collect(),
+                    Some(n) => matching
+                        .len()
+                        .checked_sub(n.unsigned_abs() as usize)
+                        .and_then(|i| matching.get(i).copied())
+                        .into_iter()
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let mut days = match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+            (true, true) => NaiveDate::from_ymd_opt(year, month, default_day)
+                .into_iter()
+                .collect(),
+            (false, true) => by_month_day,
+            (true, false) => by_day,
+            (false, false) => by_day
+                .into_iter()
+                .filter(|d| by_month_day.contains(d))
+                .collect(),
+        };
+        days.sort();
+        days.dedup();
+        days
+    }
+}
+
+fn parse_by_day(value: &str) -> Result<(Option<i32>, Weekday), String> {
+    let value = value.trim();
+    // The weekday is the last two characters; anything else isn't ASCII
+    let split = value.char_indices().rev().nth(1).map_or(0, |(i, _)| i);
+    let (ordinal, day) = value.split_at(split);
+
+    let weekday = match day.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(format!("Invalid RRULE weekday {}", value)),
+    };
+    let ordinal = match ordinal {
+        "" => None,
+        n => Some(
+            n.trim_start_matches('+')
+                .parse()
+                .ok()
+                .filter(|n: &i32| *n != 0 && n.abs() <= 5)
+                .ok_or_else(|| format!("Invalid RRULE weekday {}", value))?,
+        ),
+    };
+
+    Ok((ordinal, weekday))
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|d| d.pred_opt())
+        .map(|d| d.day())
+        .unwrap_or(28)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn dates(rule: &str, start: &str, last: &str) -> Vec<String> {
+        RecurrenceRule::parse(rule)
+            .unwrap()
+            .dates(date(start), date(last))
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn weekly_by_day_with_count() {
+        // 2024-03-04 is a Monday
+        assert_eq!(
+            dates(
+                "FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=5",
+                "2024-03-04",
+                "2025-01-01"
+            ),
+            [
+                "2024-03-04",
+                "2024-03-06",
+                "2024-03-08",
+                "2024-03-11",
+                "2024-03-13"
+            ]
+        );
+        assert_eq!(
+            dates("FREQ=WEEKLY;INTERVAL=2", "2024-03-04", "2024-04-01"),
+            ["2024-03-04", "2024-03-18", "2024-04-01"]
+        );
+    }
+
+    #[test]
+    fn monthly_by_ordinal_weekday_and_month_day() {
+        assert_eq!(
+            dates(
+                "FREQ=MONTHLY;BYDAY=-1FR;COUNT=3",
+                "2024-01-26",
+                "2025-01-01"
+            ),
+            ["2024-01-26", "2024-02-23", "2024-03-29"]
+        );
+        // Months without a 31st are skipped rather than moved
+        assert_eq!(
+            dates("FREQ=MONTHLY", "2024-01-31", "2024-05-31"),
+            ["2024-01-31", "2024-03-31", "2024-05-31"]
+        );
+        assert_eq!(
+            dates("FREQ=MONTHLY;BYMONTHDAY=1,-1", "2024-02-01", "2024-03-31"),
+            ["2024-02-01", "2024-02-29", "2024-03-01", "2024-03-31"]
+        );
+    }
+
+    #[test]
+    fn daily_and_yearly() {
+        assert_eq!(
+            dates("FREQ=DAILY;BYDAY=SA,SU", "2024-03-01", "2024-03-10"),
+            [
+                "2024-03-01",
+                "2024-03-02",
+                "2024-03-03",
+                "2024-03-09",
+                "2024-03-10"
+            ]
+        );
+        assert_eq!(
+            dates(
+                "FREQ=YEARLY;BYMONTH=6;BYDAY=2MO",
+                "2024-06-10",
+                "2026-12-31"
+            ),
+            ["2024-06-10", "2025-06-09", "2026-06-08"]
+        );
+    }
+
+    #[test]
+    fn unsupported_rules_are_rejected() {
+        assert!(RecurrenceRule::parse("FREQ=HOURLY").is_err());
+        assert!(RecurrenceRule::parse("FREQ=MONTHLY;BYSETPOS=-1").is_err());
+        assert!(RecurrenceRule::parse("FREQ=WEEKLY;BYDAY=2MO").is_err());
+        assert!(RecurrenceRule::parse("BYDAY=MO").is_err());
+    }
+
+    #[test]
+    fn non_ascii_weekdays_are_errors() {
+        for value in ["€", "1€", "M€", "日曜"] {
+            let rule = format!("FREQ=WEEKLY;BYDAY={}", value);
+            assert!(RecurrenceRule::parse(&rule).is_err(), "{}", rule);
+        }
+    }
+}
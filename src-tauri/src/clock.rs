@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+#[cfg(test)]
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+/// Time sources for the lock. Wall-clock time is whatever the user set it
+/// to; uptime can't be changed from the OS settings, which is what makes
+/// clock changes detectable.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Seconds since boot, including time spent asleep. Starts over after
+    /// a reboot.
+    fn uptime(&self) -> u64;
+}
+
+/// Most powered-off time credited across a reboot. Only the wall clock
+/// tells how long the machine was off, and it may have been set forward
+/// meanwhile; a night switched off counts, a week doesn't.
+pub const MAX_POWERED_OFF_SECS: i64 = 12 * 3600;
+
+/// Seconds since `last_observed`, seen before a reboot: this boot's uptime
+/// plus the time the machine was off, at most `MAX_POWERED_OFF_SECS`.
+pub fn elapsed_across_reboot(last_observed: DateTime<Utc>, now: DateTime<Utc>, uptime: i64) -> i64 {
+    let powered_off = (now - last_observed).num_seconds() - uptime;
+    uptime + powered_off.clamp(0, MAX_POWERED_OFF_SECS)
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn uptime(&self) -> u64 {
+        sysinfo::System::uptime()
+    }
+
+    // sysinfo isn't built for Android, and the lock runs in the plugin there
+    #[cfg(target_os = "android")]
+    fn uptime(&self) -> u64 {
+        use std::sync::OnceLock;
+        use std::time::Instant;
+
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_secs()
+    }
+}
+
+/// Clock for tests; both times only move when told to.
+#[cfg(test)]
+pub struct FakeClock {
+    now: Mutex<DateTime<Utc>>,
+    uptime: AtomicU64,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+            uptime: AtomicU64::new(1000),
+        }
+    }
+
+    /// Real time passing: both clocks move together.
+    pub fn advance(&self, seconds: i64) {
+        *self.now.lock().unwrap() += chrono::Duration::seconds(seconds);
+        self.uptime.fetch_add(seconds as u64, Ordering::SeqCst);
+    }
+
+    /// The user changing the system clock.
+    pub fn set_now(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// A reboot that took `seconds`, with uptime starting over.
+    pub fn reboot(&self, seconds: i64) {
+        *self.now.lock().unwrap() += chrono::Duration::seconds(seconds);
+        self.uptime.store(0, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+
+    fn uptime(&self) -> u64 {
+        self.uptime.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,499 @@
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DbError {
+    #[error("Database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(
+        "focuslock.db is at schema version {found}, but this version of FocusLock only \
+         understands up to {supported}. Update FocusLock to open it."
+    )]
+    TooNew { found: u32, supported: u32 },
+}
+
+/// One step of the focuslock.db schema. Applied once, in version order,
+/// and recorded in SQLite's `user_version`.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// Every schema change, oldest first. Append new steps here with the next
+/// version; never edit one that has shipped.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline schema",
+        up: baseline,
+    },
+    Migration {
+        version: 2,
+        description: "track elapsed lock time",
+        up: lock_elapsed_time,
+    },
+    Migration {
+        version: 3,
+        description: "emergency unlock requests",
+        up: emergency_requests,
+    },
+    Migration {
+        version: 4,
+        description: "recurring schedules",
+        up: schedules,
+    },
+    Migration {
+        version: 5,
+        description: "locks planned from calendars",
+        up: planned_locks,
+    },
+    Migration {
+        version: 6,
+        description: "interval mode",
+        up: pomodoro,
+    },
+    Migration {
+        version: 7,
+        description: "daily usage budgets",
+        up: budgets,
+    },
+    Migration {
+        version: 8,
+        description: "blocking profiles",
+        up: profiles,
+    },
+    Migration {
+        version: 9,
+        description: "trusted clock anchor",
+        up: clock_anchor,
+    },
+];
+
+/// Schema version this build writes.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Opens focuslock.db in the app data dir, migrated to the latest schema.
+/// Every subsystem gets its connection from here.
+pub fn open(app_data_dir: &Path) -> Result<Connection, DbError> {
+    std::fs::create_dir_all(app_data_dir).ok();
+    let mut conn = Connection::open(app_data_dir.join("focuslock.db"))?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// Applies the pending migrations in a single transaction, so a failure
+/// leaves the database as it was. Returns the resulting version.
+pub fn migrate(conn: &mut Connection) -> Result<u32, DbError> {
+    let latest = latest_version();
+
+    // Cheap check first; every subsystem opens the file at startup
+    let current: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if current == latest {
+        return Ok(current);
+    }
+
+    // Immediate, so two connections can't both decide to migrate
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let current: u32 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if current > latest {
+        return Err(DbError::TooNew {
+            found: current,
+            supported: latest,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        log::info!(
+            "Migrating database to version {}: {}",
+            migration.version,
+            migration.description
+        );
+        (migration.up)(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", latest)?;
+    tx.commit()?;
+
+    Ok(latest)
+}
+
+fn table_exists(tx: &Transaction, table: &str) -> rusqlite::Result<bool> {
+    tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [table],
+        |row| row.get(0),
+    )
+}
+
+fn column_exists(tx: &Transaction, table: &str, column: &str) -> rusqlite::Result<bool> {
+    tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        [table, column],
+        |row| row.get(0),
+    )
+}
+
+/// Everything that existed before the schema was versioned. Databases
+/// from those builds are at version 0 with some of these tables already
+/// present, so every statement has to tolerate that.
+fn baseline(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS lock_state (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            unlock_time TEXT,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started_at TEXT NOT NULL,
+            planned_end TEXT NOT NULL,
+            ended_at TEXT,
+            end_reason TEXT
+        );
+        CREATE TABLE IF NOT EXISTS hosts_tamper_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            detected_at TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            restored INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS blocked_attempts (
+            domain TEXT PRIMARY KEY,
+            count INTEGER NOT NULL,
+            last_attempt TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS suspended_processes (
+            pid INTEGER PRIMARY KEY,
+            start_time INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            rule_id INTEGER NOT NULL,
+            root_pid INTEGER NOT NULL,
+            suspended_at TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            name TEXT,
+            pid INTEGER,
+            path TEXT,
+            detail TEXT,
+            timestamp TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS events_kind_timestamp ON events (kind, timestamp);",
+    )?;
+
+    // Seed the built-in lists only with their table, so a user who empties
+    // one keeps it empty
+    let seed_domains = !table_exists(tx, "blocked_domains")?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS blocked_domains (
+            domain TEXT PRIMARY KEY,
+            group_name TEXT NOT NULL,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    if seed_domains {
+        seed_blocked_domains(tx)?;
+    }
+
+    let seed_processes = !table_exists(tx, "process_rules")?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS process_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP,
+            action TEXT NOT NULL DEFAULT 'kill',
+            UNIQUE (kind, pattern)
+        )",
+        [],
+    )?;
+    // Tables from before actions existed lack the column
+    if !column_exists(tx, "process_rules", "action")? {
+        tx.execute(
+            "ALTER TABLE process_rules ADD COLUMN action TEXT NOT NULL DEFAULT 'kill'",
+            [],
+        )?;
+    }
+    if seed_processes {
+        seed_process_rules(tx)?;
+    }
+
+    Ok(())
+}
+
+/// Locks count down by measured elapsed time instead of comparing the
+/// wall clock to `unlock_time`. A lock running during the upgrade is
+/// credited with the wall-clock time since it started.
+fn lock_elapsed_time(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE lock_state ADD COLUMN duration_seconds INTEGER;
+        ALTER TABLE lock_state ADD COLUMN elapsed_seconds INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE lock_state ADD COLUMN last_observed TEXT;
+        ALTER TABLE lock_state ADD COLUMN last_uptime INTEGER;
+        UPDATE lock_state SET
+            duration_seconds =
+                CAST(ROUND((julianday(unlock_time) - julianday(created_at)) * 86400) AS INTEGER),
+            elapsed_seconds =
+                MAX(0, CAST((julianday('now') - julianday(created_at)) * 86400 AS INTEGER));",
+    )
+}
+
+fn emergency_requests(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE emergency_requests (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            requested_at TEXT NOT NULL,
+            requested_uptime INTEGER NOT NULL,
+            delay_seconds INTEGER NOT NULL,
+            challenge TEXT,
+            status TEXT NOT NULL,
+            resolved_at TEXT
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Weekdays are a bitmask with Monday as bit 0; times are local "HH:MM"
+/// in `timezone`, or the system timezone when it is NULL.
+fn schedules(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE schedules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            weekdays INTEGER NOT NULL,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            timezone TEXT,
+            enabled INTEGER NOT NULL DEFAULT 1,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// One row per occurrence; `started` is set once it has become a lock, so
+/// a re-import never plans it twice.
+fn planned_locks(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE planned_locks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            uid TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            starts_at TEXT NOT NULL,
+            ends_at TEXT NOT NULL,
+            source TEXT NOT NULL,
+            started INTEGER NOT NULL DEFAULT 0,
+            UNIQUE (uid, starts_at)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// The running interval mode, if any; a single row like `lock_state`.
+fn pomodoro(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE pomodoro (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            work_minutes INTEGER NOT NULL,
+            break_minutes INTEGER NOT NULL,
+            long_break_minutes INTEGER NOT NULL,
+            long_break_every INTEGER NOT NULL,
+            intervals INTEGER NOT NULL,
+            break_group TEXT,
+            phase TEXT NOT NULL,
+            completed INTEGER NOT NULL,
+            phase_started_at TEXT NOT NULL,
+            phase_started_uptime INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// `domains` and `processes` are newline-separated lists. `day` is the
+/// budget day a usage row counts towards, "YYYY-MM-DD" of the local date
+/// the day started on, which depends on the configured reset time.
+fn budgets(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE budgets (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            domains TEXT NOT NULL,
+            processes TEXT NOT NULL,
+            daily_minutes INTEGER NOT NULL,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE budget_usage (
+            budget_id INTEGER NOT NULL,
+            day TEXT NOT NULL,
+            used_seconds INTEGER NOT NULL,
+            PRIMARY KEY (budget_id, day)
+        );",
+    )?;
+    Ok(())
+}
+
+/// Named sets of domain and process rules a lock can block with instead
+/// of the global lists. `lock_state.profile_id` is NULL for those.
+fn profiles(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE profiles (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            allow_emergency_unlock INTEGER NOT NULL DEFAULT 1,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE profile_domains (
+            profile_id INTEGER NOT NULL,
+            domain TEXT NOT NULL,
+            PRIMARY KEY (profile_id, domain)
+        );
+
+        CREATE TABLE profile_process_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            profile_id INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            action TEXT NOT NULL,
+            UNIQUE (profile_id, kind, pattern)
+        );
+
+        ALTER TABLE lock_state ADD COLUMN profile_id INTEGER;",
+    )?;
+    Ok(())
+}
+
+/// Where `TimeLock::now` measures from: the wall time taken as true at
+/// `uptime`, and the latest time it handed out with the uptime then.
+fn clock_anchor(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE clock_anchor (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            observed TEXT NOT NULL,
+            uptime INTEGER NOT NULL,
+            checked TEXT NOT NULL,
+            checked_uptime INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+fn seed_blocked_domains(tx: &Transaction) -> rusqlite::Result<()> {
+    for (group, domain) in crate::blocker::blocklist::default_blocked_domains() {
+        tx.execute(
+            "INSERT OR IGNORE INTO blocked_domains (domain, group_name) VALUES (?1, ?2)",
+            [domain, group],
+        )?;
+    }
+    log::info!("Seeded blocklist with built-in domains");
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+fn seed_process_rules(tx: &Transaction) -> rusqlite::Result<()> {
+    use crate::blocker::process_rules::{default_target_processes, RuleKind};
+
+    for name in default_target_processes() {
+        tx.execute(
+            "INSERT OR IGNORE INTO process_rules (kind, pattern) VALUES (?1, ?2)",
+            [RuleKind::Name.as_str(), name],
+        )?;
+    }
+    log::info!("Seeded process rules with built-in targets");
+    Ok(())
+}
+
+// The blocker (and its built-in lists) is desktop-only
+#[cfg(target_os = "android")]
+fn seed_blocked_domains(_tx: &Transaction) -> rusqlite::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "android")]
+fn seed_process_rules(_tx: &Transaction) -> rusqlite::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn fresh_database_is_migrated_and_seeded() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), latest_version());
+        assert!(count(&conn, "blocked_domains") > 0);
+        assert!(count(&conn, "process_rules") > 0);
+
+        // Running again is a no-op
+        assert_eq!(migrate(&mut conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn unversioned_database_keeps_its_data() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE blocked_domains (
+                domain TEXT PRIMARY KEY,
+                group_name TEXT NOT NULL,
+                created_at TEXT DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE process_rules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                pattern TEXT NOT NULL,
+                created_at TEXT DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (kind, pattern)
+            );
+            INSERT INTO process_rules (kind, pattern) VALUES ('name', 'game.exe');",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        // Emptied list stays empty, old rules get the default action
+        assert_eq!(count(&conn, "blocked_domains"), 0);
+        let action: String = conn
+            .query_row("SELECT action FROM process_rules", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(action, "kill");
+    }
+
+    #[test]
+    fn newer_database_is_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+
+        match migrate(&mut conn) {
+            Err(DbError::TooNew { found, supported }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(supported, latest_version());
+            }
+            other => panic!("expected TooNew, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,601 @@
+use crate::clock::{self, Clock, SystemClock};
+use crate::db::{self, DbError};
+use chrono::{DateTime, Datelike, Duration, Local, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+
+const MAX_DELAY_MINUTES: u32 = 24 * 60;
+const MAX_CHALLENGE_LENGTH: u32 = 5000;
+
+/// Words the typing challenge is built from. Plain and lowercase, so the
+/// effort goes into typing rather than deciphering.
+const CHALLENGE_WORDS: &str = "\
+    focus quiet morning river window paper garden steady patience moment breathe \
+    simple choice evening careful promise practice silence honest balance journey \
+    gentle purpose attention future habit effort return notice clear reason calm \
+    distance mountain letter harbor lantern orchard meadow thunder compass anchor \
+    season shelter horizon kettle blanket pencil";
+
+#[derive(Error, Debug)]
+pub enum EmergencyError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("No lock is running")]
+    NotLocked,
+    #[error("Instant emergency unlock is disabled; request one and wait instead")]
+    InstantDisabled,
+    #[error("No emergency unlock has been requested")]
+    NotRequested,
+    #[error("Emergency unlock is available at {0}")]
+    TooEarly(DateTime<Utc>),
+    #[error("The typed text does not match the challenge")]
+    ChallengeFailed,
+    #[error("All {0} emergency unlocks for this month are used")]
+    QuotaExhausted(u32),
+    #[error("The emergency unlock policy can only be made stricter while a lock is active")]
+    Locked,
+}
+
+/// How hard it is to end a lock early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmergencyPolicy {
+    /// Allows `emergency_unlock` to end the lock on the spot
+    pub allow_instant: bool,
+    /// Minutes between requesting an unlock and being able to confirm it
+    pub delay_minutes: u32,
+    /// Characters of generated text to retype on confirm; 0 skips it
+    pub challenge_length: u32,
+    /// Emergency unlocks per calendar month; `None` is unlimited
+    pub monthly_quota: Option<u32>,
+}
+
+impl Default for EmergencyPolicy {
+    fn default() -> Self {
+        // Ending a lock on the spot, as before policies existed; the rest
+        // is opted into
+        Self {
+            allow_instant: true,
+            delay_minutes: 0,
+            challenge_length: 0,
+            monthly_quota: None,
+        }
+    }
+}
+
+impl EmergencyPolicy {
+    pub fn clamped(self) -> Self {
+        Self {
+            delay_minutes: self.delay_minutes.min(MAX_DELAY_MINUTES),
+            challenge_length: self.challenge_length.min(MAX_CHALLENGE_LENGTH),
+            ..self
+        }
+    }
+
+    /// True when nothing in `self` is easier to get past than in `other`.
+    pub fn is_at_least_as_strict_as(&self, other: &Self) -> bool {
+        let quota_ok = match (self.monthly_quota, other.monthly_quota) {
+            (Some(quota), Some(other)) => quota <= other,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => true,
+        };
+        (!self.allow_instant || other.allow_instant)
+            && self.delay_minutes >= other.delay_minutes
+            && self.challenge_length >= other.challenge_length
+            && quota_ok
+    }
+}
+
+/// A pending request to end the lock early.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyRequest {
+    pub id: i64,
+    pub requested_at: DateTime<Utc>,
+    pub available_at: DateTime<Utc>,
+    /// Text to type back on confirm, if the policy asks for it
+    pub challenge: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmergencyStatus {
+    pub policy: EmergencyPolicy,
+    pub pending: Option<EmergencyRequest>,
+    pub used_this_month: u32,
+    /// `None` when the policy sets no quota
+    pub remaining_this_month: Option<u32>,
+}
+
+/// Emergency unlock requests and the policy they are checked against.
+/// Requests live in focuslock.db so restarting the app neither resets the
+/// wait nor the monthly count.
+pub struct EmergencyUnlock {
+    db: Mutex<Connection>,
+    clock: Arc<dyn Clock>,
+    policy: RwLock<EmergencyPolicy>,
+}
+
+impl EmergencyUnlock {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?, Arc::new(SystemClock))
+    }
+
+    pub fn from_connection(mut conn: Connection, clock: Arc<dyn Clock>) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+            clock,
+            policy: RwLock::new(EmergencyPolicy::default()),
+        })
+    }
+
+    pub fn policy(&self) -> EmergencyPolicy {
+        *self.policy.read().unwrap()
+    }
+
+    /// Replaces the policy; while `locked`, only with a stricter one.
+    /// Returns the policy as applied.
+    pub fn set_policy(
+        &self,
+        policy: EmergencyPolicy,
+        locked: bool,
+    ) -> Result<EmergencyPolicy, EmergencyError> {
+        let mut current = self.policy.write().unwrap();
+        let policy = allowed_policy(policy, &current, locked)?;
+        *current = policy;
+        Ok(policy)
+    }
+
+    /// The policy `set_policy` would apply, without applying it, so it can
+    /// be stored first.
+    pub fn check_policy(
+        &self,
+        policy: EmergencyPolicy,
+        locked: bool,
+    ) -> Result<EmergencyPolicy, EmergencyError> {
+        allowed_policy(policy, &self.policy.read().unwrap(), locked)
+    }
+
+    pub fn status(&self) -> Result<EmergencyStatus, EmergencyError> {
+        let policy = self.policy();
+        let db = self.db.lock().unwrap();
+        let used = self.used_this_month(&db)?;
+
+        Ok(EmergencyStatus {
+            policy,
+            pending: pending(&db)?,
+            used_this_month: used,
+            remaining_this_month: policy.monthly_quota.map(|q| q.saturating_sub(used)),
+        })
+    }
+
+    /// Starts the waiting period, or returns the request already waiting.
+    pub fn request(&self, locked: bool) -> Result<EmergencyRequest, EmergencyError> {
+        if !locked {
+            return Err(EmergencyError::NotLocked);
+        }
+        let policy = self.policy();
+        let db = self.db.lock().unwrap();
+
+        if let Some(request) = pending(&db)? {
+            return Ok(request);
+        }
+        self.check_quota(&db, &policy)?;
+
+        let now = self.clock.now();
+        let available_at = now + Duration::minutes(policy.delay_minutes as i64);
+        let challenge = (policy.challenge_length > 0)
+            .then(|| generate_challenge(policy.challenge_length as usize));
+        db.execute(
+            "INSERT INTO emergency_requests
+                (requested_at, requested_uptime, delay_seconds, challenge, status)
+             VALUES (?1, ?2, ?3, ?4, 'pending')",
+            params![
+                now.to_rfc3339(),
+                self.clock.uptime() as i64,
+                policy.delay_minutes as i64 * 60,
+                challenge
+            ],
+        )?;
+
+        log::info!("Emergency unlock requested, available at {}", available_at);
+        Ok(EmergencyRequest {
+            id: db.last_insert_rowid(),
+            requested_at: now,
+            available_at,
+            challenge,
+        })
+    }
+
+    pub fn cancel(&self) -> Result<(), EmergencyError> {
+        let db = self.db.lock().unwrap();
+        let cancelled = resolve_pending(&db, "cancelled", self.clock.now())?;
+        if cancelled > 0 {
+            log::info!("Emergency unlock request cancelled");
+        }
+        Ok(())
+    }
+
+    /// Checks the pending request against the policy. The caller ends the
+    /// lock on success, then calls `record_confirmed`.
+    pub fn confirm(&self, typed: Option<&str>, locked: bool) -> Result<(), EmergencyError> {
+        if !locked {
+            return Err(EmergencyError::NotLocked);
+        }
+        let policy = self.policy();
+        let db = self.db.lock().unwrap();
+
+        let Some((request, requested_uptime, delay_seconds)) = pending_row(&db)? else {
+            return Err(EmergencyError::NotRequested);
+        };
+        // Waited time is measured like lock time, so moving the clock
+        // forward doesn't skip the wait
+        if self.waited_seconds(request.requested_at, requested_uptime) < delay_seconds {
+            return Err(EmergencyError::TooEarly(request.available_at));
+        }
+        if let Some(challenge) = &request.challenge {
+            if typed.map(str::trim) != Some(challenge.as_str()) {
+                return Err(EmergencyError::ChallengeFailed);
+            }
+        }
+        self.check_quota(&db, &policy)?;
+        Ok(())
+    }
+
+    /// Uses up the pending request once the lock it ended is cleared.
+    pub fn record_confirmed(&self) -> Result<(), EmergencyError> {
+        let db = self.db.lock().unwrap();
+        resolve_pending(&db, "used", self.clock.now())?;
+        log::info!("Emergency unlock confirmed");
+        Ok(())
+    }
+
+    /// The one-call path, when the policy still allows it. The caller ends
+    /// the lock on success, then calls `record_instant`.
+    pub fn instant(&self, locked: bool) -> Result<(), EmergencyError> {
+        if !locked {
+            return Err(EmergencyError::NotLocked);
+        }
+        let policy = self.policy();
+        if !policy.allow_instant {
+            return Err(EmergencyError::InstantDisabled);
+        }
+        let db = self.db.lock().unwrap();
+        self.check_quota(&db, &policy)
+    }
+
+    /// Counts an instant unlock against the quota once the lock is cleared.
+    pub fn record_instant(&self) -> Result<(), EmergencyError> {
+        let db = self.db.lock().unwrap();
+        let now = self.clock.now();
+        let tx = db.unchecked_transaction()?;
+        resolve_pending(&tx, "cancelled", now)?;
+        tx.execute(
+            "INSERT INTO emergency_requests
+                (requested_at, requested_uptime, delay_seconds, status, resolved_at)
+             VALUES (?1, ?2, 0, 'used', ?1)",
+            params![now.to_rfc3339(), self.clock.uptime() as i64],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn check_quota(&self, db: &Connection, policy: &EmergencyPolicy) -> Result<(), EmergencyError> {
+        match policy.monthly_quota {
+            Some(quota) if self.used_this_month(db)? >= quota => {
+                Err(EmergencyError::QuotaExhausted(quota))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn used_this_month(&self, db: &Connection) -> SqliteResult<u32> {
+        let now = self.clock.now().with_timezone(&Local);
+        let month_start = Local
+            .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|| now.with_timezone(&Utc) - Duration::days(31));
+
+        db.query_row(
+            "SELECT COUNT(*) FROM emergency_requests WHERE status = 'used' AND resolved_at >= ?1",
+            [month_start.to_rfc3339()],
+            |row| row.get(0),
+        )
+    }
+
+    fn waited_seconds(&self, requested_at: DateTime<Utc>, requested_uptime: i64) -> i64 {
+        let uptime = self.clock.uptime() as i64;
+        if uptime >= requested_uptime {
+            uptime - requested_uptime
+        } else {
+            // Rebooted since the request
+            clock::elapsed_across_reboot(requested_at, self.clock.now(), uptime)
+        }
+    }
+}
+
+/// `policy` clamped, unless it loosens `current` during a lock.
+fn allowed_policy(
+    policy: EmergencyPolicy,
+    current: &EmergencyPolicy,
+    locked: bool,
+) -> Result<EmergencyPolicy, EmergencyError> {
+    let policy = policy.clamped();
+    if locked && !policy.is_at_least_as_strict_as(current) {
+        return Err(EmergencyError::Locked);
+    }
+    Ok(policy)
+}
+
+fn pending(db: &Connection) -> SqliteResult<Option<EmergencyRequest>> {
+    Ok(pending_row(db)?.map(|(request, _, _)| request))
+}
+
+/// The pending request with the uptime it was made at and its delay.
+fn pending_row(db: &Connection) -> SqliteResult<Option<(EmergencyRequest, i64, i64)>> {
+    db.query_row(
+        "SELECT id, requested_at, requested_uptime, delay_seconds, challenge
+         FROM emergency_requests WHERE status = 'pending'
+         ORDER BY id DESC LIMIT 1",
+        [],
+        |row| {
+            let requested_at: String = row.get(1)?;
+            let requested_at = DateTime::parse_from_rfc3339(&requested_at)
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or_default();
+            let delay_seconds: i64 = row.get(3)?;
+            let request = EmergencyRequest {
+                id: row.get(0)?,
+                requested_at,
+                available_at: requested_at + Duration::seconds(delay_seconds),
+                challenge: row.get(4)?,
+            };
+            Ok((request, row.get(2)?, delay_seconds))
+        },
+    )
+    .optional()
+}
+
+fn resolve_pending(db: &Connection, status: &str, at: DateTime<Utc>) -> SqliteResult<usize> {
+    db.execute(
+        "UPDATE emergency_requests SET status = ?1, resolved_at = ?2 WHERE status = 'pending'",
+        params![status, at.to_rfc3339()],
+    )
+}
+
+/// Random words up to `length` characters. Not secret, just too long to
+/// type without meaning it.
+fn generate_challenge(length: usize) -> String {
+    let words: Vec<&str> = CHALLENGE_WORDS.split_whitespace().collect();
+    let state = RandomState::new();
+    let mut text = String::with_capacity(length + 16);
+    let mut counter = 0u64;
+
+    while text.len() < length {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(counter);
+        counter += 1;
+        let word = words[(hasher.finish() % words.len() as u64) as usize];
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    fn emergency(policy: EmergencyPolicy) -> (EmergencyUnlock, Arc<FakeClock>) {
+        let clock = Arc::new(FakeClock::new(
+            DateTime::parse_from_rfc3339("2024-03-10T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        ));
+        let emergency =
+            EmergencyUnlock::from_connection(Connection::open_in_memory().unwrap(), clock.clone())
+                .unwrap();
+        emergency.set_policy(policy, false).unwrap();
+        (emergency, clock)
+    }
+
+    #[test]
+    fn confirm_waits_for_the_delay() {
+        let (emergency, clock) = emergency(EmergencyPolicy {
+            delay_minutes: 30,
+            ..Default::default()
+        });
+
+        let request = emergency.request(true).unwrap();
+        assert!(matches!(
+            emergency.confirm(None, true),
+            Err(EmergencyError::TooEarly(at)) if at == request.available_at
+        ));
+
+        // A clock change doesn't count as waiting
+        clock.set_now(request.available_at + Duration::hours(1));
+        assert!(matches!(
+            emergency.confirm(None, true),
+            Err(EmergencyError::TooEarly(_))
+        ));
+
+        clock.advance(30 * 60);
+        // With no lock running there is nothing to unlock
+        assert!(matches!(
+            emergency.confirm(None, false),
+            Err(EmergencyError::NotLocked)
+        ));
+        emergency.confirm(None, true).unwrap();
+        // Still pending until the lock is cleared
+        emergency.confirm(None, true).unwrap();
+        emergency.record_confirmed().unwrap();
+        assert!(matches!(
+            emergency.confirm(None, true),
+            Err(EmergencyError::NotRequested)
+        ));
+        assert_eq!(emergency.status().unwrap().used_this_month, 1);
+    }
+
+    #[test]
+    fn clock_set_forward_before_a_reboot_is_not_waiting() {
+        let (emergency, clock) = emergency(EmergencyPolicy {
+            delay_minutes: 12 * 60 + 10,
+            ..Default::default()
+        });
+
+        let request = emergency.request(true).unwrap();
+        clock.set_now(request.requested_at + Duration::days(7));
+        clock.reboot(60);
+        assert!(matches!(
+            emergency.confirm(None, true),
+            Err(EmergencyError::TooEarly(_))
+        ));
+
+        // Powered-off time counts up to the cap
+        clock.advance((12 * 60 + 10) * 60 - clock::MAX_POWERED_OFF_SECS);
+        emergency.confirm(None, true).unwrap();
+    }
+
+    #[test]
+    fn cancelled_request_cannot_be_confirmed() {
+        let (emergency, clock) = emergency(EmergencyPolicy {
+            delay_minutes: 30,
+            ..Default::default()
+        });
+
+        emergency.request(true).unwrap();
+        emergency.cancel().unwrap();
+        clock.advance(60 * 60);
+
+        assert!(matches!(
+            emergency.confirm(None, true),
+            Err(EmergencyError::NotRequested)
+        ));
+        assert_eq!(emergency.status().unwrap().used_this_month, 0);
+    }
+
+    #[test]
+    fn challenge_must_be_typed_back() {
+        let (emergency, _) = emergency(EmergencyPolicy {
+            delay_minutes: 0,
+            challenge_length: 200,
+            ..Default::default()
+        });
+
+        let challenge = emergency.request(true).unwrap().challenge.unwrap();
+        assert!(challenge.len() >= 200);
+        assert!(matches!(
+            emergency.confirm(Some("focus"), true),
+            Err(EmergencyError::ChallengeFailed)
+        ));
+        assert!(matches!(
+            emergency.confirm(None, true),
+            Err(EmergencyError::ChallengeFailed)
+        ));
+        emergency.confirm(Some(&challenge), true).unwrap();
+    }
+
+    #[test]
+    fn monthly_quota_is_enforced() {
+        let (emergency, clock) = emergency(EmergencyPolicy {
+            monthly_quota: Some(2),
+            ..Default::default()
+        });
+
+        // Only unlocks that were carried out count
+        emergency.instant(true).unwrap();
+        assert_eq!(emergency.status().unwrap().used_this_month, 0);
+        for _ in 0..2 {
+            emergency.instant(true).unwrap();
+            emergency.record_instant().unwrap();
+        }
+        assert!(matches!(
+            emergency.instant(true),
+            Err(EmergencyError::QuotaExhausted(2))
+        ));
+        assert!(matches!(
+            emergency.request(true),
+            Err(EmergencyError::QuotaExhausted(2))
+        ));
+        assert_eq!(emergency.status().unwrap().remaining_this_month, Some(0));
+
+        // A new month brings them back
+        clock.advance(31 * 24 * 60 * 60);
+        emergency.instant(true).unwrap();
+    }
+
+    #[test]
+    fn request_needs_a_lock() {
+        let (emergency, _) = emergency(EmergencyPolicy::default());
+        assert!(matches!(
+            emergency.request(false),
+            Err(EmergencyError::NotLocked)
+        ));
+    }
+
+    #[test]
+    fn instant_path_follows_the_policy() {
+        let (emergency, _) = emergency(EmergencyPolicy::default());
+        emergency.instant(true).unwrap();
+
+        emergency
+            .set_policy(
+                EmergencyPolicy {
+                    allow_instant: false,
+                    ..Default::default()
+                },
+                false,
+            )
+            .unwrap();
+        assert!(matches!(
+            emergency.instant(true),
+            Err(EmergencyError::InstantDisabled)
+        ));
+    }
+
+    #[test]
+    fn policy_only_tightens_during_a_lock() {
+        let (emergency, _) = emergency(EmergencyPolicy {
+            allow_instant: false,
+            delay_minutes: 30,
+            ..Default::default()
+        });
+
+        let looser = EmergencyPolicy {
+            allow_instant: false,
+            delay_minutes: 5,
+            ..Default::default()
+        };
+        assert!(matches!(
+            emergency.set_policy(looser, true),
+            Err(EmergencyError::Locked)
+        ));
+        let instant = EmergencyPolicy {
+            delay_minutes: 60,
+            ..Default::default()
+        };
+        assert!(matches!(
+            emergency.set_policy(instant, true),
+            Err(EmergencyError::Locked)
+        ));
+
+        let stricter = EmergencyPolicy {
+            allow_instant: false,
+            delay_minutes: 60,
+            monthly_quota: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(emergency.set_policy(stricter, true).unwrap(), stricter);
+        assert_eq!(emergency.set_policy(looser, false).unwrap(), looser);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::db::{self, DbError};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+
+/// Event types; `as_str` is both the Tauri event name and the stored kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    ProcessBlocked,
+    LockStarted,
+    LockExpired,
+    HostsTampered,
+    PomodoroPhase,
+    BudgetExhausted,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::ProcessBlocked => "process-blocked",
+            EventKind::LockStarted => "lock-started",
+            EventKind::LockExpired => "lock-expired",
+            EventKind::HostsTampered => "hosts-tampered",
+            EventKind::PomodoroPhase => "pomodoro-phase",
+            EventKind::BudgetExhausted => "budget-exhausted",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "process-blocked" => Some(EventKind::ProcessBlocked),
+            "lock-started" => Some(EventKind::LockStarted),
+            "lock-expired" => Some(EventKind::LockExpired),
+            "hosts-tampered" => Some(EventKind::HostsTampered),
+            "pomodoro-phase" => Some(EventKind::PomodoroPhase),
+            "budget-exhausted" => Some(EventKind::BudgetExhausted),
+            _ => None,
+        }
+    }
+}
+
+/// Payload of every event. Fields that don't apply to a kind are `None`:
+/// `name` is the process name for `process-blocked`, the tamper kind for
+/// `hosts-tampered`, the phase entered for `pomodoro-phase` and the budget
+/// for `budget-exhausted`; `detail` carries the rest (unlock time, restored
+/// flag, phase end, budget reset time).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppEvent {
+    pub id: i64,
+    pub kind: EventKind,
+    pub name: Option<String>,
+    pub pid: Option<u32>,
+    pub path: Option<String>,
+    pub detail: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// How often one name shows up, for "you tried to open X 4 times".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventCount {
+    pub name: String,
+    pub count: i64,
+    pub last_seen: DateTime<Utc>,
+}
+
+pub type EventEmitter = Box<dyn Fn(&AppEvent) + Send + Sync>;
+
+/// Records backend events in focuslock.db and forwards them to the
+/// frontend through the emitter installed at startup.
+pub struct EventBus {
+    db: Mutex<Connection>,
+    emitter: RwLock<Option<EventEmitter>>,
+}
+
+impl EventBus {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+            emitter: RwLock::new(None),
+        })
+    }
+
+    pub fn set_emitter(&self, emitter: EventEmitter) {
+        *self.emitter.write().unwrap() = Some(emitter);
+    }
+
+    pub fn process_blocked(&self, name: &str, pid: u32, path: Option<&str>) {
+        self.publish(EventKind::ProcessBlocked, Some(name), Some(pid), path, None);
+    }
+
+    pub fn lock_started(&self, unlock_time: Option<DateTime<Utc>>) {
+        let detail = unlock_time.map(|t| t.to_rfc3339());
+        self.publish(EventKind::LockStarted, None, None, None, detail.as_deref());
+    }
+
+    pub fn lock_expired(&self) {
+        self.publish(EventKind::LockExpired, None, None, None, None);
+    }
+
+    pub fn hosts_tampered(&self, kind: &str, restored: bool) {
+        let detail = if restored { "restored" } else { "not restored" };
+        self.publish(
+            EventKind::HostsTampered,
+            Some(kind),
+            None,
+            None,
+            Some(detail),
+        );
+    }
+
+    pub fn pomodoro_phase(&self, phase: &str, ends_at: Option<DateTime<Utc>>) {
+        let detail = ends_at.map(|t| t.to_rfc3339());
+        self.publish(
+            EventKind::PomodoroPhase,
+            Some(phase),
+            None,
+            None,
+            detail.as_deref(),
+        );
+    }
+
+    pub fn budget_exhausted(&self, budget: &str, resets_at: DateTime<Utc>) {
+        let detail = resets_at.to_rfc3339();
+        self.publish(
+            EventKind::BudgetExhausted,
+            Some(budget),
+            None,
+            None,
+            Some(&detail),
+        );
+    }
+
+    /// Stores the event, then emits it. A storage failure is logged and the
+    /// event is still emitted, with id 0.
+    fn publish(
+        &self,
+        kind: EventKind,
+        name: Option<&str>,
+        pid: Option<u32>,
+        path: Option<&str>,
+        detail: Option<&str>,
+    ) {
+        let timestamp = Utc::now();
+
+        let id = {
+            let db = self.db.lock().unwrap();
+            match db.execute(
+                "INSERT INTO events (kind, name, pid, path, detail, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    kind.as_str(),
+                    name,
+                    pid,
+                    path,
+                    detail,
+                    timestamp.to_rfc3339()
+                ],
+            ) {
+                Ok(_) => db.last_insert_rowid(),
+                Err(e) => {
+                    log::error!("Failed to record {} event: {}", kind.as_str(), e);
+                    0
+                }
+            }
+        };
+
+        let event = AppEvent {
+            id,
+            kind,
+            name: name.map(str::to_string),
+            pid,
+            path: path.map(str::to_string),
+            detail: detail.map(str::to_string),
+            timestamp,
+        };
+        if let Some(emitter) = self.emitter.read().unwrap().as_ref() {
+            emitter(&event);
+        }
+    }
+
+    /// Newest first, optionally only one kind.
+    pub fn history(&self, kind: Option<EventKind>, limit: u32) -> SqliteResult<Vec<AppEvent>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT id, kind, name, pid, path, detail, timestamp FROM events
+             WHERE ?1 IS NULL OR kind = ?1
+             ORDER BY id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![kind.map(|k| k.as_str()), limit], |row| {
+            let kind: String = row.get(1)?;
+            let timestamp: String = row.get(6)?;
+            let event = AppEvent {
+                id: row.get(0)?,
+                // Replaced below; rows of unknown kinds are dropped
+                kind: EventKind::ProcessBlocked,
+                name: row.get(2)?,
+                pid: row.get(3)?,
+                path: row.get(4)?,
+                detail: row.get(5)?,
+                timestamp: parse_timestamp(&timestamp),
+            };
+            Ok((kind, event))
+        })?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            let (kind, event) = row?;
+            match EventKind::parse(&kind) {
+                Some(kind) => events.push(AppEvent { kind, ..event }),
+                None => log::warn!("Ignoring event {} with unknown kind {}", event.id, kind),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Occurrences of `kind` per name since `since` (all time when `None`),
+    /// most frequent first.
+    pub fn counts(
+        &self,
+        kind: EventKind,
+        since: Option<DateTime<Utc>>,
+    ) -> SqliteResult<Vec<EventCount>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT name, COUNT(*), MAX(timestamp) FROM events
+             WHERE kind = ?1 AND name IS NOT NULL AND (?2 IS NULL OR timestamp >= ?2)
+             GROUP BY name ORDER BY COUNT(*) DESC, name",
+        )?;
+        let rows = stmt.query_map(
+            params![kind.as_str(), since.map(|t| t.to_rfc3339())],
+            |row| {
+                let last_seen: String = row.get(2)?;
+                Ok(EventCount {
+                    name: row.get(0)?,
+                    count: row.get(1)?,
+                    last_seen: parse_timestamp(&last_seen),
+                })
+            },
+        )?;
+
+        rows.collect()
+    }
+}
+
+fn parse_timestamp(value: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn bus() -> EventBus {
+        EventBus::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn published_events_are_stored_then_emitted() {
+        let bus = bus();
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let sink = emitted.clone();
+        bus.set_emitter(Box::new(move |event: &AppEvent| {
+            sink.lock().unwrap().push(event.clone())
+        }));
+
+        bus.process_blocked("game.exe", 42, Some("C:\\Games\\game.exe"));
+
+        let history = bus.history(None, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        let stored = &history[0];
+        assert_eq!(stored.kind, EventKind::ProcessBlocked);
+        assert_eq!(stored.name.as_deref(), Some("game.exe"));
+        assert_eq!(stored.pid, Some(42));
+        assert_eq!(stored.path.as_deref(), Some("C:\\Games\\game.exe"));
+
+        let emitted = emitted.lock().unwrap();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].id, stored.id);
+        assert_ne!(emitted[0].id, 0);
+    }
+
+    #[test]
+    fn history_is_newest_first_and_limited() {
+        let bus = bus();
+        bus.lock_started(None);
+        bus.hosts_tampered("removed", true);
+        bus.lock_expired();
+        bus.hosts_tampered("modified", false);
+
+        let kinds: Vec<EventKind> = bus
+            .history(None, 3)
+            .unwrap()
+            .iter()
+            .map(|e| e.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::HostsTampered,
+                EventKind::LockExpired,
+                EventKind::HostsTampered
+            ]
+        );
+
+        let tampers = bus.history(Some(EventKind::HostsTampered), 10).unwrap();
+        assert_eq!(tampers.len(), 2);
+        assert_eq!(tampers[0].name.as_deref(), Some("modified"));
+        assert_eq!(tampers[0].detail.as_deref(), Some("not restored"));
+        assert_eq!(tampers[1].detail.as_deref(), Some("restored"));
+
+        // Rows of kinds this build doesn't know are skipped
+        bus.db
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO events (kind, timestamp) VALUES ('from-the-future', ?1)",
+                [Utc::now().to_rfc3339()],
+            )
+            .unwrap();
+        assert_eq!(bus.history(None, 10).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn counts_group_one_kind_by_name() {
+        let bus = bus();
+        for _ in 0..3 {
+            bus.process_blocked("game.exe", 1, None);
+        }
+        bus.process_blocked("chat.exe", 2, None);
+        bus.hosts_tampered("game.exe", true);
+
+        let counts = bus.counts(EventKind::ProcessBlocked, None).unwrap();
+        let counts: Vec<(&str, i64)> = counts.iter().map(|c| (c.name.as_str(), c.count)).collect();
+        assert_eq!(counts, [("game.exe", 3), ("chat.exe", 1)]);
+
+        let later = Utc::now() + chrono::Duration::minutes(1);
+        assert!(bus
+            .counts(EventKind::ProcessBlocked, Some(later))
+            .unwrap()
+            .is_empty());
+    }
+}
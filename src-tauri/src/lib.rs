@@ -1,7 +1,28 @@
+mod clock;
+mod db;
+
 // Desktop-only modules
 #[cfg(not(target_os = "android"))]
 mod blocker;
 #[cfg(not(target_os = "android"))]
+mod budget;
+#[cfg(not(target_os = "android"))]
+mod calendar;
+#[cfg(not(target_os = "android"))]
+mod emergency;
+#[cfg(not(target_os = "android"))]
+mod events;
+#[cfg(not(target_os = "android"))]
+mod pomodoro;
+#[cfg(not(target_os = "android"))]
+mod profile;
+#[cfg(not(target_os = "android"))]
+mod schedule;
+#[cfg(not(target_os = "android"))]
+mod settings;
+#[cfg(not(target_os = "android"))]
+mod stats;
+#[cfg(not(target_os = "android"))]
 mod timelock;
 
 // Android plugin module
@@ -11,90 +32,1637 @@ mod android;
 mod timelock;
 
 #[cfg(not(target_os = "android"))]
-use blocker::{HostsBlocker, ProcessWatcher};
+use blocker::{
+    dns_flush::{self, SystemCommandRunner},
+    exe_hash,
+    import::{self, ImportReport},
+    BlockPageServer, BlockVerification, BlockedAttempt, BlockedDomain, Blocklist, DnsSinkhole,
+    DnsSinkholeConfig, DnsSinkholeStatus, FlushReport, HostsBlocker, HostsWatcher, KillEvent,
+    KillPolicy, KillStep, ProcessAction, ProcessRule, ProcessRuleStore, ProcessWatcher, RuleKind,
+    RuleSource, SinkholeAddresses, SuspendedProcess, TamperEvent,
+};
+#[cfg(not(target_os = "android"))]
+use budget::{Budget, BudgetInput, BudgetStore, BudgetUsage};
+#[cfg(not(target_os = "android"))]
+use calendar::{CalendarImportReport, EventFilter, Occurrence, PlannedLock, PlannedLockStore};
+#[cfg(not(target_os = "android"))]
+use emergency::{EmergencyPolicy, EmergencyRequest, EmergencyStatus, EmergencyUnlock};
+#[cfg(not(target_os = "android"))]
+use events::{AppEvent, EventBus, EventCount, EventKind};
+#[cfg(not(target_os = "android"))]
+use pomodoro::{Phase, Pomodoro, PomodoroConfig, PomodoroStatus};
+#[cfg(not(target_os = "android"))]
+use profile::{Profile, ProfileError, ProfileInput, ProfileStore};
+#[cfg(not(target_os = "android"))]
+use schedule::{Schedule, ScheduleInput, ScheduleStore, ScheduleWindow};
+#[cfg(not(target_os = "android"))]
+use settings::Settings;
+#[cfg(not(target_os = "android"))]
+use stats::{DayFocus, SessionStats, WeekFocus};
+#[cfg(not(target_os = "android"))]
+use timelock::{LockState, Session, TimeLock, TimeLockError};
+
+#[cfg(target_os = "android")]
+use timelock::LockState;
+
+#[cfg(not(target_os = "android"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_os = "android"))]
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_os = "android"))]
+use tauri::{Emitter, Manager, State};
+#[cfg(target_os = "android")]
+use tauri::Manager;
+
+#[cfg(not(target_os = "android"))]
+use tauri::{
+    image::Image,
+    menu::{Menu, MenuItem},
+    tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
+    WindowEvent,
+};
+
+#[cfg(not(target_os = "android"))]
+use tauri_plugin_autostart::MacosLauncher;
+
+#[cfg(not(target_os = "android"))]
+const SINKHOLE_SETTING: &str = "sinkhole_addresses";
+#[cfg(not(target_os = "android"))]
+const DNS_SINKHOLE_SETTING: &str = "dns_sinkhole";
+#[cfg(not(target_os = "android"))]
+const KILL_POLICY_SETTING: &str = "kill_policy";
+#[cfg(not(target_os = "android"))]
+const EMERGENCY_POLICY_SETTING: &str = "emergency_policy";
+#[cfg(not(target_os = "android"))]
+const BUDGET_RESET_SETTING: &str = "budget_reset_time";
+#[cfg(not(target_os = "android"))]
+const LOCK_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long a budgeted site counts as in use after it was last looked up.
+/// Browsers cache lookups for about a minute.
+#[cfg(not(target_os = "android"))]
+const BUDGET_ACTIVITY_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+
+// App state for desktop
+#[cfg(not(target_os = "android"))]
+struct AppState {
+    timelock: TimeLock,
+    settings: Settings,
+    blocklist: Blocklist,
+    hosts_blocker: HostsBlocker,
+    hosts_watcher: HostsWatcher,
+    dns_sinkhole: DnsSinkhole,
+    block_page: BlockPageServer,
+    process_rules: ProcessRuleStore,
+    process_watcher: ProcessWatcher,
+    events: Arc<EventBus>,
+    emergency: EmergencyUnlock,
+    schedules: ScheduleStore,
+    planned_locks: PlannedLockStore,
+    pomodoro: Pomodoro,
+    budgets: BudgetStore,
+    profiles: ProfileStore,
+    // Whether the full block of a lock or scheduled window is in place, as
+    // opposed to only a break's or used-up budgets'
+    full_block: AtomicBool,
+}
+
+/// Error of the lock commands: lock errors reach the frontend with their
+/// kind, anything else as the plain message other commands return.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+enum LockCommandError {
+    Lock(TimeLockError),
+    Other(String),
+}
+
+#[cfg(not(target_os = "android"))]
+impl From<TimeLockError> for LockCommandError {
+    fn from(e: TimeLockError) -> Self {
+        LockCommandError::Lock(e)
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl From<String> for LockCommandError {
+    fn from(e: String) -> Self {
+        LockCommandError::Other(e)
+    }
+}
+
+// ============ Desktop Commands ============
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn start_lock(
+    state: State<Mutex<AppState>>,
+    duration_minutes: i64,
+    profile_id: Option<i64>,
+) -> Result<LockState, LockCommandError> {
+    let state = state.lock().unwrap();
+
+    if let Some(id) = profile_id {
+        state.profiles.profile(id).map_err(|e| e.to_string())?;
+    }
+    // Replacing a running lock must not loosen what it blocks
+    let current = state.timelock.get_state()?;
+    if current.is_locked {
+        ensure_stricter(&state, current.profile_id, profile_id)?;
+    }
+
+    let lock_state = state.timelock.start_lock(duration_minutes, profile_id)?;
+
+    engage_lock(&state, &lock_state)?;
+    Ok(lock_state)
+}
+
+/// Moves the running lock to a stricter profile and blocks with it at once.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn switch_lock_profile(
+    state: State<Mutex<AppState>>,
+    profile_id: Option<i64>,
+) -> Result<LockState, LockCommandError> {
+    let state = state.lock().unwrap();
+
+    let current = state.timelock.get_state()?;
+    if !current.is_locked {
+        return Err(TimeLockError::NotLocked.into());
+    }
+    let profile = ensure_stricter(&state, current.profile_id, profile_id)?;
+
+    let lock_state = state.timelock.set_profile(profile_id)?;
+    log::info!("Blocking with profile {}", profile.name);
+
+    let window = active_schedule(&state).is_some();
+    apply_domain_block(&state, &lock_state, window)?;
+    reload_process_rules(&state, &lock_state, window)?;
+    Ok(lock_state)
+}
+
+/// The profile a lock with `profile_id` blocks with; the global lists when
+/// it has none.
+#[cfg(not(target_os = "android"))]
+fn lock_profile(state: &AppState, profile_id: Option<i64>) -> Result<Profile, String> {
+    if let Some(id) = profile_id {
+        match state.profiles.profile(id) {
+            Ok(profile) => return Ok(profile),
+            // Never lift a running lock over a profile that went missing
+            Err(ProfileError::NotFound(_)) => {
+                log::warn!("Profile {} of the lock is gone, using the global lists", id)
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    Ok(Profile::global(
+        state.blocklist.domains().map_err(|e| e.to_string())?,
+        state.process_rules.rules().map_err(|e| e.to_string())?,
+    ))
+}
+
+/// The profile of the running lock, if it has one.
+#[cfg(not(target_os = "android"))]
+fn running_profile(lock_state: &LockState) -> Option<i64> {
+    lock_state.profile_id.filter(|_| lock_state.is_locked)
+}
+
+/// Refuses to move a running lock from profile `from` to `to` unless `to`
+/// is strictly stricter, and returns the profile moved to.
+#[cfg(not(target_os = "android"))]
+fn ensure_stricter(
+    state: &AppState,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Profile, String> {
+    let current = lock_profile(state, from)?;
+    if from == to {
+        return Ok(current);
+    }
+
+    let target = match to {
+        Some(id) => state.profiles.profile(id).map_err(|e| e.to_string())?,
+        None => lock_profile(state, None)?,
+    };
+    if !target.is_stricter_than(&current) {
+        return Err(ProfileError::NotStricter(current.name).to_string());
+    }
+    Ok(target)
+}
+
+/// Refuses to end a lock early when its profile rules emergency unlock out.
+#[cfg(not(target_os = "android"))]
+fn ensure_emergency_allowed(state: &AppState) -> Result<(), String> {
+    let lock_state = state.timelock.get_state().map_err(|e| e.to_string())?;
+    if !lock_state.is_locked {
+        return Ok(());
+    }
+
+    let profile = lock_profile(state, lock_state.profile_id)?;
+    if !profile.options.allow_emergency_unlock {
+        return Err(ProfileError::EmergencyUnlockDisabled(profile.name).to_string());
+    }
+    Ok(())
+}
+
+/// Puts the full block in place for a lock that just started.
+#[cfg(not(target_os = "android"))]
+fn engage_lock(state: &AppState, lock_state: &LockState) -> Result<(), String> {
+    state.full_block.store(true, Ordering::Relaxed);
+    set_block_page_end(state, lock_state, None);
+    let window = active_schedule(state).is_some();
+    apply_domain_block(state, lock_state, window)?;
+    reload_process_rules(state, lock_state, window)?;
+    state.events.lock_started(lock_state.unlock_time);
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn extend_lock(state: State<Mutex<AppState>>, minutes: i64) -> Result<LockState, TimeLockError> {
+    let state = state.lock().unwrap();
+
+    let lock_state = state.timelock.extend_lock(minutes)?;
+
+    set_block_page_end(&state, &lock_state, None);
+    Ok(lock_state)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_lock_state(state: State<Mutex<AppState>>) -> Result<LockState, String> {
+    let state = state.lock().unwrap();
+    // Polled every second; keeping the block in sync is the background
+    // check's job
+    state.timelock.get_state().map_err(|e| e.to_string())
+}
+
+/// Brings the block in line with the lock, the schedules, interval mode
+/// and usage budgets: engages the full block when a scheduled window
+/// opens, narrows it to the break group and used-up budgets once nothing
+/// holds it, releases it when none of them is left, and restores it if it
+/// went missing.
+#[cfg(not(target_os = "android"))]
+fn sync_lock_state(state: &AppState) -> Result<LockState, String> {
+    let mut lock_state = state.timelock.get_state().map_err(|e| e.to_string())?;
+    if let Some(started) = start_planned_lock(state, &lock_state) {
+        lock_state = started;
+    }
+    if let Some(started) = advance_pomodoro(state, &lock_state) {
+        lock_state = started;
+    }
+    let window = active_schedule(state);
+    let blocking = lock_state.is_locked || window.is_some();
+
+    // Leaving the full block also resumes frozen apps, even when a lighter
+    // block follows
+    if !blocking && state.full_block.load(Ordering::Relaxed) {
+        let _ = release_block(state);
+    }
+    // Windows closing and breaks starting are no lock expiring
+    if state.timelock.take_expired() {
+        state.events.lock_expired();
+    }
+
+    let domains = block_domains(state, &lock_state, window.is_some())?;
+    if !blocking && domains.is_empty() {
+        if state.hosts_blocker.is_blocked() {
+            let _ = release_block(state);
+        }
+    } else if !state.hosts_watcher.is_running() || !state.hosts_blocker.is_block_intact(&domains) {
+        match &window {
+            Some(window) if !lock_state.is_locked && !state.full_block.load(Ordering::Relaxed) => {
+                log::info!("Scheduled window {} started", window.name)
+            }
+            _ if blocking && !state.hosts_watcher.is_running() => {
+                log::info!("Resuming existing lock")
+            }
+            _ if blocking => log::info!("Hosts block out of date during lock, re-applying"),
+            _ => log::info!(
+                "Blocking {} domains of the break and used-up budgets",
+                domains.len()
+            ),
+        }
+        apply_domain_block(state, &lock_state, window.is_some())?;
+    }
+
+    if blocking {
+        state.full_block.store(true, Ordering::Relaxed);
+        set_block_page_end(state, &lock_state, window.as_ref());
+    }
+    reload_process_rules(state, &lock_state, window.is_some())?;
+    watch_budget_domains(state);
+    keep_dns_sinkhole_running(state);
+
+    Ok(lock_state)
+}
+
+/// Turns a calendar occurrence that has begun into a lock until its end.
+/// Returns the state of the lock it started.
+#[cfg(not(target_os = "android"))]
+fn start_planned_lock(state: &AppState, lock_state: &LockState) -> Option<LockState> {
+    let now = trusted_now(state);
+    let planned = match state.planned_locks.take_due(now) {
+        Ok(Some(planned)) => planned,
+        Ok(None) => return None,
+        Err(e) => {
+            log::error!("Failed to read planned locks: {}", e);
+            return None;
+        }
+    };
+
+    // Locks run in whole minutes; round up so the event is fully covered
+    let minutes = ((planned.ends_at - now).num_seconds() + 59) / 60;
+    match state
+        .timelock
+        .start_lock(minutes, running_profile(lock_state))
+    {
+        Ok(lock_state) => {
+            log::info!("Started planned lock: {}", planned.summary);
+            state.events.lock_started(lock_state.unlock_time);
+            Some(lock_state)
+        }
+        Err(TimeLockError::WouldShorten { .. }) => {
+            log::info!(
+                "Planned lock {} ends before the running lock",
+                planned.summary
+            );
+            None
+        }
+        Err(e) => {
+            log::error!("Failed to start planned lock {}: {}", planned.summary, e);
+            None
+        }
+    }
+}
+
+/// Moves interval mode to its next phase when the current one is over,
+/// starting the lock for each work interval. Returns the state of the lock
+/// it started.
+#[cfg(not(target_os = "android"))]
+fn advance_pomodoro(state: &AppState, lock_state: &LockState) -> Option<LockState> {
+    let phase = match state.pomodoro.advance(lock_state.is_locked) {
+        Ok(Some(phase)) => phase,
+        Ok(None) => return None,
+        Err(e) => {
+            log::error!("Interval mode check failed: {}", e);
+            return None;
+        }
+    };
+    let status = state.pomodoro.status().unwrap_or_else(|e| {
+        log::error!("Failed to read interval mode: {}", e);
+        None
+    });
+
+    let mut started = None;
+    if let (Phase::Work, Some(status)) = (phase, &status) {
+        let minutes = status.config.work_minutes as i64;
+        match state
+            .timelock
+            .start_lock(minutes, running_profile(lock_state))
+        {
+            Ok(lock_state) => {
+                if let Err(e) = engage_lock(state, &lock_state) {
+                    log::error!("Failed to block for work interval: {}", e);
+                }
+                started = Some(lock_state);
+            }
+            // A longer lock already covers the interval
+            Err(TimeLockError::WouldShorten { .. }) => {}
+            Err(e) => log::error!("Failed to start work interval: {}", e),
+        }
+    }
+
+    state
+        .events
+        .pomodoro_phase(phase.as_str(), status.map(|s| s.phase_ends_at));
+    started
+}
+
+/// The lighter group a pomodoro break keeps blocked, if any.
+#[cfg(not(target_os = "android"))]
+fn pomodoro_break_group(state: &AppState) -> Option<String> {
+    state.pomodoro.break_group().unwrap_or_else(|e| {
+        log::error!("Failed to read interval mode: {}", e);
+        None
+    })
+}
+
+/// The local time budgets start afresh each day; midnight unless set.
+#[cfg(not(target_os = "android"))]
+fn budget_reset_time(state: &AppState) -> chrono::NaiveTime {
+    state
+        .settings
+        .get(BUDGET_RESET_SETTING)
+        .ok()
+        .flatten()
+        .unwrap_or(chrono::NaiveTime::MIN)
+}
+
+#[cfg(not(target_os = "android"))]
+fn budget_today(state: &AppState) -> chrono::NaiveDate {
+    budget::budget_day(&chrono::Local, budget_reset_time(state), trusted_now(state))
+}
+
+/// Budgets with nothing left today.
+#[cfg(not(target_os = "android"))]
+fn exhausted_budgets(state: &AppState) -> Vec<Budget> {
+    state
+        .budgets
+        .exhausted(budget_today(state))
+        .unwrap_or_else(|e| {
+            log::error!("Failed to read budgets: {}", e);
+            Vec::new()
+        })
+}
+
+/// Counts `elapsed` against every budget whose apps are running or whose
+/// sites were looked up recently, and reports the budgets this used up.
+#[cfg(not(target_os = "android"))]
+fn track_budget_usage(state: &AppState, elapsed: std::time::Duration) {
+    let budgets = match state.budgets.budgets() {
+        Ok(budgets) if !budgets.is_empty() => budgets,
+        Ok(_) => return,
+        Err(e) => {
+            log::error!("Failed to read budgets: {}", e);
+            return;
+        }
+    };
+
+    let processes = if budgets.iter().any(|b| !b.processes.is_empty()) {
+        ProcessWatcher::running_names()
+    } else {
+        Vec::new()
+    };
+    let queried = state.dns_sinkhole.recent_queries(BUDGET_ACTIVITY_WINDOW);
+    let in_use: Vec<i64> = budgets
+        .iter()
+        .filter(|b| b.in_use(&processes, &queried))
+        .map(|b| b.id)
+        .collect();
+
+    let reset = budget_reset_time(state);
+    let day = budget::budget_day(&chrono::Local, reset, trusted_now(state));
+    match state.budgets.record(&in_use, elapsed.as_secs() as i64, day) {
+        Ok(used_up) => {
+            for budget in used_up {
+                log::info!("Budget {} used up for today", budget.name);
+                let resets_at = budget::next_reset(&chrono::Local, reset, day);
+                state.events.budget_exhausted(&budget.name, resets_at);
+            }
+        }
+        Err(e) => log::error!("Failed to record budget usage: {}", e),
+    }
+}
+
+/// Keeps the DNS sinkhole watching the budgeted domains.
+#[cfg(not(target_os = "android"))]
+fn watch_budget_domains(state: &AppState) {
+    let domains: Vec<String> = match state.budgets.budgets() {
+        Ok(budgets) => budgets.into_iter().flat_map(|b| b.domains).collect(),
+        Err(e) => {
+            log::error!("Failed to read budgets: {}", e);
+            return;
+        }
+    };
+
+    state.dns_sinkhole.set_watched(&domains);
+}
+
+/// Keeps the DNS sinkhole running whenever it is enabled, blocking or not:
+/// the system resolver points at it then, so without it no name resolves.
+#[cfg(not(target_os = "android"))]
+fn keep_dns_sinkhole_running(state: &AppState) {
+    if !state.dns_sinkhole.is_enabled() {
+        return;
+    }
+    if let Err(e) = state.dns_sinkhole.start_with_backoff() {
+        log::warn!(
+            "DNS sinkhole unavailable, names don't resolve through it: {}",
+            e
+        );
+    }
+}
+
+/// The time schedules, planned locks and budgets go by, which doesn't
+/// move with the system clock.
+#[cfg(not(target_os = "android"))]
+fn trusted_now(state: &AppState) -> chrono::DateTime<chrono::Utc> {
+    state.timelock.now().unwrap_or_else(|e| {
+        log::error!("Failed to read the trusted clock: {}", e);
+        chrono::Utc::now()
+    })
+}
+
+/// The scheduled window running now, if any.
+#[cfg(not(target_os = "android"))]
+fn active_schedule(state: &AppState) -> Option<ScheduleWindow> {
+    state
+        .schedules
+        .active_window(trusted_now(state))
+        .unwrap_or_else(|e| {
+            log::error!("Failed to read schedules: {}", e);
+            None
+        })
+}
+
+/// Whether the full block is held, by the lock or by a scheduled window.
+#[cfg(not(target_os = "android"))]
+fn holds_full_block(state: &AppState) -> bool {
+    state.timelock.is_locked() || active_schedule(state).is_some()
+}
+
+/// The lock state and whether a scheduled window is open, read once for
+/// the pieces of the block built from them.
+#[cfg(not(target_os = "android"))]
+fn block_holds(state: &AppState) -> Result<(LockState, bool), String> {
+    let lock_state = state.timelock.get_state().map_err(|e| e.to_string())?;
+    Ok((lock_state, active_schedule(state).is_some()))
+}
+
+/// Whether any block is held, including a pomodoro break's lighter one and
+/// used-up budgets. Guards everything that would weaken blocking.
+#[cfg(not(target_os = "android"))]
+fn is_blocking(state: &AppState) -> bool {
+    holds_full_block(state)
+        || pomodoro_break_group(state).is_some()
+        || !exhausted_budgets(state).is_empty()
+}
+
+/// Text of the tray's status line, after a check brought the block in line
+/// with a lock running with `profile_id`.
+#[cfg(not(target_os = "android"))]
+fn tray_status(state: &AppState, profile_id: Option<i64>) -> String {
+    if let Ok(Some(status)) = state.pomodoro.status() {
+        let minutes = (status.remaining_seconds + 59) / 60;
+        match status.phase {
+            Phase::Work => {
+                return format!(
+                    "🍅 집중 {}/{} · {}분 남음",
+                    status.completed + 1,
+                    status.config.intervals,
+                    minutes
+                )
+            }
+            Phase::ShortBreak => return format!("☕ 휴식 · {}분 남음", minutes),
+            Phase::LongBreak => return format!("☕ 긴 휴식 · {}분 남음", minutes),
+            Phase::Finished => {}
+        }
+    }
+
+    if let Some(id) = profile_id {
+        if let Ok(profile) = state.profiles.profile(id) {
+            return format!("🔒 잠금 중 · {}", profile.name);
+        }
+    }
+    if state.full_block.load(Ordering::Relaxed) {
+        "🔒 잠금 중".to_string()
+    } else {
+        "🔓 잠금 해제됨".to_string()
+    }
+}
+
+/// When the block lifts if nothing else holds it, by the trusted clock at
+/// `now`: the later of the lock's end and the window's.
+#[cfg(not(target_os = "android"))]
+fn block_end(
+    lock_state: &LockState,
+    window: Option<&ScheduleWindow>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let lock_end = lock_state
+        .is_locked
+        .then(|| now + chrono::Duration::seconds(lock_state.remaining_seconds));
+    lock_end.max(window.map(|w| w.end))
+}
+
+/// Has the block page count down to when the block lifts.
+#[cfg(not(target_os = "android"))]
+fn set_block_page_end(state: &AppState, lock_state: &LockState, window: Option<&ScheduleWindow>) {
+    let now = trusted_now(state);
+    match block_end(lock_state, window, now) {
+        Some(end) => state.block_page.set_unlock_time(end, now),
+        None => state.block_page.clear_unlock_time(),
+    }
+}
+
+/// Releases the block after the lock ended early, unless a scheduled window
+/// still holds it.
+#[cfg(not(target_os = "android"))]
+fn release_lock_block(state: &AppState) -> Result<(), String> {
+    if let Some(window) = active_schedule(state) {
+        log::info!(
+            "Lock ended during scheduled window {}, keeping block",
+            window.name
+        );
+        state
+            .block_page
+            .set_unlock_time(window.end, trusted_now(state));
+        return Ok(());
+    }
+    release_block(state)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn emergency_unlock(state: State<Mutex<AppState>>) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    ensure_emergency_allowed(&state)?;
+
+    state
+        .emergency
+        .instant(state.timelock.is_locked())
+        .map_err(|e| e.to_string())?;
+    state.timelock.clear_lock().map_err(|e| e.to_string())?;
+    // Counted only once the lock is really gone
+    if let Err(e) = state.emergency.record_instant() {
+        log::error!("Failed to record emergency unlock: {}", e);
+    }
+    // Interval mode would only lock again after the break
+    let _ = state.pomodoro.stop();
+    release_lock_block(&state)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn request_emergency_unlock(state: State<Mutex<AppState>>) -> Result<EmergencyRequest, String> {
+    let state = state.lock().unwrap();
+    ensure_emergency_allowed(&state)?;
+    state
+        .emergency
+        .request(state.timelock.is_locked())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn cancel_emergency_unlock(state: State<Mutex<AppState>>) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state.emergency.cancel().map_err(|e| e.to_string())
+}
+
+/// Ends the lock once the requested wait is over and the challenge, if
+/// any, was typed back.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn confirm_emergency_unlock(
+    state: State<Mutex<AppState>>,
+    typed: Option<String>,
+) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    // The lock may have switched to a stricter profile since the request
+    ensure_emergency_allowed(&state)?;
+
+    state
+        .emergency
+        .confirm(typed.as_deref(), state.timelock.is_locked())
+        .map_err(|e| e.to_string())?;
+    state.timelock.clear_lock().map_err(|e| e.to_string())?;
+    if let Err(e) = state.emergency.record_confirmed() {
+        log::error!("Failed to record emergency unlock: {}", e);
+    }
+    // Interval mode would only lock again after the break
+    let _ = state.pomodoro.stop();
+    release_lock_block(&state)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_emergency_status(state: State<Mutex<AppState>>) -> Result<EmergencyStatus, String> {
+    let state = state.lock().unwrap();
+    state.emergency.status().map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_emergency_policy(state: State<Mutex<AppState>>) -> EmergencyPolicy {
+    let state = state.lock().unwrap();
+    state.emergency.policy()
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn set_emergency_policy(
+    state: State<Mutex<AppState>>,
+    policy: EmergencyPolicy,
+) -> Result<EmergencyPolicy, String> {
+    let state = state.lock().unwrap();
+    let locked = state.timelock.is_locked();
+    // Stored first, so a failed write doesn't leave a policy live that
+    // the next start forgets
+    let policy = state
+        .emergency
+        .check_policy(policy, locked)
+        .map_err(|e| e.to_string())?;
+    state
+        .settings
+        .set(EMERGENCY_POLICY_SETTING, &policy)
+        .map_err(|e| e.to_string())?;
+    state
+        .emergency
+        .set_policy(policy, locked)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_session_history(
+    state: State<Mutex<AppState>>,
+    limit: Option<u32>,
+) -> Result<Vec<Session>, String> {
+    let state = state.lock().unwrap();
+    state
+        .timelock
+        .recent_sessions(limit.unwrap_or(50))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_session_stats(state: State<Mutex<AppState>>) -> Result<SessionStats, String> {
+    let state = state.lock().unwrap();
+    let sessions = state.timelock.sessions().map_err(|e| e.to_string())?;
+    Ok(stats::summarize(&sessions, trusted_now(&state)))
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_daily_focus(
+    state: State<Mutex<AppState>>,
+    days: Option<u32>,
+) -> Result<Vec<DayFocus>, String> {
+    let state = state.lock().unwrap();
+    let sessions = state.timelock.sessions().map_err(|e| e.to_string())?;
+    Ok(stats::daily_focus(
+        &sessions,
+        days.unwrap_or(7),
+        trusted_now(&state),
+    ))
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_weekly_focus(
+    state: State<Mutex<AppState>>,
+    weeks: Option<u32>,
+) -> Result<Vec<WeekFocus>, String> {
+    let state = state.lock().unwrap();
+    let sessions = state.timelock.sessions().map_err(|e| e.to_string())?;
+    Ok(stats::weekly_focus(
+        &sessions,
+        weeks.unwrap_or(8),
+        trusted_now(&state),
+    ))
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_schedules(state: State<Mutex<AppState>>) -> Result<Vec<Schedule>, String> {
+    let state = state.lock().unwrap();
+    state.schedules.schedules().map_err(|e| e.to_string())
+}
+
+/// Adding is allowed during a window; a new schedule that is already
+/// running engages the block right away.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn add_schedule(
+    state: State<Mutex<AppState>>,
+    schedule: ScheduleInput,
+) -> Result<Schedule, String> {
+    let state = state.lock().unwrap();
+    let schedule = state
+        .schedules
+        .add_schedule(&schedule)
+        .map_err(|e| e.to_string())?;
+    sync_lock_state(&state)?;
+    Ok(schedule)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn update_schedule(
+    state: State<Mutex<AppState>>,
+    id: i64,
+    schedule: ScheduleInput,
+) -> Result<Schedule, String> {
+    let state = state.lock().unwrap();
+    let schedule = state
+        .schedules
+        .update_schedule(id, &schedule, trusted_now(&state))
+        .map_err(|e| e.to_string())?;
+    sync_lock_state(&state)?;
+    Ok(schedule)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn remove_schedule(state: State<Mutex<AppState>>, id: i64) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .schedules
+        .remove_schedule(id, trusted_now(&state))
+        .map_err(|e| e.to_string())
+}
+
+/// The occurrences an import would plan, so the filter can be tried out
+/// first.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn preview_calendar_import(path: String, filter: EventFilter) -> Result<Vec<Occurrence>, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let parsed = calendar::parse_calendar(&content);
+    PlannedLockStore::preview(&parsed, &filter, chrono::Utc::now()).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn import_calendar(
+    state: State<Mutex<AppState>>,
+    path: String,
+    filter: EventFilter,
+) -> Result<CalendarImportReport, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let parsed = calendar::parse_calendar(&content);
+
+    let state = state.lock().unwrap();
+    let report = state
+        .planned_locks
+        .import(&path, &parsed, &filter, trusted_now(&state))
+        .map_err(|e| e.to_string())?;
+
+    // An event already in progress locks right away
+    sync_lock_state(&state)?;
+    Ok(report)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_planned_locks(state: State<Mutex<AppState>>) -> Result<Vec<PlannedLock>, String> {
+    let state = state.lock().unwrap();
+    state
+        .planned_locks
+        .planned(trusted_now(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn remove_planned_lock(state: State<Mutex<AppState>>, id: i64) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state.planned_locks.remove(id).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_active_schedule(state: State<Mutex<AppState>>) -> Result<Option<ScheduleWindow>, String> {
+    let state = state.lock().unwrap();
+    state
+        .schedules
+        .active_window(trusted_now(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_next_schedule(state: State<Mutex<AppState>>) -> Result<Option<ScheduleWindow>, String> {
+    let state = state.lock().unwrap();
+    state
+        .schedules
+        .next_window(trusted_now(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn start_pomodoro(
+    state: State<Mutex<AppState>>,
+    config: PomodoroConfig,
+) -> Result<PomodoroStatus, String> {
+    let state = state.lock().unwrap();
+    let status = state.pomodoro.start(config).map_err(|e| e.to_string())?;
+
+    let current = state.timelock.get_state().map_err(|e| e.to_string())?;
+    let minutes = status.config.work_minutes as i64;
+    let lock_state = match state
+        .timelock
+        .start_lock(minutes, running_profile(&current))
+    {
+        Ok(lock_state) => lock_state,
+        // A longer lock already covers the first interval
+        Err(TimeLockError::WouldShorten { .. }) => current,
+        Err(e) => {
+            let _ = state.pomodoro.stop();
+            return Err(e.to_string());
+        }
+    };
+    engage_lock(&state, &lock_state)?;
+    state
+        .events
+        .pomodoro_phase(status.phase.as_str(), Some(status.phase_ends_at));
+
+    Ok(status)
+}
+
+/// Ends interval mode. A running work interval stays locked until it ends;
+/// a break's lighter block is lifted.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn stop_pomodoro(state: State<Mutex<AppState>>) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state.pomodoro.stop().map_err(|e| e.to_string())?;
+    sync_lock_state(&state).map(|_| ())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_pomodoro_status(state: State<Mutex<AppState>>) -> Result<Option<PomodoroStatus>, String> {
+    let state = state.lock().unwrap();
+    state.pomodoro.status().map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_budgets(state: State<Mutex<AppState>>) -> Result<Vec<BudgetUsage>, String> {
+    let state = state.lock().unwrap();
+    state
+        .budgets
+        .usage(budget_today(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn add_budget(state: State<Mutex<AppState>>, budget: BudgetInput) -> Result<Budget, String> {
+    let state = state.lock().unwrap();
+    ensure_sites_countable(&state, &budget)?;
+    let budget = state
+        .budgets
+        .add_budget(&budget)
+        .map_err(|e| e.to_string())?;
+
+    watch_budget_domains(&state);
+    Ok(budget)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn update_budget(
+    state: State<Mutex<AppState>>,
+    id: i64,
+    budget: BudgetInput,
+) -> Result<Budget, String> {
+    let state = state.lock().unwrap();
+    ensure_sites_countable(&state, &budget)?;
+    let budget = state
+        .budgets
+        .update_budget(id, &budget, budget_today(&state))
+        .map_err(|e| e.to_string())?;
+
+    // A lower allowance may already be used up
+    sync_lock_state(&state)?;
+    Ok(budget)
+}
+
+/// Visits to budgeted sites are only seen by the DNS sinkhole, so site
+/// budgets wait until it is turned on.
+#[cfg(not(target_os = "android"))]
+fn ensure_sites_countable(state: &AppState, budget: &BudgetInput) -> Result<(), String> {
+    if !budget.domains.is_empty() && !state.dns_sinkhole.is_enabled() {
+        return Err(
+            "Site budgets need the DNS sinkhole, which counts visits; turn it on first".to_string(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn remove_budget(state: State<Mutex<AppState>>, id: i64) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .budgets
+        .remove_budget(id, budget_today(&state))
+        .map_err(|e| e.to_string())?;
+
+    watch_budget_domains(&state);
+    Ok(())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_budget_reset_time(state: State<Mutex<AppState>>) -> chrono::NaiveTime {
+    let state = state.lock().unwrap();
+    budget_reset_time(&state)
+}
+
+/// Refused while a budget is used up, since moving the reset could start
+/// the next budget day early.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn set_budget_reset_time(
+    state: State<Mutex<AppState>>,
+    time: chrono::NaiveTime,
+) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    if !exhausted_budgets(&state).is_empty() {
+        return Err(budget::BudgetError::Exhausted.to_string());
+    }
+
+    state
+        .settings
+        .set(BUDGET_RESET_SETTING, &time)
+        .map_err(|e| e.to_string())?;
+    sync_lock_state(&state).map(|_| ())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_profiles(state: State<Mutex<AppState>>) -> Result<Vec<Profile>, String> {
+    let state = state.lock().unwrap();
+    state.profiles.profiles().map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn add_profile(state: State<Mutex<AppState>>, profile: ProfileInput) -> Result<Profile, String> {
+    let state = state.lock().unwrap();
+    state
+        .profiles
+        .add_profile(&profile)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn update_profile(
+    state: State<Mutex<AppState>>,
+    id: i64,
+    profile: ProfileInput,
+) -> Result<Profile, String> {
+    let state = state.lock().unwrap();
+    let lock_state = state.timelock.get_state().map_err(|e| e.to_string())?;
+    let in_use = running_profile(&lock_state) == Some(id);
+    state
+        .profiles
+        .update_profile(id, &profile, in_use)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn remove_profile(state: State<Mutex<AppState>>, id: i64) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    let lock_state = state.timelock.get_state().map_err(|e| e.to_string())?;
+    let in_use = running_profile(&lock_state) == Some(id);
+    state
+        .profiles
+        .remove_profile(id, in_use)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_blocked_domains(state: State<Mutex<AppState>>) -> Result<Vec<String>, String> {
+    let state = state.lock().unwrap();
+    state.blocklist.domains().map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_blocklist(state: State<Mutex<AppState>>) -> Result<Vec<BlockedDomain>, String> {
+    let state = state.lock().unwrap();
+    state.blocklist.entries().map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_domain_groups(state: State<Mutex<AppState>>) -> Result<Vec<String>, String> {
+    let state = state.lock().unwrap();
+    state.blocklist.groups().map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn add_blocked_domain(
+    state: State<Mutex<AppState>>,
+    domain: String,
+    group: String,
+) -> Result<BlockedDomain, String> {
+    let state = state.lock().unwrap();
+    let entry = state
+        .blocklist
+        .add_domain(&domain, &group)
+        .map_err(|e| e.to_string())?;
+
+    // Additions take effect immediately during a lock
+    if is_blocking(&state) {
+        let (lock_state, window) = block_holds(&state)?;
+        apply_domain_block(&state, &lock_state, window)?;
+    }
+
+    Ok(entry)
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn set_domain_group(
+    state: State<Mutex<AppState>>,
+    domain: String,
+    group: String,
+) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .blocklist
+        .set_group(&domain, &group, is_blocking(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn remove_blocked_domain(state: State<Mutex<AppState>>, domain: String) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .blocklist
+        .remove_domain(&domain, is_blocking(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn remove_domain_group(state: State<Mutex<AppState>>, group: String) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .blocklist
+        .remove_group(&group, is_blocking(&state))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn import_blocklist(
+    state: State<Mutex<AppState>>,
+    path: String,
+    group: String,
+) -> Result<ImportReport, String> {
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let parsed = import::parse_blocklist(&content);
+
+    let state = state.lock().unwrap();
+    let added = state
+        .blocklist
+        .add_domains(&parsed.domains, &group)
+        .map_err(|e| e.to_string())?;
+
+    if added > 0 && is_blocking(&state) {
+        let (lock_state, window) = block_holds(&state)?;
+        apply_domain_block(&state, &lock_state, window)?;
+    }
+
+    Ok(ImportReport {
+        parsed: parsed.domains.len(),
+        added,
+        duplicates: parsed.duplicates,
+        errors: parsed.errors,
+    })
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn restore_hosts_backup(state: State<Mutex<AppState>>) -> Result<String, String> {
+    let state = state.lock().unwrap();
+    let backup = state
+        .hosts_blocker
+        .restore_backup()
+        .map_err(|e| e.to_string())?;
+
+    // The backup predates FocusLock, so an active lock has to be written back on top
+    if is_blocking(&state) {
+        let (lock_state, window) = block_holds(&state)?;
+        apply_domain_block(&state, &lock_state, window)?;
+    }
+
+    Ok(backup.display().to_string())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_tamper_events(
+    state: State<Mutex<AppState>>,
+    limit: u32,
+) -> Result<Vec<TamperEvent>, String> {
+    let state = state.lock().unwrap();
+    let events = state
+        .events
+        .history(Some(EventKind::HostsTampered), limit)
+        .map_err(|e| e.to_string())?;
+
+    // Recorded through the event bus, the tamper kind as the name
+    Ok(events
+        .into_iter()
+        .map(|event| TamperEvent {
+            detected_at: event.timestamp,
+            kind: event.name.unwrap_or_default(),
+            restored: event.detail.as_deref() == Some("restored"),
+        })
+        .collect())
+}
+
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_sinkhole_addresses(state: State<Mutex<AppState>>) -> SinkholeAddresses {
+    let state = state.lock().unwrap();
+    state.hosts_blocker.sinkhole()
+}
+
 #[cfg(not(target_os = "android"))]
-use timelock::{LockState, TimeLock};
+#[tauri::command]
+fn set_sinkhole_addresses(
+    state: State<Mutex<AppState>>,
+    sinkhole: SinkholeAddresses,
+) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .hosts_blocker
+        .set_sinkhole(sinkhole)
+        .map_err(|e| e.to_string())?;
+    state.dns_sinkhole.set_answers(sinkhole);
+    state
+        .settings
+        .set(SINKHOLE_SETTING, &sinkhole)
+        .map_err(|e| e.to_string())?;
 
-#[cfg(target_os = "android")]
-use timelock::LockState;
+    if is_blocking(&state) {
+        let (lock_state, window) = block_holds(&state)?;
+        apply_domain_block(&state, &lock_state, window)?;
+    }
+
+    Ok(())
+}
 
 #[cfg(not(target_os = "android"))]
-use std::sync::Mutex;
+#[tauri::command]
+async fn verify_blocking(state: State<'_, Mutex<AppState>>) -> Result<BlockVerification, String> {
+    // Resolve outside the state lock; lookups can take seconds
+    let (hosts_blocker, domains) = {
+        let state = state.lock().unwrap();
+        let domains = state.blocklist.domains().map_err(|e| e.to_string())?;
+        (state.hosts_blocker.clone(), domains)
+    };
+
+    tauri::async_runtime::spawn_blocking(move || hosts_blocker.verify(&domains))
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[cfg(not(target_os = "android"))]
-use tauri::{Manager, State};
-#[cfg(target_os = "android")]
-use tauri::Manager;
+#[tauri::command]
+fn get_event_history(
+    state: State<Mutex<AppState>>,
+    kind: Option<EventKind>,
+    limit: Option<u32>,
+) -> Result<Vec<AppEvent>, String> {
+    let state = state.lock().unwrap();
+    state
+        .events
+        .history(kind, limit.unwrap_or(100))
+        .map_err(|e| e.to_string())
+}
 
+/// Per-name totals of `kind`, e.g. blocked launches since the lock started.
 #[cfg(not(target_os = "android"))]
-use tauri::{
-    image::Image,
-    menu::{Menu, MenuItem},
-    tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
-    WindowEvent,
-};
+#[tauri::command]
+fn get_event_counts(
+    state: State<Mutex<AppState>>,
+    kind: EventKind,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<Vec<EventCount>, String> {
+    let state = state.lock().unwrap();
+    state.events.counts(kind, since).map_err(|e| e.to_string())
+}
 
 #[cfg(not(target_os = "android"))]
-use tauri_plugin_autostart::MacosLauncher;
+#[tauri::command]
+fn get_blocked_attempts(state: State<Mutex<AppState>>) -> Result<Vec<BlockedAttempt>, String> {
+    let state = state.lock().unwrap();
+    state.block_page.attempts().map_err(|e| e.to_string())
+}
 
-// App state for desktop
 #[cfg(not(target_os = "android"))]
-struct AppState {
-    timelock: TimeLock,
-    hosts_blocker: HostsBlocker,
-    process_watcher: ProcessWatcher,
+#[tauri::command]
+fn flush_dns_cache() -> FlushReport {
+    dns_flush::flush_dns_cache(&SystemCommandRunner)
 }
 
-// ============ Desktop Commands ============
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_dns_sinkhole_status(state: State<Mutex<AppState>>) -> DnsSinkholeStatus {
+    let state = state.lock().unwrap();
+    state.dns_sinkhole.status()
+}
 
 #[cfg(not(target_os = "android"))]
 #[tauri::command]
-fn start_lock(state: State<Mutex<AppState>>, duration_minutes: i64) -> Result<LockState, String> {
+fn get_dns_sinkhole_config(state: State<Mutex<AppState>>) -> DnsSinkholeConfig {
     let state = state.lock().unwrap();
+    state.dns_sinkhole.config()
+}
 
-    let lock_state = state
-        .timelock
-        .start_lock(duration_minutes)
+/// Enabling only makes sense once the system resolver points at the
+/// sinkhole's address; FocusLock doesn't change resolver settings itself.
+/// Site budgets depend on it, so it stays on while any exist.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn set_dns_sinkhole_config(
+    state: State<Mutex<AppState>>,
+    config: DnsSinkholeConfig,
+) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    if !config.enabled {
+        let budgets = state.budgets.budgets().map_err(|e| e.to_string())?;
+        if budgets.iter().any(|b| !b.domains.is_empty()) {
+            return Err(
+                "Site budgets are counted by the DNS sinkhole; remove them first".to_string(),
+            );
+        }
+    }
+    state
+        .dns_sinkhole
+        .set_config(config)
         .map_err(|e| e.to_string())?;
+    state
+        .settings
+        .set(DNS_SINKHOLE_SETTING, &config)
+        .map_err(|e| e.to_string())?;
+
+    if config.enabled && is_blocking(&state) {
+        let (lock_state, window) = block_holds(&state)?;
+        apply_domain_block(&state, &lock_state, window)?;
+    }
+    keep_dns_sinkhole_running(&state);
+    Ok(())
+}
+
+/// The domains to block now: the lock's profile and, during a scheduled
+/// window, the blocklist; otherwise only a pomodoro break's group. In all
+/// cases also the domains of used-up budgets.
+#[cfg(not(target_os = "android"))]
+fn block_domains(
+    state: &AppState,
+    lock_state: &LockState,
+    window: bool,
+) -> Result<Vec<String>, String> {
+    let mut domains: Vec<String> = if lock_state.is_locked || window {
+        let mut domains = if lock_state.is_locked {
+            lock_profile(state, lock_state.profile_id)?.domains
+        } else {
+            Vec::new()
+        };
+        if window {
+            domains.extend(state.blocklist.domains().map_err(|e| e.to_string())?);
+        }
+        domains
+    } else if let Some(group) = pomodoro_break_group(state) {
+        state
+            .blocklist
+            .entries()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|e| e.group == group)
+            .map(|e| e.domain)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    domains.extend(exhausted_budgets(state).into_iter().flat_map(|b| b.domains));
+    domains.sort();
+    domains.dedup();
+    Ok(domains)
+}
+
+/// Writes the domains to block to the hosts file and the DNS sinkhole.
+#[cfg(not(target_os = "android"))]
+fn apply_domain_block(
+    state: &AppState,
+    lock_state: &LockState,
+    window: bool,
+) -> Result<(), String> {
+    let domains = block_domains(state, lock_state, window)?;
 
+    // Update the watcher first so it doesn't mistake our own rewrite for tampering
+    state.hosts_watcher.set_domains(&domains);
     state
         .hosts_blocker
-        .block()
+        .block(&domains)
         .map_err(|e| e.to_string())?;
-    state.process_watcher.start();
+    state.hosts_watcher.start(state.hosts_blocker.clone());
 
-    Ok(lock_state)
+    state.dns_sinkhole.set_rules(&domains);
+    // The sinkhole needs a privileged port; the hosts file still covers exact names without it
+    if state.dns_sinkhole.is_enabled() {
+        if let Err(e) = state.dns_sinkhole.start() {
+            log::warn!("DNS sinkhole unavailable: {}", e);
+        }
+    }
+
+    state.block_page.set_rules(&domains);
+    if let Err(e) = state.block_page.start(state.hosts_blocker.sinkhole()) {
+        log::warn!("Block page server unavailable: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Undoes everything `apply_domain_block` and the process watcher set up.
+#[cfg(not(target_os = "android"))]
+fn release_block(state: &AppState) -> Result<(), String> {
+    state.full_block.store(false, Ordering::Relaxed);
+    state.hosts_watcher.stop();
+    // The sinkhole keeps forwarding; only its blocked names go
+    state.dns_sinkhole.set_rules(&[]);
+    state.block_page.stop();
+    state.block_page.set_rules(&[]);
+    state.block_page.clear_unlock_time();
+    state.process_watcher.stop();
+    if let Err(e) = state.process_watcher.resume_suspended() {
+        log::error!("Failed to resume suspended processes: {}", e);
+    }
+    // A request still waiting has nothing left to unlock
+    if let Err(e) = state.emergency.cancel() {
+        log::error!("Failed to cancel emergency unlock request: {}", e);
+    }
+
+    state.hosts_blocker.unblock().map_err(|e| e.to_string())
 }
 
 #[cfg(not(target_os = "android"))]
 #[tauri::command]
-fn get_lock_state(state: State<Mutex<AppState>>) -> Result<LockState, String> {
+fn get_process_rules(state: State<Mutex<AppState>>) -> Result<Vec<ProcessRule>, String> {
     let state = state.lock().unwrap();
-    let lock_state = state.timelock.get_state().map_err(|e| e.to_string())?;
+    state.process_rules.rules().map_err(|e| e.to_string())
+}
 
-    if !lock_state.is_locked && state.hosts_blocker.is_blocked() {
-        let _ = state.hosts_blocker.unblock();
-        state.process_watcher.stop();
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn add_process_rule(
+    state: State<Mutex<AppState>>,
+    kind: RuleKind,
+    pattern: String,
+    action: Option<ProcessAction>,
+) -> Result<ProcessRule, String> {
+    let state = state.lock().unwrap();
+    let rule = state
+        .process_rules
+        .add_rule(kind, &pattern, action.unwrap_or_default())
+        .map_err(|e| e.to_string())?;
+
+    let (lock_state, window) = block_holds(&state)?;
+    reload_process_rules(&state, &lock_state, window)?;
+    Ok(rule)
+}
+
+/// Hashes the executables of every running process named `name` and adds a
+/// SHA-256 rule for each, so renamed copies of the app are still caught.
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+async fn learn_process_hashes(
+    state: State<'_, Mutex<AppState>>,
+    name: String,
+) -> Result<Vec<ProcessRule>, String> {
+    // Hash outside the state lock; large binaries take a while to read
+    let hashes = tauri::async_runtime::spawn_blocking(move || {
+        ProcessWatcher::find_executables(&name)
+            .into_iter()
+            .filter_map(|exe| match exe_hash::sha256_file(&exe) {
+                Ok(hash) => Some(hash),
+                Err(e) => {
+                    log::warn!("Cannot hash {}: {}", exe.display(), e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let state = state.lock().unwrap();
+    let mut rules = Vec::new();
+    for hash in hashes {
+        let rule = state
+            .process_rules
+            .add_rule(RuleKind::Sha256, &hash, ProcessAction::Kill)
+            .map_err(|e| e.to_string())?;
+        if !rules.iter().any(|r: &ProcessRule| r.id == rule.id) {
+            rules.push(rule);
+        }
     }
 
-    Ok(lock_state)
+    let (lock_state, window) = block_holds(&state)?;
+    reload_process_rules(&state, &lock_state, window)?;
+    Ok(rules)
 }
 
 #[cfg(not(target_os = "android"))]
 #[tauri::command]
-fn emergency_unlock(state: State<Mutex<AppState>>) -> Result<(), String> {
+fn get_suspended_processes(state: State<Mutex<AppState>>) -> Result<Vec<SuspendedProcess>, String> {
     let state = state.lock().unwrap();
+    state
+        .process_watcher
+        .suspended_processes()
+        .map_err(|e| e.to_string())
+}
 
-    state.timelock.clear_lock().map_err(|e| e.to_string())?;
-    state.hosts_blocker.unblock().map_err(|e| e.to_string())?;
-    state.process_watcher.stop();
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn get_kill_policy(state: State<Mutex<AppState>>) -> KillPolicy {
+    let state = state.lock().unwrap();
+    state.process_watcher.policy()
+}
 
-    Ok(())
+#[cfg(not(target_os = "android"))]
+#[tauri::command]
+fn set_kill_policy(
+    state: State<Mutex<AppState>>,
+    policy: KillPolicy,
+) -> Result<KillPolicy, String> {
+    let state = state.lock().unwrap();
+    state.process_watcher.set_policy(policy);
+
+    // Store what was applied, with the grace period capped
+    let policy = state.process_watcher.policy();
+    state
+        .settings
+        .set(KILL_POLICY_SETTING, &policy)
+        .map_err(|e| e.to_string())?;
+    Ok(policy)
 }
 
 #[cfg(not(target_os = "android"))]
 #[tauri::command]
-fn get_blocked_domains() -> Vec<&'static str> {
-    HostsBlocker::get_blocked_domains()
+fn remove_process_rule(state: State<Mutex<AppState>>, id: i64) -> Result<(), String> {
+    let state = state.lock().unwrap();
+    state
+        .process_rules
+        .remove_rule(id, is_blocking(&state))
+        .map_err(|e| e.to_string())?;
+
+    let (lock_state, window) = block_holds(&state)?;
+    reload_process_rules(&state, &lock_state, window)
+}
+
+/// Hands the rules that apply now to the watcher thread without restarting
+/// it: the lock profile's rules and, during a scheduled window, the stored
+/// ones, plus the apps of used-up budgets. The watcher runs while any
+/// applies.
+#[cfg(not(target_os = "android"))]
+fn reload_process_rules(
+    state: &AppState,
+    lock_state: &LockState,
+    window: bool,
+) -> Result<(), String> {
+    let full = lock_state.is_locked || window;
+
+    let mut rules = if lock_state.is_locked {
+        lock_profile(state, lock_state.profile_id)?.process_rules
+    } else {
+        Vec::new()
+    };
+    if window {
+        rules.extend(state.process_rules.rules().map_err(|e| e.to_string())?);
+    }
+
+    let budget_rules: Vec<ProcessRule> = exhausted_budgets(state)
+        .into_iter()
+        .flat_map(|budget| {
+            budget.processes.into_iter().map(move |name| ProcessRule {
+                id: budget.id,
+                kind: RuleKind::Name,
+                pattern: name,
+                action: ProcessAction::Kill,
+                source: RuleSource::Budget,
+            })
+        })
+        .collect();
+    let enforce = full || !budget_rules.is_empty();
+    rules.extend(budget_rules);
+
+    // A running watcher holds on to what it suspended, so new rules restart
+    // it with those resumed, as in `release_block`; whatever the new rules
+    // still match is suspended again
+    if state.process_watcher.set_rules(&rules) && state.process_watcher.is_running() {
+        state.process_watcher.stop();
+        if let Err(e) = state.process_watcher.resume_suspended() {
+            log::error!("Failed to resume suspended processes: {}", e);
+        }
+    }
+
+    if enforce {
+        state.process_watcher.start();
+    } else if state.process_watcher.is_running() {
+        state.process_watcher.stop();
+    }
+    Ok(())
 }
 
 #[cfg(not(target_os = "android"))]
@@ -140,6 +1708,7 @@ fn start_lock(app: tauri::AppHandle, duration_minutes: i64) -> Result<LockState,
                     .unwrap_or_else(chrono::Utc::now)
             }),
             remaining_seconds: state.remaining_seconds,
+            profile_id: None,
         })
     } else {
         Err(result.error.unwrap_or_else(|| "Unknown error".to_string()))
@@ -161,6 +1730,7 @@ fn get_lock_state(app: tauri::AppHandle) -> Result<LockState, String> {
                 .unwrap_or_else(chrono::Utc::now)
         }),
         remaining_seconds: state.remaining_seconds,
+        profile_id: None,
     })
 }
 
@@ -230,6 +1800,239 @@ fn is_autostart_enabled() -> Result<bool, String> {
     Ok(false)
 }
 
+/// Opens the stores, resumes blocking and sets up the tray. An error ends
+/// the app through `exit_with_error`.
+#[cfg(not(target_os = "android"))]
+fn setup_desktop(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
+    let app_data_dir = app.path().app_data_dir()?;
+
+    // Migrate up front; a database from a newer build must not be
+    // misread, so refuse to start rather than touch it
+    db::open(&app_data_dir)?;
+
+    let timelock = TimeLock::new(app_data_dir.clone())?;
+    let settings = Settings::new(app_data_dir.clone())?;
+    let blocklist = Blocklist::new(app_data_dir.clone())?;
+    let hosts_blocker = HostsBlocker::new(app_data_dir.clone());
+    let hosts_watcher = HostsWatcher::new();
+    let block_page = BlockPageServer::new(app_data_dir.clone())?;
+    let process_rules = ProcessRuleStore::new(app_data_dir.clone())?;
+    let process_watcher = ProcessWatcher::new(app_data_dir.clone())?;
+    let emergency = EmergencyUnlock::new(app_data_dir.clone())?;
+    let schedules = ScheduleStore::new(app_data_dir.clone())?;
+    let planned_locks = PlannedLockStore::new(app_data_dir.clone())?;
+    let pomodoro = Pomodoro::new(app_data_dir.clone())?;
+    let budgets = BudgetStore::new(app_data_dir.clone())?;
+    let profiles = ProfileStore::new(app_data_dir.clone())?;
+    let dns_sinkhole = DnsSinkhole::new();
+
+    if let Ok(Some(sinkhole)) = settings.get::<SinkholeAddresses>(SINKHOLE_SETTING) {
+        match hosts_blocker.set_sinkhole(sinkhole) {
+            Ok(()) => dns_sinkhole.set_answers(sinkhole),
+            Err(e) => log::warn!("Ignoring saved sinkhole: {}", e),
+        }
+    }
+    if let Ok(Some(config)) = settings.get::<DnsSinkholeConfig>(DNS_SINKHOLE_SETTING) {
+        if let Err(e) = dns_sinkhole.set_config(config) {
+            log::warn!("Ignoring saved DNS sinkhole settings: {}", e);
+        }
+    }
+    if let Ok(rules) = process_rules.rules() {
+        process_watcher.set_rules(&rules);
+    }
+    if let Ok(Some(policy)) = settings.get::<KillPolicy>(KILL_POLICY_SETTING) {
+        process_watcher.set_policy(policy);
+    }
+    if let Ok(Some(policy)) = settings.get::<EmergencyPolicy>(EMERGENCY_POLICY_SETTING) {
+        let _ = emergency.set_policy(policy, false);
+    }
+
+    let events = Arc::new(EventBus::new(app_data_dir)?);
+    let app_handle = app.handle().clone();
+    events.set_emitter(Box::new(move |event: &AppEvent| {
+        let _ = app_handle.emit(event.kind.as_str(), event);
+    }));
+
+    let app_handle = app.handle().clone();
+    let bus = events.clone();
+    process_watcher.set_event_sink(Arc::new(move |event: &KillEvent| {
+        if event.step == KillStep::Matched {
+            bus.process_blocked(&event.name, event.pid, event.path.as_deref());
+        }
+        let _ = app_handle.emit("process-kill", event);
+    }));
+    let bus = events.clone();
+    hosts_watcher.set_event_sink(Arc::new(move |event: &TamperEvent| {
+        bus.hosts_tampered(&event.kind, event.restored);
+    }));
+
+    // Repair a hosts file left half-written by a crash or power loss
+    if let Err(e) = hosts_blocker.recover() {
+        log::error!("Hosts file recovery failed: {}", e);
+    }
+
+    let app_state = AppState {
+        timelock,
+        settings,
+        blocklist,
+        hosts_blocker,
+        hosts_watcher,
+        dns_sinkhole,
+        block_page,
+        process_rules,
+        process_watcher,
+        events,
+        emergency,
+        schedules,
+        planned_locks,
+        pomodoro,
+        budgets,
+        profiles,
+        full_block: AtomicBool::new(false),
+    };
+
+    // Resume blocking for an existing lock, a scheduled window, a
+    // break or a used-up budget
+    let profile_id = match sync_lock_state(&app_state) {
+        Ok(lock_state) => running_profile(&lock_state),
+        Err(e) => {
+            log::error!("Failed to resume blocking: {}", e);
+            None
+        }
+    };
+    let is_locked = app_state.full_block.load(Ordering::Relaxed);
+    if !is_locked {
+        // The app may have quit mid-lock with apps still frozen
+        if let Err(e) = app_state.process_watcher.resume_suspended() {
+            log::error!("Failed to resume suspended processes: {}", e);
+        }
+    }
+
+    // Enable autostart if locked
+    if is_locked {
+        use tauri_plugin_autostart::ManagerExt;
+        let _ = app.autolaunch().enable();
+    }
+
+    let status_text = tray_status(&app_state, profile_id);
+    app.manage(Mutex::new(app_state));
+
+    // Setup tray icon
+    let show_item = MenuItem::with_id(app, "show", "열기", true, None::<&str>)?;
+    let status_item = MenuItem::with_id(app, "status", status_text, false, None::<&str>)?;
+    let quit_item = MenuItem::with_id(app, "quit", "종료", !is_locked, None::<&str>)?;
+
+    let menu = Menu::with_items(app, &[&status_item, &show_item, &quit_item])?;
+
+    // Keep the lock's elapsed time and budget usage counted,
+    // scheduled windows and intervals starting and ending, the
+    // block in sync and the tray status current. The window only
+    // reads the state, so this is the one place it is enforced
+    let app_handle = app.handle().clone();
+    let tray_status_item = status_item.clone();
+    std::thread::spawn(move || {
+        let mut last_check = std::time::Instant::now();
+        loop {
+            std::thread::sleep(LOCK_CHECK_INTERVAL);
+            let state = app_handle.state::<Mutex<AppState>>();
+            let state = state.lock().unwrap();
+
+            // A long gap means the machine slept; nothing was used meanwhile
+            let elapsed = last_check.elapsed().min(LOCK_CHECK_INTERVAL * 2);
+            last_check = std::time::Instant::now();
+            track_budget_usage(&state, elapsed);
+
+            let status_text = match sync_lock_state(&state) {
+                Ok(lock_state) => Some(tray_status(&state, running_profile(&lock_state))),
+                Err(e) => {
+                    log::error!("Lock check failed: {}", e);
+                    None
+                }
+            };
+            // Setting the text waits for the main thread, which may be
+            // waiting for the state in a command
+            drop(state);
+            if let Some(status_text) = status_text {
+                let _ = tray_status_item.set_text(status_text);
+            }
+        }
+    });
+
+    let icon_bytes = include_bytes!("../icons/32x32.png");
+    let icon = Image::from_bytes(icon_bytes)?;
+
+    let _tray = TrayIconBuilder::new()
+        .icon(icon)
+        .menu(&menu)
+        .tooltip("FocusLock")
+        .on_menu_event(|app, event| match event.id.as_ref() {
+            "show" => {
+                if let Some(window) = app.get_webview_window("main") {
+                    let _ = window.show();
+                    let _ = window.set_focus();
+                }
+            }
+            "quit" => {
+                let state = app.state::<Mutex<AppState>>();
+                let state = state.lock().unwrap();
+                if !is_blocking(&state) && !state.pomodoro.is_running() {
+                    app.exit(0);
+                }
+            }
+            _ => {}
+        })
+        .on_tray_icon_event(|tray, event| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                let app = tray.app_handle();
+                if let Some(window) = app.get_webview_window("main") {
+                    let _ = window.show();
+                    let _ = window.set_focus();
+                }
+            }
+        })
+        .build(app)?;
+
+    // Handle window close event
+    let main_window = app
+        .get_webview_window("main")
+        .ok_or("main window is missing")?;
+    let app_handle = app.handle().clone();
+
+    main_window.on_window_event(move |event| {
+        if let WindowEvent::CloseRequested { api, .. } = event {
+            let state = app_handle.state::<Mutex<AppState>>();
+            let state = state.lock().unwrap();
+
+            if is_blocking(&state) || state.pomodoro.is_running() {
+                api.prevent_close();
+                if let Some(window) = app_handle.get_webview_window("main") {
+                    let _ = window.hide();
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Tells the user why FocusLock cannot start and exits. Release builds on
+/// Windows have no console, so only a dialog is seen there.
+fn exit_with_error(message: &str) -> ! {
+    log::error!("FocusLock cannot start: {}", message);
+    #[cfg(not(target_os = "android"))]
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title("FocusLock")
+        .set_description(format!("FocusLock을 시작할 수 없습니다.\n\n{}", message))
+        .set_buttons(rfd::MessageButtons::Ok)
+        .show();
+    std::process::exit(1);
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     env_logger::init();
@@ -248,119 +2051,12 @@ pub fn run() {
     #[cfg(target_os = "android")]
     let builder = builder.plugin(android::init());
 
-    builder
+    let result = builder
         .setup(|app| {
             // Desktop-only setup
             #[cfg(not(target_os = "android"))]
-            {
-                let app_data_dir = app
-                    .path()
-                    .app_data_dir()
-                    .expect("Failed to get app data dir");
-
-                let timelock = TimeLock::new(app_data_dir).expect("Failed to initialize timelock");
-                let hosts_blocker = HostsBlocker::new();
-                let process_watcher = ProcessWatcher::new();
-
-                // Check if there's an existing lock and resume blocking
-                let is_locked = if let Ok(state) = timelock.get_state() {
-                    if state.is_locked {
-                        log::info!("Resuming existing lock");
-                        let _ = hosts_blocker.block();
-                        process_watcher.start();
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                };
-
-                // Enable autostart if locked
-                if is_locked {
-                    use tauri_plugin_autostart::ManagerExt;
-                    let _ = app.autolaunch().enable();
-                }
-
-                app.manage(Mutex::new(AppState {
-                    timelock,
-                    hosts_blocker,
-                    process_watcher,
-                }));
-
-                // Setup tray icon
-                let show_item = MenuItem::with_id(app, "show", "열기", true, None::<&str>)?;
-                let status_item = MenuItem::with_id(
-                    app,
-                    "status",
-                    if is_locked { "🔒 잠금 중" } else { "🔓 잠금 해제됨" },
-                    false,
-                    None::<&str>,
-                )?;
-                let quit_item = MenuItem::with_id(app, "quit", "종료", !is_locked, None::<&str>)?;
-
-                let menu = Menu::with_items(app, &[&status_item, &show_item, &quit_item])?;
-
-                let icon_bytes = include_bytes!("../icons/32x32.png");
-                let icon = Image::from_bytes(icon_bytes)?;
-
-                let _tray = TrayIconBuilder::new()
-                    .icon(icon)
-                    .menu(&menu)
-                    .tooltip("FocusLock")
-                    .on_menu_event(|app, event| match event.id.as_ref() {
-                        "show" => {
-                            if let Some(window) = app.get_webview_window("main") {
-                                let _ = window.show();
-                                let _ = window.set_focus();
-                            }
-                        }
-                        "quit" => {
-                            let state = app.state::<Mutex<AppState>>();
-                            let state = state.lock().unwrap();
-                            if let Ok(lock_state) = state.timelock.get_state() {
-                                if !lock_state.is_locked {
-                                    app.exit(0);
-                                }
-                            }
-                        }
-                        _ => {}
-                    })
-                    .on_tray_icon_event(|tray, event| {
-                        if let TrayIconEvent::Click {
-                            button: MouseButton::Left,
-                            button_state: MouseButtonState::Up,
-                            ..
-                        } = event
-                        {
-                            let app = tray.app_handle();
-                            if let Some(window) = app.get_webview_window("main") {
-                                let _ = window.show();
-                                let _ = window.set_focus();
-                            }
-                        }
-                    })
-                    .build(app)?;
-
-                // Handle window close event
-                let main_window = app.get_webview_window("main").unwrap();
-                let app_handle = app.handle().clone();
-
-                main_window.on_window_event(move |event| {
-                    if let WindowEvent::CloseRequested { api, .. } = event {
-                        let state = app_handle.state::<Mutex<AppState>>();
-                        let state = state.lock().unwrap();
-
-                        if let Ok(lock_state) = state.timelock.get_state() {
-                            if lock_state.is_locked {
-                                api.prevent_close();
-                                if let Some(window) = app_handle.get_webview_window("main") {
-                                    let _ = window.hide();
-                                }
-                            }
-                        }
-                    }
-                });
+            if let Err(e) = setup_desktop(app) {
+                exit_with_error(&e.to_string());
             }
 
             // Android setup - nothing needed, plugin handles everything
@@ -373,9 +2069,131 @@ pub fn run() {
         })
         .invoke_handler(tauri::generate_handler![
             start_lock,
+            #[cfg(not(target_os = "android"))]
+            switch_lock_profile,
+            #[cfg(not(target_os = "android"))]
+            extend_lock,
             get_lock_state,
             emergency_unlock,
+            #[cfg(not(target_os = "android"))]
+            request_emergency_unlock,
+            #[cfg(not(target_os = "android"))]
+            cancel_emergency_unlock,
+            #[cfg(not(target_os = "android"))]
+            confirm_emergency_unlock,
+            #[cfg(not(target_os = "android"))]
+            get_emergency_status,
+            #[cfg(not(target_os = "android"))]
+            get_emergency_policy,
+            #[cfg(not(target_os = "android"))]
+            set_emergency_policy,
+            #[cfg(not(target_os = "android"))]
+            get_session_history,
+            #[cfg(not(target_os = "android"))]
+            get_session_stats,
+            #[cfg(not(target_os = "android"))]
+            get_daily_focus,
+            #[cfg(not(target_os = "android"))]
+            get_weekly_focus,
+            #[cfg(not(target_os = "android"))]
+            get_schedules,
+            #[cfg(not(target_os = "android"))]
+            add_schedule,
+            #[cfg(not(target_os = "android"))]
+            update_schedule,
+            #[cfg(not(target_os = "android"))]
+            remove_schedule,
+            #[cfg(not(target_os = "android"))]
+            get_active_schedule,
+            #[cfg(not(target_os = "android"))]
+            get_next_schedule,
+            #[cfg(not(target_os = "android"))]
+            preview_calendar_import,
+            #[cfg(not(target_os = "android"))]
+            import_calendar,
+            #[cfg(not(target_os = "android"))]
+            get_planned_locks,
+            #[cfg(not(target_os = "android"))]
+            remove_planned_lock,
+            #[cfg(not(target_os = "android"))]
+            start_pomodoro,
+            #[cfg(not(target_os = "android"))]
+            stop_pomodoro,
+            #[cfg(not(target_os = "android"))]
+            get_pomodoro_status,
+            #[cfg(not(target_os = "android"))]
+            get_budgets,
+            #[cfg(not(target_os = "android"))]
+            add_budget,
+            #[cfg(not(target_os = "android"))]
+            update_budget,
+            #[cfg(not(target_os = "android"))]
+            remove_budget,
+            #[cfg(not(target_os = "android"))]
+            get_budget_reset_time,
+            #[cfg(not(target_os = "android"))]
+            set_budget_reset_time,
+            #[cfg(not(target_os = "android"))]
+            get_profiles,
+            #[cfg(not(target_os = "android"))]
+            add_profile,
+            #[cfg(not(target_os = "android"))]
+            update_profile,
+            #[cfg(not(target_os = "android"))]
+            remove_profile,
             get_blocked_domains,
+            #[cfg(not(target_os = "android"))]
+            get_blocklist,
+            #[cfg(not(target_os = "android"))]
+            get_domain_groups,
+            #[cfg(not(target_os = "android"))]
+            add_blocked_domain,
+            #[cfg(not(target_os = "android"))]
+            set_domain_group,
+            #[cfg(not(target_os = "android"))]
+            remove_blocked_domain,
+            #[cfg(not(target_os = "android"))]
+            remove_domain_group,
+            #[cfg(not(target_os = "android"))]
+            import_blocklist,
+            #[cfg(not(target_os = "android"))]
+            restore_hosts_backup,
+            #[cfg(not(target_os = "android"))]
+            get_tamper_events,
+            #[cfg(not(target_os = "android"))]
+            get_sinkhole_addresses,
+            #[cfg(not(target_os = "android"))]
+            set_sinkhole_addresses,
+            #[cfg(not(target_os = "android"))]
+            verify_blocking,
+            #[cfg(not(target_os = "android"))]
+            get_blocked_attempts,
+            #[cfg(not(target_os = "android"))]
+            flush_dns_cache,
+            #[cfg(not(target_os = "android"))]
+            get_dns_sinkhole_status,
+            #[cfg(not(target_os = "android"))]
+            get_dns_sinkhole_config,
+            #[cfg(not(target_os = "android"))]
+            set_dns_sinkhole_config,
+            #[cfg(not(target_os = "android"))]
+            get_process_rules,
+            #[cfg(not(target_os = "android"))]
+            add_process_rule,
+            #[cfg(not(target_os = "android"))]
+            learn_process_hashes,
+            #[cfg(not(target_os = "android"))]
+            remove_process_rule,
+            #[cfg(not(target_os = "android"))]
+            get_suspended_processes,
+            #[cfg(not(target_os = "android"))]
+            get_kill_policy,
+            #[cfg(not(target_os = "android"))]
+            set_kill_policy,
+            #[cfg(not(target_os = "android"))]
+            get_event_history,
+            #[cfg(not(target_os = "android"))]
+            get_event_counts,
             enable_autostart,
             disable_autostart,
             is_autostart_enabled,
@@ -386,6 +2204,8 @@ pub fn run() {
             #[cfg(target_os = "android")]
             open_accessibility_settings,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .run(tauri::generate_context!());
+    if let Err(e) = result {
+        exit_with_error(&e.to_string());
+    }
 }
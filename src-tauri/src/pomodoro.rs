@@ -0,0 +1,407 @@
+use crate::clock::{Clock, SystemClock};
+use crate::db::{self, DbError};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PomodoroError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Invalid interval settings: {0}")]
+    InvalidConfig(&'static str),
+    #[error("Interval mode is already running")]
+    AlreadyRunning,
+    #[error("Interval mode is not running")]
+    NotRunning,
+}
+
+/// N work intervals of X minutes with Y-minute breaks, and a longer break
+/// after every K intervals.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PomodoroConfig {
+    pub work_minutes: u32,
+    pub break_minutes: u32,
+    pub long_break_minutes: u32,
+    /// Every this many work intervals the break is a long one
+    pub long_break_every: u32,
+    /// Work intervals in the whole run
+    pub intervals: u32,
+    /// Blocklist group that stays blocked during breaks; `None` lifts the
+    /// block entirely
+    #[serde(default)]
+    pub break_group: Option<String>,
+}
+
+impl PomodoroConfig {
+    fn validate(&self) -> Result<(), PomodoroError> {
+        if self.work_minutes == 0 {
+            return Err(PomodoroError::InvalidConfig("work intervals need a length"));
+        }
+        if self.break_minutes == 0 || self.long_break_minutes == 0 {
+            return Err(PomodoroError::InvalidConfig("breaks need a length"));
+        }
+        if self.long_break_every == 0 || self.intervals == 0 {
+            return Err(PomodoroError::InvalidConfig(
+                "interval counts must be positive",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Work,
+    ShortBreak,
+    LongBreak,
+    /// The last work interval ended; only ever reported as a transition
+    Finished,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Work => "work",
+            Phase::ShortBreak => "short_break",
+            Phase::LongBreak => "long_break",
+            Phase::Finished => "finished",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "work" => Some(Phase::Work),
+            "short_break" => Some(Phase::ShortBreak),
+            "long_break" => Some(Phase::LongBreak),
+            "finished" => Some(Phase::Finished),
+            _ => None,
+        }
+    }
+
+    pub fn is_break(&self) -> bool {
+        matches!(self, Phase::ShortBreak | Phase::LongBreak)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PomodoroStatus {
+    pub config: PomodoroConfig,
+    pub phase: Phase,
+    /// Work intervals finished so far
+    pub completed: u32,
+    pub phase_ends_at: DateTime<Utc>,
+    pub remaining_seconds: i64,
+}
+
+/// The running interval mode as stored in the `pomodoro` row.
+struct StoredRun {
+    config: PomodoroConfig,
+    phase: Phase,
+    completed: u32,
+    phase_started_at: DateTime<Utc>,
+    phase_started_uptime: i64,
+}
+
+/// The interval state machine. Work intervals are ordinary locks, so the
+/// caller starts one on every `Work` transition and reports back whether
+/// it is still running; breaks are timed here, by uptime like the lock.
+/// The state lives in focuslock.db and carries on after a restart.
+pub struct Pomodoro {
+    db: Mutex<Connection>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Pomodoro {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?, Arc::new(SystemClock))
+    }
+
+    pub fn from_connection(mut conn: Connection, clock: Arc<dyn Clock>) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+            clock,
+        })
+    }
+
+    /// Begins with the first work interval.
+    pub fn start(&self, config: PomodoroConfig) -> Result<PomodoroStatus, PomodoroError> {
+        config.validate()?;
+        let db = self.db.lock().unwrap();
+
+        if load(&db)?.is_some() {
+            return Err(PomodoroError::AlreadyRunning);
+        }
+        db.execute(
+            "INSERT INTO pomodoro
+                (id, work_minutes, break_minutes, long_break_minutes, long_break_every, intervals,
+                 break_group, phase, completed, phase_started_at, phase_started_uptime)
+             VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, 0, ?8, ?9)",
+            params![
+                config.work_minutes,
+                config.break_minutes,
+                config.long_break_minutes,
+                config.long_break_every,
+                config.intervals,
+                config.break_group,
+                Phase::Work.as_str(),
+                self.clock.now().to_rfc3339(),
+                self.clock.uptime() as i64
+            ],
+        )?;
+
+        log::info!(
+            "Interval mode started: {} x {} minutes",
+            config.intervals,
+            config.work_minutes
+        );
+        Ok(self.status_of(&load(&db)?.ok_or(PomodoroError::NotRunning)?))
+    }
+
+    /// Ends interval mode. A work interval in progress keeps its lock.
+    pub fn stop(&self) -> Result<(), PomodoroError> {
+        let db = self.db.lock().unwrap();
+        if db.execute("DELETE FROM pomodoro WHERE id = 1", [])? == 0 {
+            return Err(PomodoroError::NotRunning);
+        }
+
+        log::info!("Interval mode stopped");
+        Ok(())
+    }
+
+    pub fn status(&self) -> SqliteResult<Option<PomodoroStatus>> {
+        let db = self.db.lock().unwrap();
+        Ok(load(&db)?.map(|run| self.status_of(&run)))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status().map(|s| s.is_some()).unwrap_or(false)
+    }
+
+    /// The group to keep blocked while on a break, if the run has one.
+    pub fn break_group(&self) -> SqliteResult<Option<String>> {
+        let db = self.db.lock().unwrap();
+        Ok(load(&db)?
+            .filter(|run| run.phase.is_break())
+            .and_then(|run| run.config.break_group))
+    }
+
+    /// Moves to the next phase when the current one is over and returns
+    /// the phase entered. A work interval is over once its lock stopped
+    /// running; a break once its time has passed.
+    pub fn advance(&self, lock_running: bool) -> SqliteResult<Option<Phase>> {
+        let db = self.db.lock().unwrap();
+        let Some(run) = load(&db)? else {
+            return Ok(None);
+        };
+
+        let next = match run.phase {
+            Phase::Work if lock_running => return Ok(None),
+            Phase::Work => {
+                let completed = run.completed + 1;
+                if completed >= run.config.intervals {
+                    db.execute("DELETE FROM pomodoro WHERE id = 1", [])?;
+                    log::info!("Interval mode finished after {} intervals", completed);
+                    return Ok(Some(Phase::Finished));
+                }
+                let phase = if completed % run.config.long_break_every == 0 {
+                    Phase::LongBreak
+                } else {
+                    Phase::ShortBreak
+                };
+                (phase, completed)
+            }
+            phase if phase.is_break() => {
+                if self.remaining_seconds(&run) > 0 {
+                    return Ok(None);
+                }
+                (Phase::Work, run.completed)
+            }
+            _ => return Ok(None),
+        };
+
+        db.execute(
+            "UPDATE pomodoro SET phase = ?1, completed = ?2, phase_started_at = ?3,
+                 phase_started_uptime = ?4
+             WHERE id = 1",
+            params![
+                next.0.as_str(),
+                next.1,
+                self.clock.now().to_rfc3339(),
+                self.clock.uptime() as i64
+            ],
+        )?;
+
+        log::info!("Interval mode entered {}", next.0.as_str());
+        Ok(Some(next.0))
+    }
+
+    fn status_of(&self, run: &StoredRun) -> PomodoroStatus {
+        let remaining = self.remaining_seconds(run);
+        PomodoroStatus {
+            config: run.config.clone(),
+            phase: run.phase,
+            completed: run.completed,
+            phase_ends_at: self.clock.now() + Duration::seconds(remaining),
+            remaining_seconds: remaining,
+        }
+    }
+
+    fn remaining_seconds(&self, run: &StoredRun) -> i64 {
+        let minutes = match run.phase {
+            Phase::Work => run.config.work_minutes,
+            Phase::ShortBreak => run.config.break_minutes,
+            Phase::LongBreak => run.config.long_break_minutes,
+            Phase::Finished => 0,
+        };
+        (minutes as i64 * 60 - self.phase_elapsed(run)).max(0)
+    }
+
+    fn phase_elapsed(&self, run: &StoredRun) -> i64 {
+        let uptime = self.clock.uptime() as i64;
+        if uptime >= run.phase_started_uptime {
+            uptime - run.phase_started_uptime
+        } else {
+            // Rebooted during the phase
+            (self.clock.now() - run.phase_started_at)
+                .num_seconds()
+                .max(uptime)
+        }
+    }
+}
+
+fn load(db: &Connection) -> SqliteResult<Option<StoredRun>> {
+    db.query_row(
+        "SELECT work_minutes, break_minutes, long_break_minutes, long_break_every, intervals,
+                break_group, phase, completed, phase_started_at, phase_started_uptime
+         FROM pomodoro WHERE id = 1",
+        [],
+        |row| {
+            let phase: String = row.get(6)?;
+            let started_at: String = row.get(8)?;
+            Ok(StoredRun {
+                config: PomodoroConfig {
+                    work_minutes: row.get(0)?,
+                    break_minutes: row.get(1)?,
+                    long_break_minutes: row.get(2)?,
+                    long_break_every: row.get(3)?,
+                    intervals: row.get(4)?,
+                    break_group: row.get(5)?,
+                },
+                // An unreadable phase restarts the current interval rather
+                // than skipping it
+                phase: Phase::parse(&phase).unwrap_or(Phase::Work),
+                completed: row.get(7)?,
+                phase_started_at: DateTime::parse_from_rfc3339(&started_at)
+                    .map(|t| t.with_timezone(&Utc))
+                    .unwrap_or_else(|_| Utc::now()),
+                phase_started_uptime: row.get(9)?,
+            })
+        },
+    )
+    .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    fn pomodoro() -> (Pomodoro, Arc<FakeClock>) {
+        let clock = Arc::new(FakeClock::new(
+            DateTime::parse_from_rfc3339("2024-03-01T09:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        ));
+        let pomodoro =
+            Pomodoro::from_connection(Connection::open_in_memory().unwrap(), clock.clone())
+                .unwrap();
+        (pomodoro, clock)
+    }
+
+    fn config() -> PomodoroConfig {
+        PomodoroConfig {
+            work_minutes: 25,
+            break_minutes: 5,
+            long_break_minutes: 15,
+            long_break_every: 2,
+            intervals: 3,
+            break_group: Some("social".to_string()),
+        }
+    }
+
+    #[test]
+    fn runs_work_and_breaks_to_the_end() {
+        let (pomodoro, clock) = pomodoro();
+        let status = pomodoro.start(config()).unwrap();
+        assert_eq!(status.phase, Phase::Work);
+        assert_eq!(pomodoro.break_group().unwrap(), None);
+
+        // Work lasts as long as its lock
+        clock.advance(25 * 60);
+        assert_eq!(pomodoro.advance(true).unwrap(), None);
+        assert_eq!(pomodoro.advance(false).unwrap(), Some(Phase::ShortBreak));
+        assert_eq!(pomodoro.break_group().unwrap().as_deref(), Some("social"));
+
+        clock.advance(4 * 60);
+        assert_eq!(pomodoro.advance(false).unwrap(), None);
+        assert_eq!(pomodoro.status().unwrap().unwrap().remaining_seconds, 60);
+        clock.advance(60);
+        assert_eq!(pomodoro.advance(false).unwrap(), Some(Phase::Work));
+
+        clock.advance(25 * 60);
+        assert_eq!(pomodoro.advance(false).unwrap(), Some(Phase::LongBreak));
+        clock.advance(15 * 60);
+        assert_eq!(pomodoro.advance(false).unwrap(), Some(Phase::Work));
+        assert_eq!(pomodoro.status().unwrap().unwrap().completed, 2);
+
+        clock.advance(25 * 60);
+        assert_eq!(pomodoro.advance(false).unwrap(), Some(Phase::Finished));
+        assert!(pomodoro.status().unwrap().is_none());
+        assert_eq!(pomodoro.advance(false).unwrap(), None);
+    }
+
+    #[test]
+    fn breaks_ignore_clock_changes_and_survive_reboots() {
+        let (pomodoro, clock) = pomodoro();
+        pomodoro.start(config()).unwrap();
+        pomodoro.advance(false).unwrap();
+        let break_start = clock.now();
+
+        clock.set_now(break_start + Duration::hours(2));
+        assert_eq!(pomodoro.advance(false).unwrap(), None);
+        clock.set_now(break_start);
+
+        // Three minutes into the break, then a reboot that took four more
+        clock.advance(3 * 60);
+        clock.reboot(4 * 60);
+        assert_eq!(pomodoro.advance(false).unwrap(), Some(Phase::Work));
+    }
+
+    #[test]
+    fn start_and_stop_are_checked() {
+        let (pomodoro, _) = pomodoro();
+        assert!(matches!(pomodoro.stop(), Err(PomodoroError::NotRunning)));
+        assert!(matches!(
+            pomodoro.start(PomodoroConfig {
+                intervals: 0,
+                ..config()
+            }),
+            Err(PomodoroError::InvalidConfig(_))
+        ));
+
+        pomodoro.start(config()).unwrap();
+        assert!(matches!(
+            pomodoro.start(config()),
+            Err(PomodoroError::AlreadyRunning)
+        ));
+        pomodoro.stop().unwrap();
+        assert!(!pomodoro.is_running());
+    }
+}
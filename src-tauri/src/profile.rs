@@ -0,0 +1,458 @@
+use crate::blocker::blocklist::normalize_domain;
+use crate::blocker::process_rules::normalize_rule;
+use crate::blocker::{ProcessAction, ProcessRule, RuleKind, RuleSource};
+use crate::db::{self, DbError};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("A profile needs a name")]
+    EmptyName,
+    #[error("Invalid domain: {0}")]
+    InvalidDomain(String),
+    #[error("Invalid process rule: {0}")]
+    InvalidRule(String),
+    #[error("Profile {0} not found")]
+    NotFound(i64),
+    #[error("Profiles cannot be changed while a lock is using them")]
+    InUse,
+    #[error("A running lock can only switch to a stricter profile than {0}")]
+    NotStricter(String),
+    #[error("The {0} profile does not allow emergency unlock")]
+    EmergencyUnlockDisabled(String),
+}
+
+/// Settings that come with a profile rather than being global.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileOptions {
+    /// Whether a lock with this profile can be ended early at all
+    #[serde(default = "allowed")]
+    pub allow_emergency_unlock: bool,
+}
+
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        Self {
+            allow_emergency_unlock: true,
+        }
+    }
+}
+
+fn allowed() -> bool {
+    true
+}
+
+/// A named set of domain rules, process rules and options a lock blocks
+/// with, e.g. Exam, Work or Sleep.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: i64,
+    pub name: String,
+    pub domains: Vec<String>,
+    pub process_rules: Vec<ProcessRule>,
+    pub options: ProfileOptions,
+}
+
+impl Profile {
+    /// Stand-in for a lock without a profile, which blocks with the global
+    /// lists under the default options.
+    pub fn global(domains: Vec<String>, process_rules: Vec<ProcessRule>) -> Self {
+        Self {
+            id: 0,
+            name: "Default".to_string(),
+            domains,
+            process_rules,
+            options: ProfileOptions::default(),
+        }
+    }
+
+    /// Whether this profile blocks everything `other` does, at least as
+    /// hard, and more besides. Killing counts as harder than suspending.
+    pub fn is_stricter_than(&self, other: &Profile) -> bool {
+        self.covers(other) && !other.covers(self)
+    }
+
+    fn covers(&self, other: &Profile) -> bool {
+        let domains = other.domains.iter().all(|d| self.domains.contains(d));
+        let rules = other.process_rules.iter().all(|theirs| {
+            self.process_rules.iter().any(|ours| {
+                ours.kind == theirs.kind
+                    && ours.pattern.eq_ignore_ascii_case(&theirs.pattern)
+                    && action_rank(ours.action) >= action_rank(theirs.action)
+            })
+        });
+        let options = !self.options.allow_emergency_unlock || other.options.allow_emergency_unlock;
+
+        domains && rules && options
+    }
+}
+
+fn action_rank(action: ProcessAction) -> u8 {
+    match action {
+        ProcessAction::Suspend => 0,
+        ProcessAction::Kill => 1,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileRuleInput {
+    pub kind: RuleKind,
+    pub pattern: String,
+    #[serde(default)]
+    pub action: ProcessAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileInput {
+    pub name: String,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub process_rules: Vec<ProfileRuleInput>,
+    #[serde(default)]
+    pub options: ProfileOptions,
+}
+
+/// Blocking profiles, kept in focuslock.db.
+pub struct ProfileStore {
+    db: Mutex<Connection>,
+}
+
+impl ProfileStore {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    pub fn profiles(&self) -> SqliteResult<Vec<Profile>> {
+        let ids: Vec<i64> = {
+            let db = self.db.lock().unwrap();
+            let mut stmt = db.prepare("SELECT id FROM profiles ORDER BY id")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<SqliteResult<_>>()?
+        };
+
+        let mut profiles = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(profile) = self.load(id)? {
+                profiles.push(profile);
+            }
+        }
+        Ok(profiles)
+    }
+
+    pub fn profile(&self, id: i64) -> Result<Profile, ProfileError> {
+        self.load(id)?.ok_or(ProfileError::NotFound(id))
+    }
+
+    pub fn add_profile(&self, input: &ProfileInput) -> Result<Profile, ProfileError> {
+        let input = validate(input)?;
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        tx.execute(
+            "INSERT INTO profiles (name, allow_emergency_unlock) VALUES (?1, ?2)",
+            params![input.name, input.options.allow_emergency_unlock],
+        )?;
+        let id = tx.last_insert_rowid();
+        insert_rules(&tx, id, &input)?;
+        tx.commit()?;
+        drop(db);
+
+        log::info!("Added profile {} ({})", id, input.name);
+        self.profile(id)
+    }
+
+    /// Refused while a lock uses the profile; only switching to a stricter
+    /// profile may change what a running lock blocks.
+    pub fn update_profile(
+        &self,
+        id: i64,
+        input: &ProfileInput,
+        in_use: bool,
+    ) -> Result<Profile, ProfileError> {
+        if in_use {
+            return Err(ProfileError::InUse);
+        }
+        let input = validate(input)?;
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        let updated = tx.execute(
+            "UPDATE profiles SET name = ?1, allow_emergency_unlock = ?2 WHERE id = ?3",
+            params![input.name, input.options.allow_emergency_unlock, id],
+        )?;
+        if updated == 0 {
+            return Err(ProfileError::NotFound(id));
+        }
+        delete_rules(&tx, id)?;
+        insert_rules(&tx, id, &input)?;
+        tx.commit()?;
+        drop(db);
+
+        log::info!("Updated profile {}", id);
+        self.profile(id)
+    }
+
+    pub fn remove_profile(&self, id: i64, in_use: bool) -> Result<(), ProfileError> {
+        if in_use {
+            return Err(ProfileError::InUse);
+        }
+        let db = self.db.lock().unwrap();
+        let tx = db.unchecked_transaction()?;
+
+        delete_rules(&tx, id)?;
+        let removed = tx.execute("DELETE FROM profiles WHERE id = ?1", [id])?;
+        if removed == 0 {
+            return Err(ProfileError::NotFound(id));
+        }
+        tx.commit()?;
+
+        log::info!("Removed profile {}", id);
+        Ok(())
+    }
+
+    fn load(&self, id: i64) -> SqliteResult<Option<Profile>> {
+        let db = self.db.lock().unwrap();
+
+        let Some((name, allow_emergency_unlock)) = db
+            .query_row(
+                "SELECT name, allow_emergency_unlock FROM profiles WHERE id = ?1",
+                [id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut stmt =
+            db.prepare("SELECT domain FROM profile_domains WHERE profile_id = ?1 ORDER BY domain")?;
+        let domains = stmt
+            .query_map([id], |row| row.get(0))?
+            .collect::<SqliteResult<Vec<String>>>()?;
+
+        let mut stmt = db.prepare(
+            "SELECT id, kind, pattern, action FROM profile_process_rules
+             WHERE profile_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        let mut process_rules = Vec::new();
+        for row in rows {
+            let (rule_id, kind, pattern, action): (i64, String, String, String) = row?;
+            match (RuleKind::parse(&kind), ProcessAction::parse(&action)) {
+                (Some(kind), Some(action)) => process_rules.push(ProcessRule {
+                    id: rule_id,
+                    kind,
+                    pattern,
+                    action,
+                    source: RuleSource::Rule,
+                }),
+                _ => log::warn!(
+                    "Ignoring rule {} of profile {} with unknown kind {} or action {}",
+                    rule_id,
+                    id,
+                    kind,
+                    action
+                ),
+            }
+        }
+
+        Ok(Some(Profile {
+            id,
+            name,
+            domains,
+            process_rules,
+            options: ProfileOptions {
+                allow_emergency_unlock,
+            },
+        }))
+    }
+}
+
+fn insert_rules(tx: &Connection, id: i64, input: &ProfileInput) -> SqliteResult<()> {
+    for domain in &input.domains {
+        tx.execute(
+            "INSERT OR IGNORE INTO profile_domains (profile_id, domain) VALUES (?1, ?2)",
+            params![id, domain],
+        )?;
+    }
+    for rule in &input.process_rules {
+        tx.execute(
+            "INSERT INTO profile_process_rules (profile_id, kind, pattern, action)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(profile_id, kind, pattern) DO UPDATE SET action = excluded.action",
+            params![id, rule.kind.as_str(), rule.pattern, rule.action.as_str()],
+        )?;
+    }
+    Ok(())
+}
+
+fn delete_rules(tx: &Connection, id: i64) -> SqliteResult<()> {
+    tx.execute("DELETE FROM profile_domains WHERE profile_id = ?1", [id])?;
+    tx.execute(
+        "DELETE FROM profile_process_rules WHERE profile_id = ?1",
+        [id],
+    )?;
+    Ok(())
+}
+
+/// The input with its name trimmed and its rules normalized as stored.
+fn validate(input: &ProfileInput) -> Result<ProfileInput, ProfileError> {
+    let name = input.name.trim();
+    if name.is_empty() {
+        return Err(ProfileError::EmptyName);
+    }
+
+    let domains = input
+        .domains
+        .iter()
+        .map(|d| normalize_domain(d).map_err(|_| ProfileError::InvalidDomain(d.clone())))
+        .collect::<Result<_, _>>()?;
+    let process_rules = input
+        .process_rules
+        .iter()
+        .map(|rule| {
+            let pattern = normalize_rule(rule.kind, &rule.pattern)
+                .map_err(|e| ProfileError::InvalidRule(e.to_string()))?;
+            Ok(ProfileRuleInput {
+                pattern,
+                ..rule.clone()
+            })
+        })
+        .collect::<Result<_, ProfileError>>()?;
+
+    Ok(ProfileInput {
+        name: name.to_string(),
+        domains,
+        process_rules,
+        options: input.options.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> ProfileStore {
+        ProfileStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn rule(pattern: &str, action: ProcessAction) -> ProfileRuleInput {
+        ProfileRuleInput {
+            kind: RuleKind::Name,
+            pattern: pattern.to_string(),
+            action,
+        }
+    }
+
+    fn work() -> ProfileInput {
+        ProfileInput {
+            name: " Work ".to_string(),
+            domains: vec!["YouTube.com".to_string(), "*.instagram.com".to_string()],
+            process_rules: vec![rule("Discord.exe", ProcessAction::Suspend)],
+            options: ProfileOptions::default(),
+        }
+    }
+
+    fn exam() -> ProfileInput {
+        let mut input = work();
+        input.name = "Exam".to_string();
+        input.domains.push("chatgpt.com".to_string());
+        input.process_rules = vec![rule("discord.exe", ProcessAction::Kill)];
+        input.options.allow_emergency_unlock = false;
+        input
+    }
+
+    #[test]
+    fn profiles_round_trip_normalized() {
+        let store = store();
+        let profile = store.add_profile(&work()).unwrap();
+
+        assert_eq!(profile.name, "Work");
+        assert_eq!(profile.domains, ["*.instagram.com", "youtube.com"]);
+        assert_eq!(profile.process_rules.len(), 1);
+        assert_eq!(profile.process_rules[0].action, ProcessAction::Suspend);
+        assert_eq!(store.profiles().unwrap().len(), 1);
+
+        let updated = store.update_profile(profile.id, &exam(), false).unwrap();
+        assert_eq!(updated.name, "Exam");
+        assert_eq!(updated.domains.len(), 3);
+        assert!(!updated.options.allow_emergency_unlock);
+
+        assert!(matches!(
+            store.add_profile(&ProfileInput {
+                domains: vec!["not a domain".to_string()],
+                ..work()
+            }),
+            Err(ProfileError::InvalidDomain(_))
+        ));
+        assert!(matches!(
+            store.add_profile(&ProfileInput {
+                process_rules: vec![ProfileRuleInput {
+                    kind: RuleKind::Sha256,
+                    pattern: "abc".to_string(),
+                    action: ProcessAction::Kill,
+                }],
+                ..work()
+            }),
+            Err(ProfileError::InvalidRule(_))
+        ));
+    }
+
+    #[test]
+    fn profile_in_use_cannot_be_changed() {
+        let store = store();
+        let profile = store.add_profile(&work()).unwrap();
+
+        assert!(matches!(
+            store.update_profile(profile.id, &exam(), true),
+            Err(ProfileError::InUse)
+        ));
+        assert!(matches!(
+            store.remove_profile(profile.id, true),
+            Err(ProfileError::InUse)
+        ));
+
+        store.remove_profile(profile.id, false).unwrap();
+        assert!(matches!(
+            store.profile(profile.id),
+            Err(ProfileError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn only_a_superset_is_stricter() {
+        let store = store();
+        let work = store.add_profile(&work()).unwrap();
+        let exam = store.add_profile(&exam()).unwrap();
+
+        assert!(exam.is_stricter_than(&work));
+        assert!(!work.is_stricter_than(&exam));
+        // Not strictly stricter than itself
+        assert!(!work.is_stricter_than(&work));
+
+        // Missing one of Work's domains rules it out, whatever else it adds
+        let mut partial = exam.clone();
+        partial.domains.retain(|d| d != "youtube.com");
+        assert!(!partial.is_stricter_than(&work));
+
+        let global = Profile::global(vec!["youtube.com".to_string()], Vec::new());
+        assert!(work.is_stricter_than(&global));
+    }
+}
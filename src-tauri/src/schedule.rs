@@ -0,0 +1,497 @@
+use crate::db::{self, DbError};
+use chrono::{
+    DateTime, Datelike, Duration, Local, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
+use rusqlite::{params, Connection, Result as SqliteResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ScheduleError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("A schedule needs at least one weekday")]
+    NoWeekdays,
+    #[error("A schedule window cannot start and end at the same time")]
+    EmptyWindow,
+    #[error("Unknown timezone: {0}")]
+    InvalidTimezone(String),
+    #[error("Schedule {0} not found")]
+    NotFound(i64),
+    #[error("Schedules cannot be changed while a scheduled window is active")]
+    Active,
+}
+
+/// A weekly blocking window, e.g. Mon–Fri 09:00–12:30. A window whose end
+/// is not after its start runs past midnight into the next day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: i64,
+    pub name: String,
+    /// Days the window starts on
+    pub weekdays: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// IANA name such as "Europe/Berlin"; `None` follows the system timezone
+    pub timezone: Option<String>,
+    pub enabled: bool,
+}
+
+/// A schedule as entered, before it has an id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleInput {
+    pub name: String,
+    pub weekdays: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// One occurrence of a schedule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleWindow {
+    pub schedule_id: i64,
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Schedule {
+    /// The occurrence running at `now`, if any.
+    pub fn active_window(&self, now: DateTime<Utc>) -> Option<ScheduleWindow> {
+        self.windows_around(now)
+            .into_iter()
+            .find(|w| w.start <= now && now < w.end)
+    }
+
+    /// The first occurrence starting after `now`.
+    pub fn next_window(&self, now: DateTime<Utc>) -> Option<ScheduleWindow> {
+        self.windows_around(now)
+            .into_iter()
+            .filter(|w| w.start > now)
+            .min_by_key(|w| w.start)
+    }
+
+    /// Occurrences starting from the day before `now` through the next
+    /// week, which covers overnight windows and the next start.
+    fn windows_around(&self, now: DateTime<Utc>) -> Vec<ScheduleWindow> {
+        let spans = match self.timezone.as_deref() {
+            Some(name) => match name.parse::<chrono_tz::Tz>() {
+                Ok(tz) => self.spans_in(&tz, now),
+                Err(_) => {
+                    // Checked on save, so only a hand-edited row gets here
+                    log::warn!("Schedule {} has unknown timezone {}", self.id, name);
+                    self.spans_in(&Local, now)
+                }
+            },
+            None => self.spans_in(&Local, now),
+        };
+
+        spans
+            .into_iter()
+            .map(|(start, end)| ScheduleWindow {
+                schedule_id: self.id,
+                name: self.name.clone(),
+                start,
+                end,
+            })
+            .collect()
+    }
+
+    fn spans_in<Z: TimeZone>(
+        &self,
+        tz: &Z,
+        now: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let today = now.with_timezone(tz).date_naive();
+
+        (-1..=7)
+            .map(|offset| today + Duration::days(offset))
+            .filter(|date| self.weekdays.contains(&date.weekday()))
+            .map(|date| {
+                let end_date = if self.end > self.start {
+                    date
+                } else {
+                    date + Duration::days(1)
+                };
+                (
+                    resolve(tz, date.and_time(self.start)),
+                    resolve(tz, end_date.and_time(self.end)),
+                )
+            })
+            .collect()
+    }
+}
+
+/// A local wall time as an instant. When clocks go back the repeated hour
+/// resolves to its first occurrence; when they go forward a skipped time
+/// resolves to where it would have been, an hour later on the new clock.
+pub(crate) fn resolve<Z: TimeZone>(tz: &Z, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+        LocalResult::None => tz
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_else(|| local.and_utc()),
+    }
+}
+
+/// Recurring blocking windows, kept in focuslock.db.
+pub struct ScheduleStore {
+    db: Mutex<Connection>,
+}
+
+impl ScheduleStore {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    pub fn schedules(&self) -> SqliteResult<Vec<Schedule>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT id, name, weekdays, start_time, end_time, timezone, enabled
+             FROM schedules ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let weekdays: u8 = row.get(2)?;
+            let start: String = row.get(3)?;
+            let end: String = row.get(4)?;
+            Ok(Schedule {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                weekdays: weekdays_from_mask(weekdays),
+                start: parse_time(&start),
+                end: parse_time(&end),
+                timezone: row.get(5)?,
+                enabled: row.get(6)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    /// The window of any enabled schedule running at `now`. When several
+    /// overlap, the one ending last.
+    pub fn active_window(&self, now: DateTime<Utc>) -> SqliteResult<Option<ScheduleWindow>> {
+        Ok(self
+            .schedules()?
+            .iter()
+            .filter(|s| s.enabled)
+            .filter_map(|s| s.active_window(now))
+            .max_by_key(|w| w.end))
+    }
+
+    /// The next window of any enabled schedule to start after `now`.
+    pub fn next_window(&self, now: DateTime<Utc>) -> SqliteResult<Option<ScheduleWindow>> {
+        Ok(self
+            .schedules()?
+            .iter()
+            .filter(|s| s.enabled)
+            .filter_map(|s| s.next_window(now))
+            .min_by_key(|w| w.start))
+    }
+
+    /// Adding is always allowed; it can only make blocking stricter.
+    pub fn add_schedule(&self, input: &ScheduleInput) -> Result<Schedule, ScheduleError> {
+        validate(input)?;
+        let db = self.db.lock().unwrap();
+
+        db.execute(
+            "INSERT INTO schedules (name, weekdays, start_time, end_time, timezone, enabled)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                input.name,
+                weekdays_to_mask(&input.weekdays),
+                format_time(input.start),
+                format_time(input.end),
+                input.timezone,
+                input.enabled
+            ],
+        )?;
+
+        let id = db.last_insert_rowid();
+        log::info!("Added schedule {} ({})", id, input.name);
+        Ok(schedule_from_input(id, input))
+    }
+
+    pub fn update_schedule(
+        &self,
+        id: i64,
+        input: &ScheduleInput,
+        now: DateTime<Utc>,
+    ) -> Result<Schedule, ScheduleError> {
+        validate(input)?;
+        self.ensure_inactive(now)?;
+        let db = self.db.lock().unwrap();
+
+        let updated = db.execute(
+            "UPDATE schedules
+             SET name = ?1, weekdays = ?2, start_time = ?3, end_time = ?4, timezone = ?5,
+                 enabled = ?6
+             WHERE id = ?7",
+            params![
+                input.name,
+                weekdays_to_mask(&input.weekdays),
+                format_time(input.start),
+                format_time(input.end),
+                input.timezone,
+                input.enabled,
+                id
+            ],
+        )?;
+        if updated == 0 {
+            return Err(ScheduleError::NotFound(id));
+        }
+
+        log::info!("Updated schedule {}", id);
+        Ok(schedule_from_input(id, input))
+    }
+
+    pub fn remove_schedule(&self, id: i64, now: DateTime<Utc>) -> Result<(), ScheduleError> {
+        self.ensure_inactive(now)?;
+        let db = self.db.lock().unwrap();
+
+        let removed = db.execute("DELETE FROM schedules WHERE id = ?1", [id])?;
+        if removed == 0 {
+            return Err(ScheduleError::NotFound(id));
+        }
+
+        log::info!("Removed schedule {}", id);
+        Ok(())
+    }
+
+    fn ensure_inactive(&self, now: DateTime<Utc>) -> Result<(), ScheduleError> {
+        match self.active_window(now)? {
+            Some(_) => Err(ScheduleError::Active),
+            None => Ok(()),
+        }
+    }
+}
+
+fn validate(input: &ScheduleInput) -> Result<(), ScheduleError> {
+    if input.weekdays.is_empty() {
+        return Err(ScheduleError::NoWeekdays);
+    }
+    if input.start == input.end {
+        return Err(ScheduleError::EmptyWindow);
+    }
+    if let Some(name) = &input.timezone {
+        if name.parse::<chrono_tz::Tz>().is_err() {
+            return Err(ScheduleError::InvalidTimezone(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn schedule_from_input(id: i64, input: &ScheduleInput) -> Schedule {
+    Schedule {
+        id,
+        name: input.name.clone(),
+        weekdays: weekdays_from_mask(weekdays_to_mask(&input.weekdays)),
+        start: input.start,
+        end: input.end,
+        timezone: input.timezone.clone(),
+        enabled: input.enabled,
+    }
+}
+
+/// Monday is bit 0, Sunday bit 6.
+fn weekdays_to_mask(weekdays: &[Weekday]) -> u8 {
+    weekdays
+        .iter()
+        .fold(0, |mask, day| mask | 1 << day.num_days_from_monday())
+}
+
+fn weekdays_from_mask(mask: u8) -> Vec<Weekday> {
+    (0..7)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .filter_map(|bit| Weekday::try_from(bit as u8).ok())
+        .collect()
+}
+
+fn format_time(time: NaiveTime) -> String {
+    time.format("%H:%M").to_string()
+}
+
+fn parse_time(value: &str) -> NaiveTime {
+    NaiveTime::parse_from_str(value, "%H:%M").unwrap_or(NaiveTime::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn schedule(weekdays: &[Weekday], start: &str, end: &str, timezone: &str) -> Schedule {
+        Schedule {
+            id: 1,
+            name: "Work".to_string(),
+            weekdays: weekdays.to_vec(),
+            start: time(start),
+            end: time(end),
+            timezone: Some(timezone.to_string()),
+            enabled: true,
+        }
+    }
+
+    const WEEKDAYS: &[Weekday] = &[
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+    ];
+
+    #[test]
+    fn window_follows_weekdays_and_timezone() {
+        let morning = schedule(WEEKDAYS, "09:00", "12:30", "Asia/Seoul");
+
+        // Friday 2024-03-01 10:00 in Seoul
+        let window = morning.active_window(utc("2024-03-01T01:00:00Z")).unwrap();
+        assert_eq!(window.start, utc("2024-03-01T00:00:00Z"));
+        assert_eq!(window.end, utc("2024-03-01T03:30:00Z"));
+
+        // 12:30 is already outside, and Saturday doesn't count
+        assert!(morning.active_window(utc("2024-03-01T03:30:00Z")).is_none());
+        assert!(morning.active_window(utc("2024-03-02T01:00:00Z")).is_none());
+
+        // After Friday's window the next one is Monday's
+        let next = morning.next_window(utc("2024-03-01T04:00:00Z")).unwrap();
+        assert_eq!(next.start, utc("2024-03-04T00:00:00Z"));
+    }
+
+    #[test]
+    fn overnight_window_runs_into_the_next_day() {
+        let night = schedule(&[Weekday::Fri], "22:00", "06:00", "UTC");
+
+        let window = night.active_window(utc("2024-03-02T03:00:00Z")).unwrap();
+        assert_eq!(window.start, utc("2024-03-01T22:00:00Z"));
+        assert_eq!(window.end, utc("2024-03-02T06:00:00Z"));
+        assert!(night.active_window(utc("2024-03-02T07:00:00Z")).is_none());
+    }
+
+    #[test]
+    fn windows_keep_local_time_across_dst() {
+        let morning = schedule(WEEKDAYS, "09:00", "12:30", "Europe/Berlin");
+
+        // CET (+01:00) on Friday, CEST (+02:00) on the Monday after
+        let friday = morning.active_window(utc("2024-03-29T08:30:00Z")).unwrap();
+        assert_eq!(friday.start, utc("2024-03-29T08:00:00Z"));
+        let monday = morning.active_window(utc("2024-04-01T07:30:00Z")).unwrap();
+        assert_eq!(monday.start, utc("2024-04-01T07:00:00Z"));
+    }
+
+    #[test]
+    fn skipped_and_repeated_hours_resolve() {
+        // Berlin skips 02:00-03:00 on 2024-03-31 and repeats it on 2024-10-27
+        let spring = schedule(&[Weekday::Sun], "02:30", "04:00", "Europe/Berlin");
+        let window = spring.active_window(utc("2024-03-31T01:30:00Z")).unwrap();
+        assert_eq!(window.start, utc("2024-03-31T01:30:00Z"));
+        assert_eq!(window.end, utc("2024-03-31T02:00:00Z"));
+
+        let autumn = schedule(&[Weekday::Sun], "02:30", "04:00", "Europe/Berlin");
+        let window = autumn.active_window(utc("2024-10-27T00:45:00Z")).unwrap();
+        assert_eq!(window.start, utc("2024-10-27T00:30:00Z"));
+        assert_eq!(window.end, utc("2024-10-27T03:00:00Z"));
+    }
+
+    #[test]
+    fn schedules_are_locked_while_a_window_runs() {
+        let store = ScheduleStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let input = ScheduleInput {
+            name: "Work".to_string(),
+            weekdays: WEEKDAYS.to_vec(),
+            start: time("09:00"),
+            end: time("18:00"),
+            timezone: Some("UTC".to_string()),
+            enabled: true,
+        };
+        let schedule = store.add_schedule(&input).unwrap();
+        assert_eq!(store.schedules().unwrap()[0].weekdays, WEEKDAYS);
+
+        let during = utc("2024-03-01T10:00:00Z");
+        assert!(store.active_window(during).unwrap().is_some());
+        assert!(matches!(
+            store.remove_schedule(schedule.id, during),
+            Err(ScheduleError::Active)
+        ));
+        let disabled = ScheduleInput {
+            enabled: false,
+            ..input.clone()
+        };
+        assert!(matches!(
+            store.update_schedule(schedule.id, &disabled, during),
+            Err(ScheduleError::Active)
+        ));
+
+        let after = utc("2024-03-01T19:00:00Z");
+        store
+            .update_schedule(schedule.id, &disabled, after)
+            .unwrap();
+        assert!(store.active_window(during).unwrap().is_none());
+        store.remove_schedule(schedule.id, after).unwrap();
+        assert!(store.schedules().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let store = ScheduleStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let input = ScheduleInput {
+            name: String::new(),
+            weekdays: vec![Weekday::Mon],
+            start: time("09:00"),
+            end: time("10:00"),
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            enabled: true,
+        };
+
+        assert!(matches!(
+            store.add_schedule(&input),
+            Err(ScheduleError::InvalidTimezone(_))
+        ));
+        assert!(matches!(
+            store.add_schedule(&ScheduleInput {
+                weekdays: Vec::new(),
+                ..input.clone()
+            }),
+            Err(ScheduleError::NoWeekdays)
+        ));
+        assert!(matches!(
+            store.add_schedule(&ScheduleInput {
+                end: time("09:00"),
+                timezone: None,
+                ..input
+            }),
+            Err(ScheduleError::EmptyWindow)
+        ));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::db::{self, DbError};
+use rusqlite::{Connection, OptionalExtension, Result as SqliteResult};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Small key/value store for user preferences, kept as JSON in focuslock.db.
+pub struct Settings {
+    db: Mutex<Connection>,
+}
+
+impl Settings {
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        let conn = db::open(&app_data_dir)?;
+
+        Ok(Self {
+            db: Mutex::new(conn),
+        })
+    }
+
+    /// Returns `None` when the key is unset or its stored value no longer parses.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> SqliteResult<Option<T>> {
+        let db = self.db.lock().unwrap();
+
+        let value: Option<String> = db
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+
+        Ok(value.and_then(|v| match serde_json::from_str(&v) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                log::warn!("Ignoring unreadable setting {}: {}", key, e);
+                None
+            }
+        }))
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: &T) -> SqliteResult<()> {
+        let value = serde_json::to_string(value)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let db = self.db.lock().unwrap();
+
+        db.execute(
+            "INSERT INTO settings (key, value, updated_at) VALUES (?1, ?2, CURRENT_TIMESTAMP)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            [key, &value],
+        )?;
+        Ok(())
+    }
+}
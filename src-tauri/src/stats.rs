@@ -0,0 +1,337 @@
+use crate::timelock::{EndReason, Session};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DayFocus {
+    pub date: NaiveDate,
+    pub focused_seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeekFocus {
+    /// Monday of the week
+    pub week_start: NaiveDate,
+    pub focused_seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub total_sessions: usize,
+    /// Sessions that ran until their planned end
+    pub completed_sessions: usize,
+    pub emergency_unlocks: usize,
+    pub total_focused_seconds: i64,
+    /// Most consecutive local days with any locked time
+    pub longest_streak_days: u32,
+    /// Run of days ending today, or yesterday if today has no lock yet
+    pub current_streak_days: u32,
+}
+
+/// Locked time per local day for the `days` days ending today, oldest first.
+/// Days without a lock are included with zero.
+pub fn daily_focus(sessions: &[Session], days: u32, now: DateTime<Utc>) -> Vec<DayFocus> {
+    daily_focus_in(sessions, days, now, &Local)
+}
+
+fn daily_focus_in<Tz: TimeZone>(
+    sessions: &[Session],
+    days: u32,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Vec<DayFocus> {
+    let by_day = focus_by_day(sessions, now, tz);
+    let today = now.with_timezone(tz).date_naive();
+
+    (0..days as i64)
+        .rev()
+        .map(|offset| {
+            let date = today - Duration::days(offset);
+            DayFocus {
+                date,
+                focused_seconds: by_day.get(&date).copied().unwrap_or(0),
+            }
+        })
+        .collect()
+}
+
+/// Locked time per Monday-to-Sunday week for the `weeks` weeks ending with
+/// the current one, oldest first.
+pub fn weekly_focus(sessions: &[Session], weeks: u32, now: DateTime<Utc>) -> Vec<WeekFocus> {
+    weekly_focus_in(sessions, weeks, now, &Local)
+}
+
+fn weekly_focus_in<Tz: TimeZone>(
+    sessions: &[Session],
+    weeks: u32,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> Vec<WeekFocus> {
+    let by_day = focus_by_day(sessions, now, tz);
+    let today = now.with_timezone(tz).date_naive();
+    let this_week = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+
+    (0..weeks as i64)
+        .rev()
+        .map(|offset| {
+            let week_start = this_week - Duration::weeks(offset);
+            let week_end = week_start + Duration::days(7);
+            WeekFocus {
+                week_start,
+                focused_seconds: by_day.range(week_start..week_end).map(|(_, s)| s).sum(),
+            }
+        })
+        .collect()
+}
+
+pub fn summarize(sessions: &[Session], now: DateTime<Utc>) -> SessionStats {
+    summarize_in(sessions, now, &Local)
+}
+
+fn summarize_in<Tz: TimeZone>(sessions: &[Session], now: DateTime<Utc>, tz: &Tz) -> SessionStats {
+    let by_day = focus_by_day(sessions, now, tz);
+    let today = now.with_timezone(tz).date_naive();
+
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for (&date, &seconds) in &by_day {
+        if seconds <= 0 {
+            continue;
+        }
+        run = match previous {
+            Some(prev) if date - prev == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(date);
+    }
+    let current = match previous {
+        Some(last) if last == today || last == today - Duration::days(1) => run,
+        _ => 0,
+    };
+
+    SessionStats {
+        total_sessions: sessions.len(),
+        completed_sessions: count_reason(sessions, EndReason::Expired),
+        emergency_unlocks: count_reason(sessions, EndReason::EmergencyUnlock),
+        total_focused_seconds: by_day.values().sum(),
+        longest_streak_days: longest,
+        current_streak_days: current,
+    }
+}
+
+fn count_reason(sessions: &[Session], reason: EndReason) -> usize {
+    sessions
+        .iter()
+        .filter(|s| s.end_reason == Some(reason))
+        .count()
+}
+
+/// Splits every session at local midnights and sums the pieces per day.
+fn focus_by_day<Tz: TimeZone>(
+    sessions: &[Session],
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> BTreeMap<NaiveDate, i64> {
+    let mut by_day = BTreeMap::new();
+
+    for session in sessions {
+        // A running session counts up to now
+        let end = session
+            .ended_at
+            .unwrap_or_else(|| session.planned_end.min(now));
+        let mut cursor = session.started_at;
+
+        while cursor < end {
+            let date = cursor.with_timezone(tz).date_naive();
+            let segment_end = next_local_midnight(date, tz).min(end);
+            if segment_end <= cursor {
+                break;
+            }
+            *by_day.entry(date).or_insert(0) += (segment_end - cursor).num_seconds();
+            cursor = segment_end;
+        }
+    }
+
+    by_day
+}
+
+fn next_local_midnight<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> DateTime<Utc> {
+    let next = date + Duration::days(1);
+    let midnight = next.and_hms_opt(0, 0, 0).unwrap();
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(time) => time.with_timezone(&Utc),
+        // Midnight skipped by a DST change; the day ends an hour later
+        None => tz
+            .from_local_datetime(&(midnight + Duration::hours(1)))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_else(|| midnight.and_utc()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    fn session(start: &str, end: &str, end_reason: EndReason) -> Session {
+        Session {
+            id: 0,
+            started_at: at(start),
+            planned_end: at(end),
+            ended_at: Some(at(end)),
+            end_reason: Some(end_reason),
+        }
+    }
+
+    /// UTC+2, without DST
+    fn tz() -> FixedOffset {
+        FixedOffset::east_opt(2 * 3600).unwrap()
+    }
+
+    #[test]
+    fn sessions_are_split_at_local_midnight() {
+        // 23:00 to 01:30 local time
+        let sessions = [session(
+            "2024-03-04T21:00:00Z",
+            "2024-03-04T23:30:00Z",
+            EndReason::Expired,
+        )];
+
+        let days = daily_focus_in(&sessions, 3, at("2024-03-05T12:00:00Z"), &tz());
+        let days: Vec<_> = days.iter().map(|d| (d.date, d.focused_seconds)).collect();
+        assert_eq!(
+            days,
+            [
+                (date("2024-03-03"), 0),
+                (date("2024-03-04"), 3600),
+                (date("2024-03-05"), 5400),
+            ]
+        );
+    }
+
+    #[test]
+    fn running_sessions_count_up_to_now() {
+        let mut running = session(
+            "2024-03-05T08:00:00Z",
+            "2024-03-05T12:00:00Z",
+            EndReason::Expired,
+        );
+        running.ended_at = None;
+        running.end_reason = None;
+
+        let days = daily_focus_in(&[running], 1, at("2024-03-05T09:15:00Z"), &tz());
+        assert_eq!(days[0].focused_seconds, 4500);
+    }
+
+    #[test]
+    fn weeks_run_monday_to_sunday() {
+        let sessions = [
+            // Sunday 23:00 to Monday 01:00 local time
+            session(
+                "2024-03-03T21:00:00Z",
+                "2024-03-03T23:00:00Z",
+                EndReason::Expired,
+            ),
+            session(
+                "2024-03-06T08:00:00Z",
+                "2024-03-06T09:00:00Z",
+                EndReason::Expired,
+            ),
+        ];
+
+        let weeks = weekly_focus_in(&sessions, 2, at("2024-03-10T12:00:00Z"), &tz());
+        let weeks: Vec<_> = weeks
+            .iter()
+            .map(|w| (w.week_start, w.focused_seconds))
+            .collect();
+        assert_eq!(
+            weeks,
+            [
+                (date("2024-02-26"), 3600),
+                (date("2024-03-04"), 3600 + 3600)
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_os = "android"))]
+    fn dst_days_are_counted_by_their_real_length() {
+        let berlin = chrono_tz::Europe::Berlin;
+        // Clocks went forward on Sunday 2024-03-31; that day has 23 hours
+        let sessions = [session(
+            "2024-03-24T23:00:00Z",
+            "2024-03-31T22:00:00Z",
+            EndReason::Expired,
+        )];
+
+        let days = daily_focus_in(&sessions, 7, at("2024-03-31T12:00:00Z"), &berlin);
+        assert_eq!(days[0].date, date("2024-03-25"));
+        assert!(days[..6].iter().all(|d| d.focused_seconds == 24 * 3600));
+        assert_eq!(days[6].focused_seconds, 23 * 3600);
+
+        let weeks = weekly_focus_in(&sessions, 1, at("2024-03-31T12:00:00Z"), &berlin);
+        assert_eq!(weeks[0].week_start, date("2024-03-25"));
+        assert_eq!(weeks[0].focused_seconds, (7 * 24 - 1) * 3600);
+    }
+
+    #[test]
+    fn streaks_need_consecutive_local_days() {
+        let sessions = [
+            session(
+                "2024-03-01T08:00:00Z",
+                "2024-03-01T09:00:00Z",
+                EndReason::Expired,
+            ),
+            session(
+                "2024-03-02T08:00:00Z",
+                "2024-03-02T09:00:00Z",
+                EndReason::Expired,
+            ),
+            session(
+                "2024-03-03T08:00:00Z",
+                "2024-03-03T09:00:00Z",
+                EndReason::EmergencyUnlock,
+            ),
+            // A gap on the 4th
+            session(
+                "2024-03-05T08:00:00Z",
+                "2024-03-05T09:00:00Z",
+                EndReason::Expired,
+            ),
+            // 23:30 to 00:30 local time covers the 5th and the 6th
+            session(
+                "2024-03-05T21:30:00Z",
+                "2024-03-05T22:30:00Z",
+                EndReason::Expired,
+            ),
+        ];
+
+        // The 7th has no lock yet, so the run ending on the 6th still counts
+        let stats = summarize_in(&sessions, at("2024-03-07T12:00:00Z"), &tz());
+        assert_eq!(stats.total_sessions, 5);
+        assert_eq!(stats.completed_sessions, 4);
+        assert_eq!(stats.emergency_unlocks, 1);
+        assert_eq!(stats.total_focused_seconds, 5 * 3600);
+        assert_eq!(stats.longest_streak_days, 3);
+        assert_eq!(stats.current_streak_days, 2);
+
+        // A day without a lock breaks it
+        let stats = summarize_in(&sessions, at("2024-03-08T12:00:00Z"), &tz());
+        assert_eq!(stats.longest_streak_days, 3);
+        assert_eq!(stats.current_streak_days, 0);
+    }
+}
@@ -1,48 +1,260 @@
+use crate::clock::{self, Clock, SystemClock};
+use crate::db::{self, DbError};
 use chrono::{DateTime, Duration, Utc};
-use rusqlite::{Connection, Result as SqliteResult};
-use serde::{Deserialize, Serialize};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// How far the wall clock may move away from uptime before it counts as the
+/// clock being changed rather than drift.
+const CLOCK_JUMP_TOLERANCE_SECS: i64 = 120;
+
+/// Elapsed lock time is written back at least this often; in between it
+/// is recomputed from the last write.
+const CHECKPOINT_INTERVAL_SECS: i64 = 30;
+
+/// Longest lock that can be started or extended to, a year.
+pub const MAX_LOCK_MINUTES: i64 = 366 * 24 * 60;
+
+#[derive(Error, Debug)]
+pub enum TimeLockError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Lock duration must be 1 to {MAX_LOCK_MINUTES} minutes, got {0}")]
+    InvalidDuration(i64),
+    #[error("No lock is running")]
+    NotLocked,
+    #[error(
+        "The running lock has {remaining_seconds}s left; a {requested_seconds}s lock would end it early"
+    )]
+    WouldShorten {
+        remaining_seconds: i64,
+        requested_seconds: i64,
+    },
+}
+
+impl TimeLockError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TimeLockError::Database(_) => "database",
+            TimeLockError::InvalidDuration(_) => "invalid_duration",
+            TimeLockError::NotLocked => "not_locked",
+            TimeLockError::WouldShorten { .. } => "would_shorten",
+        }
+    }
+}
+
+/// Sent to the frontend as `{ kind, message }` plus the fields of the
+/// variant, so it can tell a refused shorter lock apart from a failure.
+impl Serialize for TimeLockError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            TimeLockError::InvalidDuration(minutes) => {
+                map.serialize_entry("minutes", minutes)?;
+            }
+            TimeLockError::WouldShorten {
+                remaining_seconds,
+                requested_seconds,
+            } => {
+                map.serialize_entry("remaining_seconds", remaining_seconds)?;
+                map.serialize_entry("requested_seconds", requested_seconds)?;
+            }
+            TimeLockError::Database(_) | TimeLockError::NotLocked => {}
+        }
+        map.end()
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LockState {
     pub is_locked: bool,
     pub unlock_time: Option<DateTime<Utc>>,
     pub remaining_seconds: i64,
+    /// Profile the lock blocks with; `None` for the global blocklist and
+    /// process rules
+    #[serde(default)]
+    pub profile_id: Option<i64>,
+}
+
+/// Why a lock session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// Ran until its planned end
+    Expired,
+    EmergencyUnlock,
+    /// A new lock was started over it
+    Replaced,
+    /// The lock disappeared without being ended, e.g. the app died while
+    /// its state was being written
+    Crash,
+}
+
+impl EndReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EndReason::Expired => "expired",
+            EndReason::EmergencyUnlock => "emergency_unlock",
+            EndReason::Replaced => "replaced",
+            EndReason::Crash => "crash",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "expired" => Some(EndReason::Expired),
+            "emergency_unlock" => Some(EndReason::EmergencyUnlock),
+            "replaced" => Some(EndReason::Replaced),
+            "crash" => Some(EndReason::Crash),
+            _ => None,
+        }
+    }
+}
+
+/// One lock from start to end. `ended_at` and `end_reason` are `None`
+/// while it is running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub planned_end: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub end_reason: Option<EndReason>,
+}
+
+impl LockState {
+    fn unlocked() -> Self {
+        Self {
+            is_locked: false,
+            unlock_time: None,
+            remaining_seconds: 0,
+            profile_id: None,
+        }
+    }
+}
+
+/// A running lock as stored in `lock_state`.
+struct StoredLock {
+    duration_seconds: Option<i64>,
+    elapsed_seconds: i64,
+    last_observed: Option<String>,
+    last_uptime: Option<i64>,
+    profile_id: Option<i64>,
 }
 
+/// Counts a lock down by measured elapsed time. Each check credits the
+/// uptime that passed since the previous one, so moving the system clock
+/// neither ends nor stretches a lock.
 pub struct TimeLock {
     db: Mutex<Connection>,
+    clock: Arc<dyn Clock>,
+    /// A lock ran out since `take_expired` was last called
+    expired: AtomicBool,
 }
 
 impl TimeLock {
-    pub fn new(app_data_dir: PathBuf) -> SqliteResult<Self> {
-        std::fs::create_dir_all(&app_data_dir).ok();
-        let db_path = app_data_dir.join("focuslock.db");
-        let conn = Connection::open(db_path)?;
+    pub fn new(app_data_dir: PathBuf) -> Result<Self, DbError> {
+        Self::with_clock(app_data_dir, Arc::new(SystemClock))
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS lock_state (
-                id INTEGER PRIMARY KEY CHECK (id = 1),
-                unlock_time TEXT,
-                created_at TEXT DEFAULT CURRENT_TIMESTAMP
-            )",
+    pub fn with_clock(app_data_dir: PathBuf, clock: Arc<dyn Clock>) -> Result<Self, DbError> {
+        Self::from_connection(db::open(&app_data_dir)?, clock)
+    }
+
+    /// Runs over any connection, e.g. an in-memory database in tests.
+    /// The schema is migrated first.
+    pub fn from_connection(mut conn: Connection, clock: Arc<dyn Clock>) -> Result<Self, DbError> {
+        db::migrate(&mut conn)?;
+
+        // Sessions left open without a lock behind them ended uncleanly
+        let has_lock: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM lock_state WHERE id = 1)",
+            [],
+            |row| row.get(0),
+        )?;
+        let has_open_session: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sessions WHERE ended_at IS NULL)",
             [],
+            |row| row.get(0),
         )?;
+        if has_lock && !has_open_session {
+            // A lock started before sessions were recorded
+            conn.execute(
+                "INSERT INTO sessions (started_at, planned_end)
+                 SELECT strftime('%Y-%m-%dT%H:%M:%S+00:00', created_at), unlock_time
+                 FROM lock_state WHERE id = 1",
+                [],
+            )?;
+        } else if !has_lock {
+            let closed = conn.execute(
+                "UPDATE sessions SET ended_at = MIN(planned_end, ?1), end_reason = ?2
+                 WHERE ended_at IS NULL",
+                params![clock.now().to_rfc3339(), EndReason::Crash.as_str()],
+            )?;
+            if closed > 0 {
+                log::warn!("Closed {} lock session(s) that never ended", closed);
+            }
+        }
 
         Ok(Self {
             db: Mutex::new(conn),
+            clock,
+            expired: AtomicBool::new(false),
         })
     }
 
-    pub fn start_lock(&self, duration_minutes: i64) -> SqliteResult<LockState> {
-        let unlock_time = Utc::now() + Duration::minutes(duration_minutes);
+    /// Starts a lock with `profile_id`, replacing a running one only if the
+    /// new lock ends later; shortening takes an emergency unlock. Whether
+    /// the profile may replace the running one is up to the caller.
+    pub fn start_lock(
+        &self,
+        duration_minutes: i64,
+        profile_id: Option<i64>,
+    ) -> Result<LockState, TimeLockError> {
+        let duration = lock_duration(duration_minutes)?;
+        let duration_seconds = duration.num_seconds();
         let db = self.db.lock().unwrap();
 
-        db.execute(
-            "INSERT OR REPLACE INTO lock_state (id, unlock_time) VALUES (1, ?1)",
-            [unlock_time.to_rfc3339()],
+        let current = self.current_state(&db)?;
+        if current.is_locked && current.remaining_seconds > duration_seconds {
+            return Err(TimeLockError::WouldShorten {
+                remaining_seconds: current.remaining_seconds,
+                requested_seconds: duration_seconds,
+            });
+        }
+
+        let now = self.clock.now();
+        let unlock_time = now
+            .checked_add_signed(duration)
+            .ok_or(TimeLockError::InvalidDuration(duration_minutes))?;
+
+        let tx = db.unchecked_transaction()?;
+        end_open_sessions(&tx, now, EndReason::Replaced)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO lock_state
+                (id, unlock_time, duration_seconds, elapsed_seconds, last_observed, last_uptime,
+                 profile_id)
+             VALUES (1, ?1, ?2, 0, ?3, ?4, ?5)",
+            params![
+                unlock_time.to_rfc3339(),
+                duration_seconds,
+                now.to_rfc3339(),
+                self.clock.uptime() as i64,
+                profile_id
+            ],
+        )?;
+        tx.execute(
+            "INSERT INTO sessions (started_at, planned_end) VALUES (?1, ?2)",
+            params![now.to_rfc3339(), unlock_time.to_rfc3339()],
         )?;
+        tx.commit()?;
 
         log::info!("Lock started until: {}", unlock_time);
 
@@ -50,65 +262,838 @@ impl TimeLock {
             is_locked: true,
             unlock_time: Some(unlock_time),
             remaining_seconds: duration_minutes * 60,
+            profile_id,
         })
     }
 
-    pub fn get_state(&self) -> SqliteResult<LockState> {
+    /// Adds time to the running lock.
+    pub fn extend_lock(&self, minutes: i64) -> Result<LockState, TimeLockError> {
+        let extension = lock_duration(minutes)?;
+        let db = self.db.lock().unwrap();
+
+        let current = self.current_state(&db)?;
+        let Some(unlock_time) = current.unlock_time.filter(|_| current.is_locked) else {
+            return Err(TimeLockError::NotLocked);
+        };
+        // The extended lock can't run longer than a new one could
+        let remaining_seconds = current
+            .remaining_seconds
+            .checked_add(extension.num_seconds())
+            .filter(|&seconds| seconds <= MAX_LOCK_MINUTES * 60)
+            .ok_or(TimeLockError::InvalidDuration(minutes))?;
+        let unlock_time = unlock_time
+            .checked_add_signed(extension)
+            .ok_or(TimeLockError::InvalidDuration(minutes))?;
+
+        let tx = db.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE lock_state SET duration_seconds = duration_seconds + ?1, unlock_time = ?2
+             WHERE id = 1",
+            params![extension.num_seconds(), unlock_time.to_rfc3339()],
+        )?;
+        tx.execute(
+            "UPDATE sessions SET planned_end = ?1 WHERE ended_at IS NULL",
+            [unlock_time.to_rfc3339()],
+        )?;
+        tx.commit()?;
+
+        log::info!(
+            "Lock extended by {} minutes until: {}",
+            minutes,
+            unlock_time
+        );
+
+        Ok(LockState {
+            is_locked: true,
+            unlock_time: Some(unlock_time),
+            remaining_seconds,
+            profile_id: current.profile_id,
+        })
+    }
+
+    /// The current time for schedules, planned locks and budgets. Within a
+    /// boot it follows the wall clock, re-anchoring on uptime as it goes,
+    /// except for a forward jump while a lock runs: that time is measured
+    /// by uptime from the anchor instead, so setting the clock forward
+    /// doesn't end the lock's windows early. Drift, NTP corrections and the
+    /// clock going back are followed, and after a reboot the wall clock is
+    /// taken as it is.
+    pub fn now(&self) -> SqliteResult<DateTime<Utc>> {
         let db = self.db.lock().unwrap();
+        let wall = self.clock.now();
+        let uptime = self.clock.uptime() as i64;
+
+        let anchor = db
+            .query_row(
+                "SELECT observed, uptime, checked, checked_uptime FROM clock_anchor WHERE id = 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i64>(3)?,
+                    ))
+                },
+            )
+            .optional()?
+            .and_then(|(observed, anchor_uptime, checked, checked_uptime)| {
+                Some((
+                    parse_time(&observed)?,
+                    anchor_uptime,
+                    parse_time(&checked)?,
+                    checked_uptime,
+                ))
+            });
 
-        let mut stmt = db.prepare("SELECT unlock_time FROM lock_state WHERE id = 1")?;
-        let result: Option<String> = stmt
-            .query_row([], |row| row.get(0))
-            .ok();
-
-        match result {
-            Some(unlock_time_str) => {
-                if let Ok(unlock_time) = DateTime::parse_from_rfc3339(&unlock_time_str) {
-                    let unlock_time = unlock_time.with_timezone(&Utc);
-                    let now = Utc::now();
-
-                    if now < unlock_time {
-                        let remaining = (unlock_time - now).num_seconds();
-                        Ok(LockState {
-                            is_locked: true,
-                            unlock_time: Some(unlock_time),
-                            remaining_seconds: remaining,
-                        })
-                    } else {
-                        // Lock has expired
-                        drop(stmt);
-                        drop(db);
-                        self.clear_lock()?;
-                        Ok(LockState {
-                            is_locked: false,
-                            unlock_time: None,
-                            remaining_seconds: 0,
-                        })
+        match anchor {
+            Some((observed, anchor_uptime, checked, checked_uptime))
+                if uptime >= checked_uptime =>
+            {
+                let expected = observed + Duration::seconds(uptime - anchor_uptime);
+                let jump = (wall - expected).num_seconds();
+                let checkpoint_due = uptime - checked_uptime >= CHECKPOINT_INTERVAL_SECS;
+
+                let has_lock: bool = db.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM lock_state WHERE id = 1)",
+                    [],
+                    |row| row.get(0),
+                )?;
+                if jump > CLOCK_JUMP_TOLERANCE_SECS && has_lock {
+                    let now = expected.max(checked);
+                    if checkpoint_due {
+                        db.execute(
+                            "UPDATE clock_anchor SET checked = ?1, checked_uptime = ?2 WHERE id = 1",
+                            params![now.to_rfc3339(), uptime],
+                        )?;
                     }
-                } else {
-                    Ok(LockState {
-                        is_locked: false,
-                        unlock_time: None,
-                        remaining_seconds: 0,
+                    return Ok(now);
+                }
+
+                if jump.abs() > CLOCK_JUMP_TOLERANCE_SECS || checkpoint_due {
+                    db.execute(
+                        "UPDATE clock_anchor SET observed = ?1, uptime = ?2, checked = ?1,
+                            checked_uptime = ?2 WHERE id = 1",
+                        params![wall.to_rfc3339(), uptime],
+                    )?;
+                }
+                Ok(wall)
+            }
+            // First use, or uptime started over
+            _ => {
+                db.execute(
+                    "INSERT OR REPLACE INTO clock_anchor (id, observed, uptime, checked, checked_uptime)
+                     VALUES (1, ?1, ?2, ?1, ?2)",
+                    params![wall.to_rfc3339(), uptime],
+                )?;
+                Ok(wall)
+            }
+        }
+    }
+
+    /// Switches the running lock to another profile. Whether the switch is
+    /// allowed is up to the caller.
+    pub fn set_profile(&self, profile_id: Option<i64>) -> Result<LockState, TimeLockError> {
+        let db = self.db.lock().unwrap();
+
+        let current = self.current_state(&db)?;
+        if !current.is_locked {
+            return Err(TimeLockError::NotLocked);
+        }
+        db.execute(
+            "UPDATE lock_state SET profile_id = ?1 WHERE id = 1",
+            [profile_id],
+        )?;
+
+        log::info!("Lock switched to profile {:?}", profile_id);
+        Ok(LockState {
+            profile_id,
+            ..current
+        })
+    }
+
+    pub fn get_state(&self) -> SqliteResult<LockState> {
+        let db = self.db.lock().unwrap();
+        self.current_state(&db)
+    }
+
+    /// Credits the time passed since the last check and ends the lock if
+    /// it ran out. Takes the connection so callers can act on the result
+    /// without another thread getting in between.
+    fn current_state(&self, db: &Connection) -> SqliteResult<LockState> {
+        let stored = db
+            .query_row(
+                "SELECT duration_seconds, elapsed_seconds, last_observed, last_uptime, profile_id
+                 FROM lock_state WHERE id = 1",
+                [],
+                |row| {
+                    Ok(StoredLock {
+                        duration_seconds: row.get(0)?,
+                        elapsed_seconds: row.get(1)?,
+                        last_observed: row.get(2)?,
+                        last_uptime: row.get(3)?,
+                        profile_id: row.get(4)?,
                     })
+                },
+            )
+            .optional()?;
+        let Some(stored) = stored else {
+            return Ok(LockState::unlocked());
+        };
+        let Some(duration) = stored.duration_seconds else {
+            // Only a lock whose unlock_time couldn't be read gets here
+            return Ok(LockState::unlocked());
+        };
+
+        let now = self.clock.now();
+        let uptime = self.clock.uptime() as i64;
+        let last = stored
+            .last_observed
+            .as_deref()
+            .and_then(parse_time)
+            .zip(stored.last_uptime);
+
+        let (credit, checkpoint) = match last {
+            Some((last_observed, last_uptime)) if uptime >= last_uptime => {
+                let credit = uptime - last_uptime;
+                let jump = (now - last_observed).num_seconds() - credit;
+                if jump > CLOCK_JUMP_TOLERANCE_SECS {
+                    log::warn!(
+                        "System clock moved {}s forward during the lock, ignoring it",
+                        jump
+                    );
                 }
+                (
+                    credit,
+                    credit >= CHECKPOINT_INTERVAL_SECS || jump > CLOCK_JUMP_TOLERANCE_SECS,
+                )
             }
-            None => Ok(LockState {
-                is_locked: false,
-                unlock_time: None,
-                remaining_seconds: 0,
-            }),
+            // Uptime started over, so the machine rebooted; the wall clock
+            // is all there is to tell how long it was off
+            Some((last_observed, _)) => (
+                clock::elapsed_across_reboot(last_observed, now, uptime),
+                true,
+            ),
+            None => (0, true),
+        };
+        let elapsed = stored.elapsed_seconds + credit;
+        let remaining = duration - elapsed;
+
+        if remaining <= 0 {
+            // Ended when the elapsed time ran out, even if the app wasn't
+            // running then
+            end_lock(db, now + Duration::seconds(remaining), EndReason::Expired)?;
+            self.expired.store(true, Ordering::Relaxed);
+            return Ok(LockState::unlocked());
+        }
+
+        if checkpoint {
+            // The stored observed time only moves forward, so setting the
+            // clock back and forth can't be used to gain credit
+            let high_water = last.map_or(now, |(last_observed, _)| last_observed.max(now));
+            db.execute(
+                "UPDATE lock_state SET elapsed_seconds = ?1, last_observed = ?2, last_uptime = ?3
+                 WHERE id = 1",
+                params![elapsed, high_water.to_rfc3339(), uptime],
+            )?;
         }
+
+        Ok(LockState {
+            is_locked: true,
+            unlock_time: Some(now + Duration::seconds(remaining)),
+            remaining_seconds: remaining,
+            profile_id: stored.profile_id,
+        })
     }
 
+    /// Ends the lock early (emergency unlock).
     pub fn clear_lock(&self) -> SqliteResult<()> {
         let db = self.db.lock().unwrap();
-        db.execute("DELETE FROM lock_state WHERE id = 1", [])?;
+        end_lock(&db, self.clock.now(), EndReason::EmergencyUnlock)?;
         log::info!("Lock cleared");
         Ok(())
     }
 
+    /// Every session, oldest first.
+    pub fn sessions(&self) -> SqliteResult<Vec<Session>> {
+        let mut sessions = self.recent_sessions(u32::MAX)?;
+        sessions.reverse();
+        Ok(sessions)
+    }
+
+    /// The most recent sessions, newest first.
+    pub fn recent_sessions(&self, limit: u32) -> SqliteResult<Vec<Session>> {
+        let db = self.db.lock().unwrap();
+
+        let mut stmt = db.prepare(
+            "SELECT id, started_at, planned_end, ended_at, end_reason FROM sessions
+             ORDER BY started_at DESC LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            let started_at: String = row.get(1)?;
+            let planned_end: String = row.get(2)?;
+            let ended_at: Option<String> = row.get(3)?;
+            let end_reason: Option<String> = row.get(4)?;
+            Ok(Session {
+                id: row.get(0)?,
+                started_at: parse_time(&started_at).unwrap_or_default(),
+                planned_end: parse_time(&planned_end).unwrap_or_default(),
+                ended_at: ended_at.as_deref().and_then(parse_time),
+                end_reason: end_reason.as_deref().and_then(EndReason::parse),
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    /// Whether a lock ran out since the last call, as opposed to being
+    /// cleared or never started.
+    pub fn take_expired(&self) -> bool {
+        self.expired.swap(false, Ordering::Relaxed)
+    }
+
     pub fn is_locked(&self) -> bool {
         self.get_state().map(|s| s.is_locked).unwrap_or(false)
     }
 }
+
+/// A lock of `minutes`, if that is within 1 minute and `MAX_LOCK_MINUTES`.
+fn lock_duration(minutes: i64) -> Result<Duration, TimeLockError> {
+    if !(1..=MAX_LOCK_MINUTES).contains(&minutes) {
+        return Err(TimeLockError::InvalidDuration(minutes));
+    }
+    Duration::try_minutes(minutes).ok_or(TimeLockError::InvalidDuration(minutes))
+}
+
+fn end_lock(db: &Connection, ended_at: DateTime<Utc>, reason: EndReason) -> SqliteResult<()> {
+    let tx = db.unchecked_transaction()?;
+    tx.execute("DELETE FROM lock_state WHERE id = 1", [])?;
+    end_open_sessions(&tx, ended_at, reason)?;
+    tx.commit()
+}
+
+fn end_open_sessions(
+    conn: &Connection,
+    ended_at: DateTime<Utc>,
+    reason: EndReason,
+) -> SqliteResult<usize> {
+    conn.execute(
+        "UPDATE sessions SET ended_at = ?1, end_reason = ?2 WHERE ended_at IS NULL",
+        params![ended_at.to_rfc3339(), reason.as_str()],
+    )
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use std::thread;
+
+    fn start() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-03-01T09:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn fake_clock() -> Arc<FakeClock> {
+        Arc::new(FakeClock::new(start()))
+    }
+
+    fn timelock(clock: &Arc<FakeClock>) -> TimeLock {
+        TimeLock::from_connection(Connection::open_in_memory().unwrap(), clock.clone()).unwrap()
+    }
+
+    fn execute(timelock: &TimeLock, sql: &str) {
+        timelock.db.lock().unwrap().execute_batch(sql).unwrap();
+    }
+
+    #[test]
+    fn no_lock_is_unlocked() {
+        let timelock = timelock(&fake_clock());
+
+        let state = timelock.get_state().unwrap();
+        assert!(!state.is_locked);
+        assert_eq!(state.unlock_time, None);
+        assert!(!timelock.is_locked());
+    }
+
+    #[test]
+    fn start_lock_runs_for_the_full_duration() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        let started = timelock.start_lock(90, None).unwrap();
+        assert!(started.is_locked);
+        assert_eq!(started.remaining_seconds, 90 * 60);
+        assert_eq!(started.unlock_time, Some(start() + Duration::minutes(90)));
+
+        clock.advance(30 * 60);
+        let state = timelock.get_state().unwrap();
+        assert!(state.is_locked);
+        assert_eq!(state.remaining_seconds, 60 * 60);
+        assert_eq!(state.unlock_time, started.unlock_time);
+
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].started_at, start());
+        assert_eq!(sessions[0].ended_at, None);
+    }
+
+    #[test]
+    fn lock_expires_after_elapsed_time() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(59 * 60);
+        assert!(timelock.get_state().unwrap().is_locked);
+        assert!(!timelock.take_expired());
+        clock.advance(5 * 60);
+        assert!(!timelock.get_state().unwrap().is_locked);
+        // Stays expired, and says so once
+        assert!(!timelock.get_state().unwrap().is_locked);
+        assert!(timelock.take_expired());
+        assert!(!timelock.take_expired());
+
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions[0].end_reason, Some(EndReason::Expired));
+        assert_eq!(sessions[0].ended_at, Some(start() + Duration::hours(1)));
+    }
+
+    #[test]
+    fn clear_lock_is_an_emergency_unlock() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(10 * 60);
+        timelock.clear_lock().unwrap();
+
+        assert!(!timelock.get_state().unwrap().is_locked);
+        assert!(!timelock.take_expired());
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions[0].end_reason, Some(EndReason::EmergencyUnlock));
+        assert_eq!(sessions[0].ended_at, Some(start() + Duration::minutes(10)));
+
+        // Clearing without a lock is harmless
+        timelock.clear_lock().unwrap();
+        assert_eq!(timelock.sessions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn starting_over_a_lock_replaces_its_session() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(60);
+        timelock.start_lock(120, None).unwrap();
+
+        let sessions = timelock.recent_sessions(10).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].ended_at, None);
+        assert_eq!(sessions[1].end_reason, Some(EndReason::Replaced));
+    }
+
+    #[test]
+    fn running_lock_cannot_be_shortened() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(3 * 24 * 60, None).unwrap();
+        match timelock.start_lock(1, None) {
+            Err(TimeLockError::WouldShorten {
+                remaining_seconds,
+                requested_seconds,
+            }) => {
+                assert_eq!(remaining_seconds, 3 * 24 * 60 * 60);
+                assert_eq!(requested_seconds, 60);
+            }
+            other => panic!("expected WouldShorten, got {:?}", other),
+        }
+        assert_eq!(
+            timelock.get_state().unwrap().remaining_seconds,
+            3 * 24 * 60 * 60
+        );
+        assert_eq!(timelock.sessions().unwrap().len(), 1);
+
+        // Ending later is fine
+        clock.advance(60 * 60);
+        let state = timelock.start_lock(3 * 24 * 60, None).unwrap();
+        assert_eq!(state.remaining_seconds, 3 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn extend_lock_adds_time() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(20 * 60);
+        let state = timelock.extend_lock(30).unwrap();
+        assert_eq!(state.remaining_seconds, 70 * 60);
+        assert_eq!(state.unlock_time, Some(start() + Duration::minutes(90)));
+
+        clock.advance(69 * 60);
+        assert!(timelock.get_state().unwrap().is_locked);
+        clock.advance(60);
+        assert!(!timelock.get_state().unwrap().is_locked);
+
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].planned_end, start() + Duration::minutes(90));
+        assert_eq!(sessions[0].end_reason, Some(EndReason::Expired));
+    }
+
+    #[test]
+    fn extend_needs_a_running_lock_and_positive_minutes() {
+        let timelock = timelock(&fake_clock());
+
+        assert!(matches!(
+            timelock.extend_lock(10),
+            Err(TimeLockError::NotLocked)
+        ));
+        timelock.start_lock(10, None).unwrap();
+        assert!(matches!(
+            timelock.extend_lock(0),
+            Err(TimeLockError::InvalidDuration(0))
+        ));
+        assert!(matches!(
+            timelock.start_lock(-5, None),
+            Err(TimeLockError::InvalidDuration(-5))
+        ));
+    }
+
+    #[test]
+    fn trusted_time_ignores_clock_changes() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        assert_eq!(timelock.now().unwrap(), start());
+        clock.advance(60 * 60);
+        assert_eq!(timelock.now().unwrap(), start() + Duration::hours(1));
+
+        // Small corrections are followed, setting it forward during a lock
+        // is not
+        clock.set_now(start() + Duration::hours(1) + Duration::seconds(30));
+        assert_eq!(
+            timelock.now().unwrap(),
+            start() + Duration::hours(1) + Duration::seconds(30)
+        );
+        clock.advance(60);
+        timelock.now().unwrap();
+        timelock.start_lock(60, None).unwrap();
+        clock.set_now(start() + Duration::days(1));
+        assert_eq!(
+            timelock.now().unwrap(),
+            start() + Duration::hours(1) + Duration::seconds(90)
+        );
+        clock.advance(60);
+        assert_eq!(
+            timelock.now().unwrap(),
+            start() + Duration::hours(1) + Duration::seconds(150)
+        );
+
+        // With no lock to shorten it is the user's clock to set
+        timelock.clear_lock().unwrap();
+        assert_eq!(
+            timelock.now().unwrap(),
+            start() + Duration::days(1) + Duration::seconds(60)
+        );
+    }
+
+    #[test]
+    fn trusted_time_follows_a_backward_correction() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+        timelock.start_lock(120, None).unwrap();
+
+        // The clock ran ten minutes fast until NTP set it right
+        timelock.now().unwrap();
+        clock.advance(60 * 60);
+        clock.set_now(start() + Duration::minutes(50));
+        assert_eq!(timelock.now().unwrap(), start() + Duration::minutes(50));
+
+        // And it counts on from the corrected time
+        clock.advance(60);
+        assert_eq!(timelock.now().unwrap(), start() + Duration::minutes(51));
+        clock.set_now(start() + Duration::hours(3));
+        assert_eq!(timelock.now().unwrap(), start() + Duration::minutes(51));
+    }
+
+    #[test]
+    fn trusted_time_takes_the_wall_clock_after_a_reboot() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+        timelock.start_lock(24 * 60, None).unwrap();
+
+        timelock.now().unwrap();
+        clock.advance(60 * 60);
+        clock.set_now(start() + Duration::days(1));
+        assert_eq!(timelock.now().unwrap(), start() + Duration::hours(1));
+
+        clock.reboot(60);
+        assert_eq!(
+            timelock.now().unwrap(),
+            start() + Duration::days(1) + Duration::seconds(60)
+        );
+        clock.advance(60);
+        assert_eq!(
+            timelock.now().unwrap(),
+            start() + Duration::days(1) + Duration::seconds(120)
+        );
+    }
+
+    #[test]
+    fn durations_are_capped_instead_of_overflowing() {
+        let timelock = timelock(&fake_clock());
+
+        for minutes in [i64::MAX, i64::MAX / 60 + 1, MAX_LOCK_MINUTES + 1] {
+            assert!(matches!(
+                timelock.start_lock(minutes, None),
+                Err(TimeLockError::InvalidDuration(m)) if m == minutes
+            ));
+        }
+        assert!(!timelock.is_locked());
+
+        timelock.start_lock(MAX_LOCK_MINUTES - 10, None).unwrap();
+        assert!(matches!(
+            timelock.extend_lock(i64::MAX),
+            Err(TimeLockError::InvalidDuration(_))
+        ));
+        assert!(matches!(
+            timelock.extend_lock(11),
+            Err(TimeLockError::InvalidDuration(11))
+        ));
+        let state = timelock.extend_lock(10).unwrap();
+        assert_eq!(state.remaining_seconds, MAX_LOCK_MINUTES * 60);
+    }
+
+    #[test]
+    fn errors_serialize_with_their_kind() {
+        let error = TimeLockError::WouldShorten {
+            remaining_seconds: 600,
+            requested_seconds: 60,
+        };
+        let value = serde_json::to_value(&error).unwrap();
+        assert_eq!(value["kind"], "would_shorten");
+        assert_eq!(value["remaining_seconds"], 600);
+        assert_eq!(value["requested_seconds"], 60);
+        assert_eq!(value["message"], error.to_string());
+
+        let value = serde_json::to_value(TimeLockError::NotLocked).unwrap();
+        assert_eq!(value["kind"], "not_locked");
+    }
+
+    #[test]
+    fn lock_reports_its_profile_until_it_ends() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        assert!(matches!(
+            timelock.set_profile(Some(1)),
+            Err(TimeLockError::NotLocked)
+        ));
+        assert_eq!(
+            timelock.start_lock(60, Some(1)).unwrap().profile_id,
+            Some(1)
+        );
+
+        clock.advance(10 * 60);
+        let state = timelock.set_profile(Some(2)).unwrap();
+        assert_eq!(state.profile_id, Some(2));
+        assert_eq!(state.remaining_seconds, 50 * 60);
+        assert_eq!(timelock.extend_lock(10).unwrap().profile_id, Some(2));
+        assert_eq!(timelock.get_state().unwrap().profile_id, Some(2));
+
+        clock.advance(60 * 60);
+        assert_eq!(timelock.get_state().unwrap().profile_id, None);
+    }
+
+    #[test]
+    fn forward_clock_jump_is_ignored() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(10 * 60);
+        clock.set_now(start() + Duration::days(7));
+
+        let state = timelock.get_state().unwrap();
+        assert!(state.is_locked);
+        assert_eq!(state.remaining_seconds, 50 * 60);
+
+        // Still measured from uptime after the jump was recorded
+        clock.advance(10 * 60);
+        assert_eq!(timelock.get_state().unwrap().remaining_seconds, 40 * 60);
+    }
+
+    #[test]
+    fn backward_clock_jump_does_not_stretch_the_lock() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.set_now(start() - Duration::days(1));
+        clock.advance(61 * 60);
+
+        assert!(!timelock.get_state().unwrap().is_locked);
+    }
+
+    #[test]
+    fn time_powered_off_counts_by_wall_clock() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(10 * 60);
+        timelock.get_state().unwrap();
+        clock.reboot(20 * 60);
+
+        assert_eq!(timelock.get_state().unwrap().remaining_seconds, 30 * 60);
+    }
+
+    #[test]
+    fn clock_set_forward_before_a_reboot_gains_little() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(24 * 60, None).unwrap();
+        clock.advance(10 * 60);
+        timelock.get_state().unwrap();
+        // Set a week ahead and rebooted before the app looked again
+        clock.set_now(start() + Duration::days(7));
+        clock.reboot(60);
+
+        let state = timelock.get_state().unwrap();
+        assert!(state.is_locked);
+        assert_eq!(
+            state.remaining_seconds,
+            24 * 3600 - 10 * 60 - clock::MAX_POWERED_OFF_SECS
+        );
+    }
+
+    #[test]
+    fn corrupt_unlock_time_does_not_end_the_lock() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        execute(
+            &timelock,
+            "UPDATE lock_state SET unlock_time = 'not a time'",
+        );
+        clock.advance(60);
+
+        let state = timelock.get_state().unwrap();
+        assert!(state.is_locked);
+        assert_eq!(state.remaining_seconds, 59 * 60);
+    }
+
+    #[test]
+    fn unreadable_legacy_lock_is_unlocked() {
+        let timelock = timelock(&fake_clock());
+
+        // What the elapsed-time migration leaves for an unparsable unlock_time
+        execute(
+            &timelock,
+            "INSERT INTO lock_state (id, unlock_time) VALUES (1, 'garbage')",
+        );
+
+        assert!(!timelock.get_state().unwrap().is_locked);
+    }
+
+    #[test]
+    fn corrupt_last_observed_restarts_measuring() {
+        let clock = fake_clock();
+        let timelock = timelock(&clock);
+
+        timelock.start_lock(60, None).unwrap();
+        clock.advance(10 * 60);
+        timelock.get_state().unwrap();
+        execute(&timelock, "UPDATE lock_state SET last_observed = '???'");
+
+        // The interval that can't be measured isn't credited
+        clock.advance(10 * 60);
+        assert_eq!(timelock.get_state().unwrap().remaining_seconds, 50 * 60);
+        clock.advance(10 * 60);
+        assert_eq!(timelock.get_state().unwrap().remaining_seconds, 40 * 60);
+    }
+
+    #[test]
+    fn session_left_open_without_lock_is_a_crash() {
+        let clock = fake_clock();
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO sessions (started_at, planned_end) VALUES (?1, ?2)",
+            params![
+                (start() - Duration::hours(2)).to_rfc3339(),
+                (start() - Duration::hours(1)).to_rfc3339()
+            ],
+        )
+        .unwrap();
+
+        let timelock = TimeLock::from_connection(conn, clock.clone()).unwrap();
+
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions[0].end_reason, Some(EndReason::Crash));
+        assert_eq!(sessions[0].ended_at, Some(start() - Duration::hours(1)));
+    }
+
+    #[test]
+    fn lock_from_before_sessions_gets_one() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO lock_state (id, unlock_time, created_at, duration_seconds)
+             VALUES (1, ?1, '2024-03-01 08:00:00', 7200)",
+            [(start() + Duration::hours(1)).to_rfc3339()],
+        )
+        .unwrap();
+
+        let timelock = TimeLock::from_connection(conn, fake_clock()).unwrap();
+
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].started_at, start() - Duration::hours(1));
+        assert_eq!(sessions[0].planned_end, start() + Duration::hours(1));
+    }
+
+    #[test]
+    fn concurrent_access_keeps_one_consistent_lock() {
+        let clock = fake_clock();
+        let timelock = Arc::new(timelock(&clock));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let timelock = timelock.clone();
+                let clock = clock.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        if i % 2 == 0 {
+                            timelock.start_lock(60, None).unwrap();
+                        } else {
+                            clock.advance(1);
+                            timelock.get_state().unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(timelock.get_state().unwrap().is_locked);
+        let sessions = timelock.sessions().unwrap();
+        assert_eq!(sessions.len(), 100);
+        assert_eq!(sessions.iter().filter(|s| s.ended_at.is_none()).count(), 1);
+    }
+}